@@ -33,6 +33,11 @@ fn preprocess() -> Result<(), Box<dyn Error>> {
         true => &["-target", "riscv64", "-march=rv64imac"],
         false => &["-march=rv64imac_zicsr_zifencei"],
     };
+    // Keep in sync with `src/platform.rs`.
+    let platform: &[_] = match env::var_os("CARGO_FEATURE_QEMU_VIRT") {
+        Some(_) => &["-DPLATFORM_QEMU_VIRT", "-DBOOT_HART=0"],
+        None => &["-DBOOT_HART=1"],
+    };
 
     walk_dir("src", |dirent| {
         let path = dirent.path();
@@ -49,12 +54,8 @@ fn preprocess() -> Result<(), Box<dyn Error>> {
 
         Command::new(&cc)
             .args(cflags)
-            .args([
-                "-mabi=lp64",
-                "-E",
-                "-xassembler-with-cpp",
-                "-o",
-            ])
+            .args(platform)
+            .args(["-mabi=lp64", "-E", "-xassembler-with-cpp", "-o"])
             .arg(output)
             .arg(&path)
             .spawn()?
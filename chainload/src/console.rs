@@ -0,0 +1,144 @@
+//! A command line on the UART, entered by pressing ESC while waiting for a transfer.
+
+use crate::uart::{Reg, Uart};
+use core::time::Duration;
+use zmodem::{
+    proto::consts::{SOH, STX, ZPAD},
+    SerialDevice,
+};
+
+const ESC: u8 = 0x1b;
+const BS: u8 = 0x08;
+const DEL: u8 = 0x7f;
+
+pub const HELP: &str = "\
+commands:
+  load                         receive an image with ZMODEM, YMODEM, XMODEM or Kermit, and run it
+  linux nommu [<bootargs>]     receive a kernel Image built for M-mode (CONFIG_RISCV_M_MODE), and
+                               optionally a device tree and an initrd, in one ZMODEM batch, and
+                               boot them in M-mode; S-mode kernels need an SBI, which isn't provided
+  save <addr> <len> [<name>]   send memory to the host with ZMODEM, addr and len in hex
+  help";
+
+/// Cancels the session if ESC is received before a transfer starts.
+pub struct Interruptible<D> {
+    dev: D,
+    // Set once the first byte of a header or block is seen.
+    started: bool,
+    interrupted: bool,
+}
+
+impl<D> Interruptible<D> {
+    pub fn new(dev: D) -> Interruptible<D> {
+        Self {
+            dev,
+            started: false,
+            interrupted: false,
+        }
+    }
+}
+
+impl<D: SerialDevice> SerialDevice for Interruptible<D> {
+    type Error = D::Error;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.dev.send(byte)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        let byte = self.dev.recv(timeout)?;
+        match byte {
+            Some(ZPAD | SOH | STX) => self.started = true,
+            Some(ESC) if !self.started => self.interrupted = true,
+            _ => {}
+        }
+        Ok(byte)
+    }
+
+    fn is_cancelled(&mut self) -> bool {
+        self.interrupted || self.dev.is_cancelled()
+    }
+}
+
+pub enum Command<'a> {
+    Load,
+    Linux {
+        /// Whether the user said the kernel is built for M-mode.
+        nommu: bool,
+        bootargs: &'a str,
+    },
+    Save {
+        addr: usize,
+        len: usize,
+        name: &'a str,
+    },
+    Help,
+}
+
+fn parse_hex(s: &str) -> Option<usize> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    usize::from_str_radix(s, 16).ok()
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(line: &'a str) -> Option<Command<'a>> {
+        // The rest of the line is passed on as is.
+        let line = line.trim();
+        if let Some(bootargs) = line.strip_prefix("linux") {
+            if bootargs.is_empty() || bootargs.starts_with(' ') {
+                let bootargs = bootargs.trim_start();
+                let rest = bootargs.strip_prefix("nommu");
+                let rest = rest.filter(|rest| rest.is_empty() || rest.starts_with(' '));
+                return Some(Command::Linux {
+                    nommu: rest.is_some(),
+                    bootargs: rest.unwrap_or(bootargs).trim_start(),
+                });
+            }
+        }
+        let mut words = line.split_ascii_whitespace();
+        let command = match words.next()? {
+            "load" => Command::Load,
+            "save" => Command::Save {
+                addr: parse_hex(words.next()?)?,
+                len: parse_hex(words.next()?)?,
+                name: words.next().unwrap_or("memory.bin"),
+            },
+            "help" => Command::Help,
+            _ => return None,
+        };
+        match words.next() {
+            Some(_) => None,
+            None => Some(command),
+        }
+    }
+}
+
+/// Read a line into `buf`, echoing it, until return is pressed.
+pub fn read_line<'a>(uart: &mut Uart, buf: &'a mut [u8]) -> &'a str {
+    let mut len = 0;
+    loop {
+        while !uart.data_ready() {
+            core::hint::spin_loop();
+        }
+        let byte = uart.read_register(Reg::RX_BUFFER);
+        match byte {
+            b'\r' | b'\n' => break,
+            BS | DEL if len > 0 => {
+                len -= 1;
+                for byte in [BS, b' ', BS] {
+                    uart.transmit(byte).ok();
+                }
+            }
+            b' '..=b'~' if len < buf.len() => {
+                buf[len] = byte;
+                len += 1;
+                uart.transmit(byte).ok();
+            }
+            _ => {}
+        }
+    }
+    uart.transmit(b'\r').ok();
+    uart.transmit(b'\n').ok();
+    // Only printable ASCII is kept.
+    core::str::from_utf8(&buf[..len]).unwrap_or_default()
+}
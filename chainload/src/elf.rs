@@ -0,0 +1,208 @@
+//! Loading ELF64 RISC-V executables, such as cargo builds, to their physical addresses.
+
+use bytemuck::{Pod, Zeroable};
+use core::{fmt, mem::size_of, ops::Range};
+
+const MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Header {
+    ident: [u8; 16],
+    r#type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct ProgramHeader {
+    r#type: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+#[derive(Debug)]
+pub enum ElfError {
+    /// Not a little-endian ELF64 RISC-V executable.
+    Unsupported,
+    /// A header or segment is cut off by the end of the image.
+    Truncated,
+    /// A segment's addresses overflow, or it's bigger in the file than in memory.
+    InvalidSegment,
+    /// A segment would be loaded outside of usable memory, or over something in use.
+    Overlap(Range<usize>),
+    /// The entry point isn't in a loaded segment.
+    InvalidEntry(usize),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::Unsupported => write!(f, "not a little-endian ELF64 RISC-V executable"),
+            ElfError::Truncated => write!(f, "truncated"),
+            ElfError::InvalidSegment => write!(f, "invalid segment"),
+            ElfError::Overlap(range) => write!(
+                f,
+                "segment at {:#x}..{:#x} isn't in usable memory",
+                range.start, range.end
+            ),
+            ElfError::InvalidEntry(entry) => write!(f, "entry point {entry:#x} isn't in a segment"),
+        }
+    }
+}
+
+/// A `PT_LOAD` segment, whose data is followed by zeroes up to the end of its memory.
+pub struct Segment<'a> {
+    /// Where the segment is loaded, at its physical address.
+    pub memory: Range<usize>,
+    /// The virtual address it's linked at.
+    pub vaddr: usize,
+    pub data: &'a [u8],
+}
+
+pub struct Elf<'a> {
+    image: &'a [u8],
+    header: Header,
+}
+
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(MAGIC)
+}
+
+fn read<T: Pod>(image: &[u8], offset: usize) -> Result<T, ElfError> {
+    let bytes = offset
+        .checked_add(size_of::<T>())
+        .and_then(|end| image.get(offset..end))
+        .ok_or(ElfError::Truncated)?;
+    Ok(bytemuck::pod_read_unaligned(bytes))
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(image: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        let header: Header = read(image, 0)?;
+        if !is_elf(&header.ident)
+            || header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.r#type != ET_EXEC
+            || header.machine != EM_RISCV
+            || usize::from(header.phentsize) != size_of::<ProgramHeader>()
+        {
+            return Err(ElfError::Unsupported);
+        }
+        let elf = Elf { image, header };
+        // Checked now, so that `segments` can skip errors.
+        for index in 0..header.phnum {
+            elf.segment(index)?;
+        }
+        Ok(elf)
+    }
+
+    /// The entry point, as a virtual address.
+    pub fn entry(&self) -> usize {
+        self.header.entry as usize
+    }
+
+    /// The physical address of the entry point, in the segment whose virtual addresses hold it.
+    pub fn physical_entry(&self) -> Option<usize> {
+        let entry = self.entry();
+        self.segments().find_map(|segment| {
+            let offset = entry.checked_sub(segment.vaddr)?;
+            (offset < segment.memory.len()).then(|| segment.memory.start + offset)
+        })
+    }
+
+    fn segment(&self, index: u16) -> Result<Option<Segment<'a>>, ElfError> {
+        let offset = usize::from(index) * size_of::<ProgramHeader>();
+        let offset = (self.header.phoff as usize)
+            .checked_add(offset)
+            .ok_or(ElfError::Truncated)?;
+        let ph: ProgramHeader = read(self.image, offset)?;
+        if ph.r#type != PT_LOAD {
+            return Ok(None);
+        }
+        let data = (ph.offset as usize)
+            .checked_add(ph.filesz as usize)
+            .and_then(|end| self.image.get(ph.offset as usize..end))
+            .ok_or(ElfError::Truncated)?;
+        let start = ph.paddr as usize;
+        let end = start
+            .checked_add(ph.memsz as usize)
+            .ok_or(ElfError::InvalidSegment)?;
+        if ph.filesz > ph.memsz {
+            return Err(ElfError::InvalidSegment);
+        }
+        Ok(Some(Segment {
+            memory: start..end,
+            vaddr: ph.vaddr as usize,
+            data,
+        }))
+    }
+
+    /// The `PT_LOAD` segments, skipping empty ones.
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + '_ {
+        (0..self.header.phnum)
+            .filter_map(|index| self.segment(index).ok().flatten())
+            .filter(|segment| !segment.memory.is_empty())
+    }
+
+    /// Copy the segments to memory and zero the rest of each, returning the physical address of
+    /// the entry point.
+    ///
+    /// Nothing is written unless every segment lies within `usable` and clear of `reserved`,
+    /// which must include the image itself.
+    ///
+    /// # Safety
+    ///
+    /// `usable` must be memory that nothing else uses, other than `reserved`.
+    pub unsafe fn load(
+        &self,
+        usable: Range<usize>,
+        reserved: &[Range<usize>],
+    ) -> Result<usize, ElfError> {
+        for segment in self.segments() {
+            let memory = segment.memory;
+            if memory.start < usable.start
+                || memory.end > usable.end
+                || reserved
+                    .iter()
+                    .any(|range| range.start < memory.end && memory.start < range.end)
+            {
+                return Err(ElfError::Overlap(memory));
+            }
+        }
+        let entry = self
+            .physical_entry()
+            .ok_or(ElfError::InvalidEntry(self.entry()))?;
+
+        for segment in self.segments() {
+            let dest = segment.memory.start as *mut u8;
+            let len = segment.data.len();
+            unsafe {
+                core::ptr::copy_nonoverlapping(segment.data.as_ptr(), dest, len);
+                dest.add(len).write_bytes(0, segment.memory.len() - len);
+            }
+        }
+        Ok(entry)
+    }
+}
@@ -0,0 +1,243 @@
+//! Booting a RISC-V Linux `Image`, with a device tree and an optional initrd.
+//!
+//! The files are received as one ZMODEM batch, in any order, and told apart by their contents.
+//! The kernel is entered in machine mode, which chainload runs in, with a0 set to the hart ID and
+//! a1 to the device tree. There's no SBI for a supervisor mode kernel to call, so only kernels
+//! built for machine mode (`CONFIG_RISCV_M_MODE`, which implies no MMU) can be booted. Nothing in
+//! the `Image` header tells them apart, so the user says it is one with `linux nommu`.
+
+use crate::platform::{Board, Platform};
+use core::{fmt, ops::Range, ptr, slice};
+use fdt::{Editor, Fdt, FdtError};
+use zmodem::{
+    proto::FileInfo,
+    sink::{ExistingFile, OpenMode, Sink, SinkError},
+};
+
+/// "RSC\x05", at `MAGIC_OFFSET` in the `Image` header.
+const MAGIC: &[u8] = b"RSC\x05";
+const MAGIC_OFFSET: usize = 56;
+const HEADER_LEN: usize = 64;
+/// The kernel is placed `text_offset` past an address aligned to this.
+const KERNEL_ALIGN: usize = 2 << 20;
+const PAGE_SIZE: usize = 4096;
+/// Room for the properties added to the device tree.
+const DTB_SLACK: usize = 4096;
+/// A kernel, a device tree and an initrd.
+const MAX_FILES: usize = 3;
+const MAX_BOOTARGS: usize = 255;
+
+#[derive(Debug)]
+pub enum LinuxError {
+    /// No file with an `Image` header was received.
+    NoKernel,
+    /// No device tree was received, nor handed to us by the previous stage.
+    NoDeviceTree,
+    /// Two files of the same kind were received.
+    Duplicate,
+    DeviceTree(FdtError),
+    /// The kernel, device tree and initrd don't fit in memory.
+    TooBig,
+    BootargsTooLong,
+}
+
+impl fmt::Display for LinuxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinuxError::NoKernel => write!(f, "no kernel Image received"),
+            LinuxError::NoDeviceTree => write!(f, "no device tree"),
+            LinuxError::Duplicate => write!(f, "expected one kernel, device tree and initrd"),
+            LinuxError::DeviceTree(error) => write!(f, "can't patch the device tree: {error:?}"),
+            LinuxError::TooBig => write!(f, "not enough memory"),
+            LinuxError::BootargsTooLong => write!(f, "bootargs longer than {MAX_BOOTARGS} bytes"),
+        }
+    }
+}
+
+/// A sink that stores a batch of files back to back, keeping where each one is.
+pub struct Files<'a> {
+    buf: &'a mut [u8],
+    files: [Range<usize>; MAX_FILES],
+    count: usize,
+}
+
+impl<'a> Files<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Files<'a> {
+        Self {
+            buf,
+            files: Default::default(),
+            count: 0,
+        }
+    }
+
+    /// Where each file is in the buffer.
+    pub fn files(&self) -> &[Range<usize>] {
+        &self.files[..self.count]
+    }
+
+    fn end(&self) -> usize {
+        self.files().last().map_or(0, |file| file.end)
+    }
+}
+
+impl Sink for Files<'_> {
+    fn existing(&mut self, _info: &FileInfo) -> Option<ExistingFile> {
+        None
+    }
+
+    fn free_space(&mut self) -> Option<u64> {
+        Some((self.buf.len() - self.end()) as u64)
+    }
+
+    fn open(&mut self, _info: &FileInfo, _mode: OpenMode) -> Result<(), SinkError> {
+        if self.count == MAX_FILES {
+            return Err(SinkError::Refused);
+        }
+        let end = self.end();
+        self.files[self.count] = end..end;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), SinkError> {
+        let file = &mut self.files[self.count - 1];
+        let offset = usize::try_from(offset).map_err(|_| SinkError::Full)?;
+        let start = file.start + offset;
+        let end = start + data.len();
+        self.buf
+            .get_mut(start..end)
+            .ok_or(SinkError::Full)?
+            .copy_from_slice(data);
+        file.end = file.end.max(end);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Where to enter the kernel, and the device tree to pass it.
+pub struct Boot {
+    pub entry: usize,
+    pub dtb: usize,
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+fn le64(bytes: &[u8]) -> usize {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize
+}
+
+/// Copy `dtb` to `out` with `/chosen` giving the initrd, if any, and `bootargs`, a string with
+/// its NUL or empty, returning the length of the copy.
+fn patch_dtb(
+    dtb: &[u8],
+    out: &mut [u8],
+    initrd: Option<Range<usize>>,
+    bootargs: &[u8],
+) -> Result<usize, FdtError> {
+    let mut editor = Editor::new(Fdt::new(dtb)?);
+    editor.add_node("/chosen")?;
+    let initrd = initrd.map(|initrd| {
+        let start = (initrd.start as u64).to_be_bytes();
+        let end = (initrd.end as u64).to_be_bytes();
+        (start, end)
+    });
+    if let Some((start, end)) = &initrd {
+        editor.set_property("/chosen", "linux,initrd-start", start)?;
+        editor.set_property("/chosen", "linux,initrd-end", end)?;
+    }
+    if !bootargs.is_empty() {
+        editor.set_property("/chosen", "bootargs", bootargs)?;
+    }
+    editor.write(out)
+}
+
+/// Lay out the received `files`, given relative to `LOAD_ADDRESS`, to boot them with `bootargs`,
+/// if not empty.
+///
+/// The previous stage's device tree is used if none was received. The kernel goes at
+/// `LOAD_ADDRESS` rounded up to 2 MiB, followed by the device tree and the initrd.
+///
+/// # Safety
+///
+/// `files` must be back to back, and DRAM from `LOAD_ADDRESS` to `dram_end` unused but for them.
+pub unsafe fn prepare(
+    files: &[Range<usize>],
+    bootargs: &str,
+    firmware_dtb: Option<&[u8]>,
+    dram_end: usize,
+) -> Result<Boot, LinuxError> {
+    let received = match files {
+        [first, .., last] => first.start..last.end,
+        [file] => file.clone(),
+        [] => return Err(LinuxError::NoKernel),
+    };
+    // Moved to the end of DRAM, out of the way of where the files are placed.
+    let staging = (dram_end - received.len()) & !7;
+    unsafe {
+        ptr::copy(
+            (Board::LOAD_ADDRESS + received.start) as *const u8,
+            staging as *mut u8,
+            received.len(),
+        )
+    };
+
+    let (mut kernel, mut dtb, mut initrd) = (None, None, None);
+    for file in files {
+        let data = unsafe {
+            let start = staging + (file.start - received.start);
+            slice::from_raw_parts(start as *const u8, file.len())
+        };
+        let kind = if data.len() >= HEADER_LEN && data[MAGIC_OFFSET..].starts_with(MAGIC) {
+            &mut kernel
+        } else if fdt::is_fdt(data) {
+            &mut dtb
+        } else {
+            &mut initrd
+        };
+        if kind.replace(data).is_some() {
+            return Err(LinuxError::Duplicate);
+        }
+    }
+    let kernel: &[u8] = kernel.ok_or(LinuxError::NoKernel)?;
+    let dtb = dtb.or(firmware_dtb).ok_or(LinuxError::NoDeviceTree)?;
+
+    let text_offset = le64(&kernel[8..]);
+    let image_size = le64(&kernel[16..]).max(kernel.len());
+    let entry = align_up(Board::LOAD_ADDRESS, KERNEL_ALIGN) + text_offset;
+    let dtb_addr = align_up(entry + image_size, PAGE_SIZE);
+    let dtb_len = dtb.len() + DTB_SLACK;
+    let initrd_addr = align_up(dtb_addr + dtb_len, PAGE_SIZE);
+    let end = initrd_addr + initrd.map_or(0, <[u8]>::len);
+    if end > staging {
+        return Err(LinuxError::TooBig);
+    }
+
+    // A string, so with a terminating NUL.
+    let mut args = [0; MAX_BOOTARGS + 1];
+    args.get_mut(..bootargs.len())
+        .ok_or(LinuxError::BootargsTooLong)?
+        .copy_from_slice(bootargs.as_bytes());
+    let args = match bootargs.len() {
+        0 => &[],
+        len => &args[..len + 1],
+    };
+
+    unsafe {
+        let out = slice::from_raw_parts_mut(dtb_addr as *mut u8, dtb_len);
+        patch_dtb(dtb, out, initrd.map(|_| initrd_addr..end), args)
+            .map_err(LinuxError::DeviceTree)?;
+        ptr::copy_nonoverlapping(kernel.as_ptr(), entry as *mut u8, kernel.len());
+        if let Some(initrd) = initrd {
+            ptr::copy_nonoverlapping(initrd.as_ptr(), initrd_addr as *mut u8, initrd.len());
+        }
+    }
+    Ok(Boot {
+        entry,
+        dtb: dtb_addr,
+    })
+}
@@ -4,8 +4,9 @@
 .section .text._start,"ax",@progbits
 .global _start
 _start:
+        mv              s11, a1         // the device tree from the previous stage
         csrr            t0, mhartid
-        li              t1, 1
+        li              t1, BOOT_HART
         bne             t0, t1, _ap_wait
 .option push
 .option norelax
@@ -20,11 +21,17 @@ _start:
         call            _relocate_firmware
         lla             t0, _trap_vector
         csrw            mtvec, t0
+        mv              a0, s11
         j               chainload_start
 
 _ap_wait:
+#ifdef PLATFORM_QEMU_VIRT
+        wfi
+        j       _ap_wait
+#else
         cease
         j       .
+#endif
 
 .p2align 2
 _trap_vector:
@@ -77,7 +84,6 @@ _trap_vector:
 
         mv              a0, sp
         call            trap_handler
-        cease
         j               .
 
 #define DT_RELA             7
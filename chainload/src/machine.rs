@@ -0,0 +1,111 @@
+//! What chainload needs to know about the machine, from the previous stage's device tree where it
+//! says, and the platform's constants otherwise.
+
+use crate::{
+    platform::{Board, Platform},
+    uart::Uart,
+};
+use core::{fmt, ops::Range};
+use fdt::Fdt;
+
+/// UARTs that `uart` can drive, by `compatible`.
+const UART_COMPATIBLE: &[&str] = &["ns16550a", "ns16550", "snps,dw-apb-uart"];
+/// Reserved regions kept, any more are ignored.
+pub const MAX_RESERVED: usize = 4;
+
+pub struct Machine {
+    uart_base: usize,
+    uart_reg_shift: usize,
+    uart_clock: u32,
+    pub timebase_frequency: u64,
+    /// The bank of DRAM that `LOAD_ADDRESS` is in, cut short by any region reserved after it.
+    pub dram: Range<usize>,
+    /// Regions reserved in the device tree that are in `dram`, the rest empty.
+    pub reserved: [Range<usize>; MAX_RESERVED],
+    /// A region reserved in the device tree that holds `LOAD_ADDRESS`, leaving nowhere to
+    /// receive images.
+    pub load_reserved: Option<Range<usize>>,
+    /// How many harts are enabled, when there's a device tree to say.
+    harts: Option<usize>,
+}
+
+impl Machine {
+    pub fn new(fdt: Option<&Fdt>) -> Machine {
+        let mut machine = Machine {
+            uart_base: Board::UART_BASE,
+            uart_reg_shift: Board::UART_REG_SHIFT,
+            uart_clock: Board::UART_CLOCK,
+            timebase_frequency: Board::TIMEBASE_FREQUENCY,
+            dram: Board::DRAM_BASE..Board::DRAM_END,
+            reserved: Default::default(),
+            load_reserved: None,
+            harts: None,
+        };
+        if let Some(fdt) = fdt {
+            machine.update(fdt);
+        }
+        machine
+    }
+
+    fn update(&mut self, fdt: &Fdt) {
+        let stdout = fdt.stdout().map(|(node, _)| node);
+        if let Some(uart) =
+            stdout.filter(|uart| UART_COMPATIBLE.iter().any(|c| uart.is_compatible(c)))
+        {
+            let property = |name| uart.property(name).and_then(|p| p.as_u32());
+            if let Some((base, _)) = uart.reg().next() {
+                self.uart_base = base as usize;
+            }
+            if let Some(shift) = property("reg-shift") {
+                self.uart_reg_shift = shift as usize;
+            }
+            if let Some(clock) = property("clock-frequency").filter(|&clock| clock != 0) {
+                self.uart_clock = clock;
+            }
+        }
+        if let Some(frequency) = fdt.timebase_frequency().filter(|&frequency| frequency != 0) {
+            self.timebase_frequency = frequency;
+        }
+
+        let load = Board::LOAD_ADDRESS as u64;
+        if let Some(bank) = fdt.memory().find(|bank| bank.contains(&load)) {
+            let end = fdt
+                .reserved_memory()
+                .filter(|range| range.start > load)
+                .fold(bank.end, |end, range| end.min(range.start));
+            self.dram = bank.start as usize..end as usize;
+        }
+        let dram = self.dram.start as u64..self.dram.end as u64;
+        let reserved = fdt
+            .reserved_memory()
+            .filter(|range| range.start < dram.end && dram.start < range.end);
+        for (slot, range) in self.reserved.iter_mut().zip(reserved) {
+            *slot = range.start as usize..range.end as usize;
+        }
+        self.load_reserved = fdt
+            .reserved_memory()
+            .find(|range| range.contains(&load))
+            .map(|range| range.start as usize..range.end as usize);
+
+        self.harts = Some(fdt.cpus().filter(|cpu| cpu.is_enabled()).count());
+    }
+
+    /// The console, as named by `/chosen/stdout-path` if it's a UART we can drive.
+    pub fn uart(&self) -> Uart {
+        Uart::at(self.uart_base, self.uart_reg_shift, self.uart_clock)
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DRAM {:#x}..{:#x}, console {:#x}, timebase {} Hz",
+            self.dram.start, self.dram.end, self.uart_base, self.timebase_frequency
+        )?;
+        match self.harts {
+            Some(harts) => write!(f, ", harts {harts}"),
+            None => Ok(()),
+        }
+    }
+}
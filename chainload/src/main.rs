@@ -1,15 +1,28 @@
 #![no_std]
 #![no_main]
 
+mod console;
+mod elf;
+mod linux;
+mod machine;
+mod platform;
 mod time;
 mod uart;
 
+use console::{Command, Interruptible};
 use core::{
     arch::{asm, global_asm},
     fmt::{self, Write},
+    ops::Range,
+    slice,
     time::Duration,
 };
-use uart::Uart;
+use elf::{Elf, ElfError};
+use fdt::Fdt;
+use machine::Machine;
+use platform::{Board, Platform};
+use uart::{Uart, UartError};
+use zmodem::{detect::Protocol, embedded::NbDevice, recv::Receiver, Error};
 
 global_asm!(
     ".pushsection _dummy",
@@ -18,8 +31,9 @@ global_asm!(
     options(raw),
 );
 
-fn cease() -> ! {
-    unsafe { asm!(".insn i 0x73, 0x0, x0, x0, 0x305", options(noreturn)) };
+extern "C" {
+    static __image_base: u8;
+    static __ebss: u8;
 }
 
 impl Write for Uart {
@@ -47,7 +61,7 @@ macro_rules! uprintln {
 fn rust_panic(info: &core::panic::PanicInfo) -> ! {
     let mut uart = Uart::new();
     uprintln!(uart, "panic: {info}");
-    cease();
+    Board::halt(1);
 }
 
 #[repr(C)]
@@ -80,38 +94,242 @@ unsafe extern "C" fn trap_handler(tf: &mut TrapFrame) -> ! {
         tf.sp,
     );
 
-    cease();
+    Board::halt(1);
 }
 
-impl zmodem::SerialDevice for &mut Uart {
-    type Error = uart::UartError;
+/// Room for a copy of the previous stage's device tree.
+const FIRMWARE_DTB_LEN: usize = 0x20000;
 
-    fn recv(&mut self, timeout: core::time::Duration) -> Result<Option<u8>, Self::Error> {
-        match self.receive_timeout(timeout) {
-            Ok(byte) => Ok(Some(byte)),
-            Err(uart::UartError::TimedOut) => Ok(None),
+/// Where chainload, including its stack, is in memory.
+fn footprint() -> Range<usize> {
+    unsafe { &__image_base as *const u8 as usize..&__ebss as *const u8 as usize }
+}
+
+/// Whether the device tree reserves memory at `LOAD_ADDRESS`, which is then said on `uart`, as
+/// nothing can be received.
+fn load_reserved(uart: &mut Uart, machine: &Machine) -> bool {
+    let Some(reserved) = &machine.load_reserved else {
+        return false;
+    };
+    uprintln!(
+        uart,
+        "can't receive at {:#x}, which is reserved at {:#x}..{:#x}",
+        Board::LOAD_ADDRESS,
+        reserved.start,
+        reserved.end
+    );
+    true
+}
+
+/// DRAM from `LOAD_ADDRESS`, where images are received.
+///
+/// # Safety
+///
+/// Nothing else may be using it, and it mustn't be reserved, as checked with [`load_reserved`].
+unsafe fn load_area(machine: &Machine) -> &'static mut [u8] {
+    let len = machine.dram.end - Board::LOAD_ADDRESS;
+    unsafe { slice::from_raw_parts_mut(Board::LOAD_ADDRESS as *mut u8, len) }
+}
+
+fn load(uart: &mut Uart, machine: &Machine) -> Result<(Protocol, usize), Error<UartError>> {
+    let output = unsafe { load_area(machine) };
+    let start = time::Instant::now();
+    let dev = NbDevice::new(uart, || time::Instant::now() - start);
+    Receiver::new(Interruptible::new(dev))
+        .set_verify(true)
+        .receive_auto(output)
+}
+
+/// Whether `len` bytes from `addr` are all in `machine.dram`, so they can be read.
+fn in_dram(machine: &Machine, addr: usize, len: usize) -> bool {
+    addr.checked_add(len)
+        .is_some_and(|end| machine.dram.start <= addr && end <= machine.dram.end)
+}
+
+/// Send `len` bytes of memory from `addr`, which must be checked with [`in_dram`].
+fn save(uart: &mut Uart, addr: usize, len: usize, name: &str) -> Result<(), Error<UartError>> {
+    let data = unsafe { slice::from_raw_parts(addr as *const u8, len) };
+    let start = time::Instant::now();
+    let dev = NbDevice::new(uart, || time::Instant::now() - start);
+    zmodem::send(dev, name, data)
+}
+
+/// Get a received image ready to run, returning its entry point.
+///
+/// ELF images are first moved to the end of DRAM, out of the way of their segments, and flat
+/// binaries run where they were received.
+fn prepare(len: usize, machine: &Machine) -> Result<usize, ElfError> {
+    let image = unsafe { slice::from_raw_parts(Board::LOAD_ADDRESS as *const u8, len) };
+    if !elf::is_elf(image) {
+        return Ok(Board::LOAD_ADDRESS);
+    }
+    let dram = machine.dram.clone();
+    let staging = (dram.end - len) & !7;
+    let image = unsafe {
+        core::ptr::copy(image.as_ptr(), staging as *mut u8, len);
+        slice::from_raw_parts(staging as *const u8, len)
+    };
+    let elf = Elf::parse(image)?;
+    let mut reserved: [Range<usize>; machine::MAX_RESERVED + 2] = Default::default();
+    reserved[0] = footprint();
+    reserved[1] = staging..dram.end;
+    reserved[2..].clone_from_slice(&machine.reserved);
+    unsafe { elf.load(dram, &reserved) }
+}
+
+/// Copy the device tree at `addr`, if there is one, out of the way of received images.
+fn copy_firmware_dtb(addr: usize, buf: &mut [u8]) -> Option<&[u8]> {
+    if addr == 0 {
+        return None;
+    }
+    let header = unsafe { slice::from_raw_parts(addr as *const u8, 8) };
+    let len = Fdt::total_size(header)
+        .ok()
+        .filter(|&len| len <= buf.len())?;
+    buf[..len].copy_from_slice(unsafe { slice::from_raw_parts(addr as *const u8, len) });
+    Some(&buf[..len])
+}
+
+/// Receive a kernel, and optionally a device tree and an initrd, and boot them, returning if
+/// that fails.
+fn linux(uart: &mut Uart, machine: &Machine, bootargs: &str, firmware_dtb: Option<&[u8]>) {
+    if load_reserved(uart, machine) {
+        return;
+    }
+    let output = unsafe { load_area(machine) };
+    let mut files = linux::Files::new(output);
+    let start = time::Instant::now();
+    let dev = NbDevice::new(&mut *uart, || time::Instant::now() - start);
+    let mut receiver = Receiver::new(Interruptible::new(dev)).set_verify(true);
+    let result = receiver
+        .send_zrinit()
+        .and_then(|()| receiver.receive_files(&mut files));
+    if let Err(error) = result {
+        uprintln!(uart, "load failed: {error:?}");
+        return;
+    }
+
+    match unsafe { linux::prepare(files.files(), bootargs, firmware_dtb, machine.dram.end) } {
+        Ok(boot) => {
+            time::sleep(Duration::from_secs(5));
+            uprintln!(
+                uart,
+                "booting Linux at {:#x} with the device tree at {:#x}",
+                boot.entry,
+                boot.dtb
+            );
+            let hartid: usize;
+            unsafe {
+                asm!("csrr {}, mhartid", out(reg) hartid);
+                asm!(
+                    "fence.i",
+                    "jr {}",
+                    in(reg) boot.entry,
+                    in("a0") hartid,
+                    in("a1") boot.dtb,
+                    options(noreturn),
+                );
+            }
+        }
+        Err(error) => {
+            uprintln!(uart, "can't boot Linux: {error}");
         }
     }
+}
 
-    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
-        self.transmit(byte)
+/// Run console commands until one asks for an image to be loaded.
+fn console(uart: &mut Uart, machine: &Machine, firmware_dtb: Option<&[u8]>) {
+    let mut buf = [0; 128];
+    loop {
+        uart.write_str("> ").ok();
+        let line = console::read_line(uart, &mut buf);
+        match Command::parse(line) {
+            Some(Command::Load) => return,
+            Some(Command::Linux { nommu: false, .. }) => {
+                uprintln!(
+                    uart,
+                    "only M-mode (NOMMU) kernels can be booted, there's no SBI for others: use \
+                     `linux nommu [<bootargs>]` for a kernel built with CONFIG_RISCV_M_MODE"
+                );
+            }
+            Some(Command::Linux { bootargs, .. }) => linux(uart, machine, bootargs, firmware_dtb),
+            Some(Command::Save { addr, len, .. }) if !in_dram(machine, addr, len) => {
+                uprintln!(
+                    uart,
+                    "{addr:#x}+{len:#x} isn't in DRAM at {:#x}..{:#x}",
+                    machine.dram.start,
+                    machine.dram.end
+                );
+            }
+            Some(Command::Save { addr, len, name }) => match save(uart, addr, len, name) {
+                Ok(()) => {
+                    uprintln!(uart, "saved {len:#x} bytes from {addr:#x} as {name}");
+                }
+                Err(error) => {
+                    uprintln!(uart, "save failed: {error:?}");
+                }
+            },
+            Some(Command::Help) => {
+                uprintln!(uart, "{}", console::HELP);
+            }
+            None if line.is_empty() => {}
+            None => {
+                uprintln!(uart, "unknown command, try help");
+            }
+        }
     }
 }
 
 #[no_mangle]
-unsafe extern "C" fn chainload_start() -> ! {
-    let mut uart = Uart::new();
+unsafe extern "C" fn chainload_start(dtb: usize) -> ! {
+    let mut buf = [0; FIRMWARE_DTB_LEN];
+    let firmware_dtb = copy_firmware_dtb(dtb, &mut buf);
+    let fdt = firmware_dtb.and_then(|dtb| Fdt::new(dtb).ok());
+    let machine = Machine::new(fdt.as_ref());
+    time::set_frequency(machine.timebase_frequency);
+    let mut uart = machine.uart();
+    uart.set_console();
     uart.initialize(115200).unwrap();
     uprintln!(uart, "hello, world!");
+    match fdt {
+        Some(_) => {
+            uprintln!(uart, "from the device tree: {machine}");
+        }
+        None => {
+            uprintln!(uart, "no device tree, assuming {machine}");
+        }
+    }
+    if machine.load_reserved.is_none() {
+        uprintln!(uart, "waiting for an image, press ESC for the console");
+    }
 
-    let output =
-        unsafe { core::slice::from_raw_parts_mut(0x80000000 as *mut u8, 0x200000000 - 0x40000000) };
-    zmodem::receive(&mut uart, output).unwrap();
+    let (protocol, len, entry) = loop {
+        if !load_reserved(&mut uart, &machine) {
+            match load(&mut uart, &machine) {
+                Ok((protocol, len)) => match prepare(len, &machine) {
+                    Ok(entry) => break (protocol, len, entry),
+                    Err(error) => {
+                        uprintln!(uart, "invalid ELF image: {error}");
+                    }
+                },
+                Err(Error::Cancelled) => {
+                    uprintln!(uart);
+                }
+                Err(error) => {
+                    uprintln!(uart, "load failed: {error:?}");
+                }
+            }
+        }
+        console(&mut uart, &machine, firmware_dtb);
+    };
     time::sleep(Duration::from_secs(5));
 
-    uprintln!(uart, "load finished");
+    uprintln!(
+        uart,
+        "load finished: {len} bytes via {protocol:?}, entry at {entry:#x}"
+    );
 
-    unsafe { asm!("jr {}", in(reg) 0x80000000usize) };
+    unsafe { asm!("fence.i", "jr {}", in(reg) entry) };
 
-    cease();
+    Board::halt(1);
 }
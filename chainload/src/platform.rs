@@ -0,0 +1,92 @@
+//! What differs between the machines we boot on, chosen by cargo feature.
+//!
+//! The UART, timebase and DRAM are only defaults, for what the previous stage's device tree
+//! doesn't say, see `machine.rs`.
+//!
+//! The boot hart is also passed to `locore.s` by `build.rs`.
+
+#[cfg(all(feature = "jh7110", feature = "qemu-virt"))]
+compile_error!("only one of the `jh7110` and `qemu-virt` features can be enabled");
+
+#[cfg(not(any(feature = "jh7110", feature = "qemu-virt")))]
+compile_error!("a platform feature, `jh7110` or `qemu-virt`, must be enabled");
+
+pub trait Platform {
+    /// Base address of the 16550 console UART.
+    const UART_BASE: usize;
+    /// Log2 of the spacing of the UART registers, which are accessed with the same width.
+    const UART_REG_SHIFT: usize;
+    /// Frequency of the clock the UART divides down to the baud rate.
+    const UART_CLOCK: u32;
+    /// Address of the CLINT mtime register.
+    const MTIME: usize;
+    const TIMEBASE_FREQUENCY: u64;
+    /// Where images are received, up to the end of DRAM, and flat binaries run from.
+    const LOAD_ADDRESS: usize;
+    /// DRAM, anywhere in which ELF segments can be loaded, clear of chainload itself.
+    const DRAM_BASE: usize;
+    const DRAM_END: usize;
+
+    /// Stop, reporting `status`, 0 for success, where the platform can.
+    fn halt(status: u16) -> !;
+}
+
+/// The StarFive JH7110, as on the VisionFive 2, booted by its SPL into DRAM at 0x40000000.
+#[cfg(feature = "jh7110")]
+pub struct Jh7110;
+
+#[cfg(feature = "jh7110")]
+impl Platform for Jh7110 {
+    const UART_BASE: usize = 0x10000000;
+    const UART_REG_SHIFT: usize = 2;
+    const UART_CLOCK: u32 = 24000000;
+    const MTIME: usize = 0x200bff8;
+    const TIMEBASE_FREQUENCY: u64 = 4000000;
+    const LOAD_ADDRESS: usize = 0x80000000;
+    const DRAM_BASE: usize = 0x40000000;
+    const DRAM_END: usize = 0x240000000;
+
+    fn halt(_status: u16) -> ! {
+        loop {
+            unsafe { core::arch::asm!(".insn i 0x73, 0x0, x0, x0, 0x305") };
+        }
+    }
+}
+
+#[cfg(feature = "jh7110")]
+pub type Board = Jh7110;
+
+/// QEMU's `virt` machine with its default 128 MiB of memory, booted with `-bios` so that we run
+/// from the start of DRAM.
+#[cfg(feature = "qemu-virt")]
+pub struct QemuVirt;
+
+/// The SiFive test device, which powers off or exits QEMU.
+#[cfg(feature = "qemu-virt")]
+const TEST_DEVICE: usize = 0x100000;
+
+#[cfg(feature = "qemu-virt")]
+impl Platform for QemuVirt {
+    const UART_BASE: usize = 0x10000000;
+    const UART_REG_SHIFT: usize = 0;
+    const UART_CLOCK: u32 = 3686400;
+    const MTIME: usize = 0x200bff8;
+    const TIMEBASE_FREQUENCY: u64 = 10000000;
+    const LOAD_ADDRESS: usize = 0x80200000;
+    const DRAM_BASE: usize = 0x80000000;
+    const DRAM_END: usize = 0x88000000;
+
+    fn halt(status: u16) -> ! {
+        let command = match status {
+            0 => 0x5555,
+            _ => (status as u32) << 16 | 0x3333,
+        };
+        unsafe { (TEST_DEVICE as *mut u32).write_volatile(command) };
+        loop {
+            unsafe { core::arch::asm!("wfi") };
+        }
+    }
+}
+
+#[cfg(feature = "qemu-virt")]
+pub type Board = QemuVirt;
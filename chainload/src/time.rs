@@ -1,14 +1,24 @@
-use core::time::Duration;
+use crate::platform::{Board, Platform};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
 const MICROS_PER_SECOND: u64 = 1000000;
-const TIME_BASE_FREQUENCY: u64 = 4000000;
+
+/// The platform's until the device tree says otherwise.
+static TIME_BASE_FREQUENCY: AtomicU64 = AtomicU64::new(Board::TIMEBASE_FREQUENCY);
+
+pub fn set_frequency(frequency: u64) {
+    TIME_BASE_FREQUENCY.store(frequency, Ordering::Relaxed);
+}
 
 #[derive(Clone, Copy)]
 pub struct Instant(u64);
 
 impl Instant {
     pub fn now() -> Instant {
-        let mtime = 0x200bff8 as *mut u64;
+        let mtime = Board::MTIME as *mut u64;
         Instant(unsafe { mtime.read_volatile() })
     }
 
@@ -17,12 +27,13 @@ impl Instant {
     }
 
     pub fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
-        let ticks_per_micro = (TIME_BASE_FREQUENCY + MICROS_PER_SECOND - 1) / MICROS_PER_SECOND;
+        let frequency = TIME_BASE_FREQUENCY.load(Ordering::Relaxed);
+        let ticks_per_micro = (frequency + MICROS_PER_SECOND - 1) / MICROS_PER_SECOND;
 
         let diff = self.0.checked_sub(earlier.0)?;
 
-        let secs = diff / TIME_BASE_FREQUENCY;
-        let rems = diff % TIME_BASE_FREQUENCY;
+        let secs = diff / frequency;
+        let rems = diff % frequency;
         let nanos = (rems / ticks_per_micro) * 1000;
 
         Some(Duration::new(secs, nanos as u32))
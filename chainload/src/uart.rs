@@ -1,5 +1,11 @@
-use crate::time::Timeout;
-use core::time::Duration;
+use crate::platform::{Board, Platform};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use embedded_hal_nb::{nb, serial};
+
+/// The console's settings, the platform's until the device tree says otherwise.
+static CONSOLE_BASE: AtomicUsize = AtomicUsize::new(Board::UART_BASE);
+static CONSOLE_REG_SHIFT: AtomicUsize = AtomicUsize::new(Board::UART_REG_SHIFT);
+static CONSOLE_CLOCK: AtomicU32 = AtomicU32::new(Board::UART_CLOCK);
 
 pub struct Reg(usize);
 
@@ -69,27 +75,65 @@ bitflags::bitflags! {
 
 #[derive(Debug)]
 #[non_exhaustive]
-pub enum UartError {
-    TimedOut,
+pub enum UartError {}
+
+impl serial::Error for UartError {
+    fn kind(&self) -> serial::ErrorKind {
+        match *self {}
+    }
 }
 
 pub struct Uart {
-    regs: *mut u32,
+    base: usize,
+    /// Log2 of the spacing of the registers, which are accessed with the same width.
+    reg_shift: usize,
+    /// Frequency of the clock divided down to the baud rate.
+    clock: u32,
 }
 
 impl Uart {
+    /// The console UART, as last set with [`set_console`](Self::set_console).
     pub fn new() -> Uart {
+        Self::at(
+            CONSOLE_BASE.load(Ordering::Relaxed),
+            CONSOLE_REG_SHIFT.load(Ordering::Relaxed),
+            CONSOLE_CLOCK.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Make this UART the console, for the panic and trap handlers.
+    pub fn set_console(&self) {
+        CONSOLE_BASE.store(self.base, Ordering::Relaxed);
+        CONSOLE_REG_SHIFT.store(self.reg_shift, Ordering::Relaxed);
+        CONSOLE_CLOCK.store(self.clock, Ordering::Relaxed);
+    }
+
+    pub fn at(base: usize, reg_shift: usize, clock: u32) -> Uart {
         Self {
-            regs: 0x10000000 as *mut u32,
+            base,
+            reg_shift,
+            clock,
         }
     }
 
+    fn register(&self, reg: Reg) -> usize {
+        self.base + (reg.0 << self.reg_shift)
+    }
+
     pub fn read_register(&self, reg: Reg) -> u8 {
-        unsafe { self.regs.add(reg.0).read_volatile() as u8 }
+        let addr = self.register(reg);
+        match self.reg_shift {
+            0 => unsafe { (addr as *const u8).read_volatile() },
+            _ => unsafe { (addr as *const u32).read_volatile() as u8 },
+        }
     }
 
     pub fn write_register(&self, reg: Reg, val: u8) {
-        unsafe { self.regs.add(reg.0).write_volatile(val as u32) };
+        let addr = self.register(reg);
+        match self.reg_shift {
+            0 => unsafe { (addr as *mut u8).write_volatile(val) },
+            _ => unsafe { (addr as *mut u32).write_volatile(val as u32) },
+        }
     }
 
     pub fn set_interrupt_enable(&self, ier: InterruptEnable) {
@@ -113,7 +157,7 @@ impl Uart {
     }
 
     pub fn set_baud(&self, baud: u32) -> Result<(), UartError> {
-        let divisor = divisor_for_baud(baud);
+        let divisor = divisor_for_baud(self.clock, baud);
         self.set_line_control(self.line_control() | LineControl::DIVISOR_LATCH_ACCESS);
         self.write_register(Reg::DIVISOR_LO, divisor[0]);
         self.write_register(Reg::DIVISOR_HI, divisor[1]);
@@ -152,32 +196,46 @@ impl Uart {
             .contains(LineStatus::TX_HOLDING_REGISTER_EMPTY)
     }
 
-    pub fn receive_timeout(&self, timo: Duration) -> Result<u8, UartError> {
-        let timeout = Timeout::start(timo);
-        while !self.data_ready() {
-            if timeout.expired() {
-                return Err(UartError::TimedOut);
-            }
+    pub fn transmit(&self, byte: u8) -> Result<(), UartError> {
+        while !self.tx_holding_register_empty() {
+            core::hint::spin_loop();
         }
-        Ok(self.read_register(Reg::RX_BUFFER))
+        self.write_register(Reg::TX_HOLDING, byte);
+        Ok(())
     }
+}
+
+impl serial::ErrorType for Uart {
+    type Error = UartError;
+}
 
-    pub fn receive(&self) -> Result<u8, UartError> {
-        self.receive_timeout(Duration::MAX)
+impl serial::Read for Uart {
+    fn read(&mut self) -> nb::Result<u8, UartError> {
+        if !self.data_ready() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(self.read_register(Reg::RX_BUFFER))
     }
+}
 
-    pub fn transmit(&self, byte: u8) -> Result<(), UartError> {
-        while !self.tx_holding_register_empty() {
-            core::hint::spin_loop();
+impl serial::Write for Uart {
+    fn write(&mut self, byte: u8) -> nb::Result<(), UartError> {
+        if !self.tx_holding_register_empty() {
+            return Err(nb::Error::WouldBlock);
         }
         self.write_register(Reg::TX_HOLDING, byte);
         Ok(())
     }
-}
 
-const CLOCK_FREQUENCY: u32 = 24000000;
+    fn flush(&mut self) -> nb::Result<(), UartError> {
+        if !self.line_status().contains(LineStatus::TX_EMPTY) {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
 
-fn divisor_for_baud(baud: u32) -> [u8; 2] {
-    let divisor = u16::try_from(CLOCK_FREQUENCY / (baud * 16)).unwrap();
+fn divisor_for_baud(clock: u32, baud: u32) -> [u8; 2] {
+    let divisor = u16::try_from(clock / (baud * 16)).unwrap();
     divisor.to_le_bytes()
 }
@@ -0,0 +1,376 @@
+//! Writing a copy of a tree with properties, nodes and memory reservations added or changed.
+
+use crate::{
+    align4, Fdt, FdtError, Node, Token, FDT_BEGIN_NODE, FDT_END, FDT_END_NODE, FDT_PROP,
+    HEADER_LEN, LAST_COMP_VERSION, MAGIC, VERSION,
+};
+
+const MAX_EDITS: usize = 16;
+const MAX_RESERVATIONS: usize = 4;
+/// How deeply the tree being copied can nest.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Copy)]
+enum Edit<'a> {
+    /// Change a property, or add it after the node's others.
+    SetProperty {
+        path: &'a str,
+        name: &'a str,
+        value: &'a [u8],
+    },
+    RemoveProperty {
+        path: &'a str,
+        name: &'a str,
+    },
+    /// Add a node after its parent's other children.
+    AddNode {
+        path: &'a str,
+    },
+}
+
+/// Edits to a tree, made by [`write`](Editor::write) to a copy of it.
+///
+/// Nodes are named by absolute paths, such as `/chosen`, with unit addresses where they have
+/// them.
+pub struct Editor<'a> {
+    fdt: Fdt<'a>,
+    edits: [Option<Edit<'a>>; MAX_EDITS],
+    reservations: [Option<(u64, u64)>; MAX_RESERVATIONS],
+}
+
+/// The components of an absolute path, none for `/`.
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
+
+/// The parent of an absolute path other than `/`, and the last component.
+fn split(path: &str) -> (&str, &str) {
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => ("/", path),
+    }
+}
+
+/// Whether `path` is the innermost of the nodes `open`, the first of which is the root.
+fn is_at(path: &str, open: &[&str]) -> bool {
+    components(path).eq(open[1..].iter().copied())
+}
+
+/// The offset of `name` in the strings block, which may be the end of a longer string.
+fn find_string(strings: &[u8], name: &str) -> Option<u32> {
+    let len = name.len();
+    strings
+        .windows(len + 1)
+        .position(|s| s[len] == 0 && &s[..len] == name.as_bytes())
+        .map(|offset| offset as u32)
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), FdtError> {
+        let end = self.len + bytes.len();
+        self.out
+            .get_mut(self.len..end)
+            .ok_or(FdtError::Full)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    fn u32(&mut self, value: u32) -> Result<(), FdtError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    fn align(&mut self) -> Result<(), FdtError> {
+        let padding = align4(self.len) - self.len;
+        self.bytes(&[0; 3][..padding])
+    }
+
+    fn prop(&mut self, name_offset: u32, value: &[u8]) -> Result<(), FdtError> {
+        self.u32(FDT_PROP)?;
+        self.u32(value.len() as u32)?;
+        self.u32(name_offset)?;
+        self.bytes(value)?;
+        self.align()
+    }
+}
+
+/// What's carried through a write: where each set property's name is in the strings block, and
+/// which of them have been written.
+struct State {
+    name_offsets: [u32; MAX_EDITS],
+    written: [bool; MAX_EDITS],
+}
+
+impl<'a> Editor<'a> {
+    pub fn new(fdt: Fdt<'a>) -> Editor<'a> {
+        Editor {
+            fdt,
+            edits: [None; MAX_EDITS],
+            reservations: [None; MAX_RESERVATIONS],
+        }
+    }
+
+    /// The node at `path` in the original tree, matching names exactly.
+    fn node(&self, path: &str) -> Option<Node<'a>> {
+        components(path).try_fold(self.fdt.root(), |node, name| {
+            node.children().find(|child| child.name() == name)
+        })
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        let added = || {
+            self.edits
+                .iter()
+                .any(|edit| matches!(edit, Some(Edit::AddNode { path: p }) if *p == path))
+        };
+        path.starts_with('/') && (self.node(path).is_some() || added())
+    }
+
+    fn push(&mut self, edit: Edit<'a>) -> Result<(), FdtError> {
+        let slot = self.edits.iter_mut().find(|slot| slot.is_none());
+        *slot.ok_or(FdtError::TooManyEdits)? = Some(edit);
+        Ok(())
+    }
+
+    /// Record an edit to property `name` of `path`, replacing any earlier one.
+    fn edit_property(&mut self, path: &str, name: &str, edit: Edit<'a>) -> Result<(), FdtError> {
+        if !self.exists(path) {
+            return Err(FdtError::NotFound);
+        }
+        let earlier = self.edits.iter_mut().find(|slot| match slot {
+            Some(Edit::SetProperty {
+                path: p, name: n, ..
+            })
+            | Some(Edit::RemoveProperty { path: p, name: n }) => *p == path && *n == name,
+            _ => false,
+        });
+        match earlier {
+            Some(slot) => *slot = Some(edit),
+            None => self.push(edit)?,
+        }
+        Ok(())
+    }
+
+    /// Set property `name` of the node at `path`, which exists or has been added.
+    pub fn set_property(
+        &mut self,
+        path: &'a str,
+        name: &'a str,
+        value: &'a [u8],
+    ) -> Result<(), FdtError> {
+        self.edit_property(path, name, Edit::SetProperty { path, name, value })
+    }
+
+    pub fn remove_property(&mut self, path: &'a str, name: &'a str) -> Result<(), FdtError> {
+        self.edit_property(path, name, Edit::RemoveProperty { path, name })
+    }
+
+    /// Add the node at `path` unless it exists. Its parent must exist or have been added.
+    pub fn add_node(&mut self, path: &'a str) -> Result<(), FdtError> {
+        if self.exists(path) {
+            return Ok(());
+        }
+        let (parent, name) = split(path);
+        if name.is_empty() || !self.exists(parent) {
+            return Err(FdtError::NotFound);
+        }
+        self.push(Edit::AddNode { path })
+    }
+
+    /// Add an entry to the memory reservation block.
+    pub fn add_reservation(&mut self, address: u64, size: u64) -> Result<(), FdtError> {
+        let slot = self.reservations.iter_mut().find(|slot| slot.is_none());
+        *slot.ok_or(FdtError::TooManyEdits)? = Some((address, size));
+        Ok(())
+    }
+
+    /// The properties set on the node `at` is true for, by index into `edits`.
+    fn set_properties<'e>(
+        &'e self,
+        at: impl Fn(&str) -> bool + 'e,
+    ) -> impl Iterator<Item = (usize, &'a [u8])> + 'e {
+        self.edits
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, edit)| match edit {
+                Some(Edit::SetProperty { path, value, .. }) if at(path) => Some((i, *value)),
+                _ => None,
+            })
+    }
+
+    /// The nodes added as children of the one `at` is true for.
+    fn added_children<'e>(
+        &'e self,
+        at: impl Fn(&str) -> bool + 'e,
+    ) -> impl Iterator<Item = &'a str> + 'e {
+        self.edits.iter().filter_map(move |edit| match edit {
+            Some(Edit::AddNode { path }) if at(split(path).0) => Some(*path),
+            _ => None,
+        })
+    }
+
+    /// Write the properties set on a node that it doesn't already have.
+    fn write_properties(
+        &self,
+        w: &mut Writer,
+        state: &mut State,
+        at: impl Fn(&str) -> bool,
+    ) -> Result<(), FdtError> {
+        for (i, value) in self.set_properties(at) {
+            if !state.written[i] {
+                w.prop(state.name_offsets[i], value)?;
+                state.written[i] = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_node(&self, w: &mut Writer, state: &mut State, path: &str) -> Result<(), FdtError> {
+        w.u32(FDT_BEGIN_NODE)?;
+        w.bytes(split(path).1.as_bytes())?;
+        w.bytes(&[0])?;
+        w.align()?;
+        self.write_properties(w, state, |p| p == path)?;
+        for child in self.added_children(|p| p == path) {
+            self.write_node(w, state, child)?;
+        }
+        w.u32(FDT_END_NODE)
+    }
+
+    /// Write the edited tree to `out`, returning its length.
+    pub fn write(&self, out: &mut [u8]) -> Result<usize, FdtError> {
+        let fdt = &self.fdt;
+        let mut state = State {
+            name_offsets: [0; MAX_EDITS],
+            written: [false; MAX_EDITS],
+        };
+
+        // Names not already in the strings block are appended to it.
+        let mut strings_len = fdt.strings.len();
+        for (i, edit) in self.edits.iter().enumerate() {
+            let Some(Edit::SetProperty { name, .. }) = edit else {
+                continue;
+            };
+            let earlier = self.edits[..i].iter().position(
+                |edit| matches!(edit, Some(Edit::SetProperty { name: n, .. }) if n == name),
+            );
+            state.name_offsets[i] = match (find_string(fdt.strings, name), earlier) {
+                (Some(offset), _) => offset,
+                (None, Some(j)) => state.name_offsets[j],
+                (None, None) => {
+                    strings_len += name.len() + 1;
+                    (strings_len - name.len() - 1) as u32
+                }
+            };
+        }
+
+        let mut w = Writer { out, len: 0 };
+        w.bytes(&[0; HEADER_LEN])?;
+        let added = self.reservations.iter().flatten().copied();
+        for (address, size) in fdt.reservations().chain(added) {
+            w.bytes(&address.to_be_bytes())?;
+            w.bytes(&size.to_be_bytes())?;
+        }
+        w.bytes(&[0; 16])?;
+
+        let struct_start = w.len;
+        let mut open = [""; MAX_DEPTH];
+        let mut depth = 0;
+        // Whether the innermost node's added properties are still to be written, which they are
+        // before its first child or its end.
+        let mut pending = false;
+        let mut offset = 0;
+        loop {
+            let (token, next) = fdt.token(offset)?;
+            match token {
+                Token::BeginNode(name) => {
+                    if pending {
+                        self.write_properties(&mut w, &mut state, |p| is_at(p, &open[..depth]))?;
+                    }
+                    *open.get_mut(depth).ok_or(FdtError::Unsupported)? = name;
+                    depth += 1;
+                    pending = true;
+                    w.bytes(&fdt.structure[offset..next])?;
+                }
+                Token::EndNode => {
+                    let at = |p: &str| is_at(p, &open[..depth]);
+                    if pending {
+                        self.write_properties(&mut w, &mut state, at)?;
+                    }
+                    pending = false;
+                    for child in self.added_children(at) {
+                        self.write_node(&mut w, &mut state, child)?;
+                    }
+                    w.u32(FDT_END_NODE)?;
+                    depth -= 1;
+                }
+                Token::Prop(property) => {
+                    let edit = self
+                        .edits
+                        .iter()
+                        .enumerate()
+                        .find_map(|(i, edit)| match edit {
+                            Some(
+                                Edit::SetProperty { path, name, .. }
+                                | Edit::RemoveProperty { path, name },
+                            ) if *name == property.name && is_at(path, &open[..depth]) => {
+                                Some((i, edit.unwrap()))
+                            }
+                            _ => None,
+                        });
+                    match edit {
+                        Some((i, Edit::SetProperty { value, .. })) => {
+                            w.prop(state.name_offsets[i], value)?;
+                            state.written[i] = true;
+                        }
+                        Some(_) => {}
+                        None => w.bytes(&fdt.structure[offset..next])?,
+                    }
+                }
+                Token::Nop => {}
+                Token::End => {
+                    w.u32(FDT_END)?;
+                    break;
+                }
+            }
+            offset = next;
+        }
+        let struct_len = w.len - struct_start;
+
+        let strings_start = w.len;
+        w.bytes(fdt.strings)?;
+        for (i, edit) in self.edits.iter().enumerate() {
+            if let Some(Edit::SetProperty { name, .. }) = edit {
+                // Once each, in the order the offsets were handed out.
+                if state.name_offsets[i] as usize == w.len - strings_start {
+                    w.bytes(name.as_bytes())?;
+                    w.bytes(&[0])?;
+                }
+            }
+        }
+        let len = w.len;
+
+        let header = [
+            MAGIC,
+            len as u32,
+            struct_start as u32,
+            strings_start as u32,
+            HEADER_LEN as u32,
+            VERSION,
+            LAST_COMP_VERSION,
+            fdt.boot_cpuid(),
+            strings_len as u32,
+            struct_len as u32,
+        ];
+        for (i, value) in header.into_iter().enumerate() {
+            w.out[i * 4..][..4].copy_from_slice(&value.to_be_bytes());
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,521 @@
+//! Reading and editing flattened device trees, the DTBs boot firmware hands over.
+//!
+//! [`Fdt`] reads a blob where it lies, without allocating, and [`Editor`] writes a copy of it
+//! with properties, nodes and memory reservations added or changed.
+
+#![no_std]
+
+mod edit;
+
+pub use edit::Editor;
+
+use core::{ops::Range, str};
+
+const MAGIC: u32 = 0xd00dfeed;
+const HEADER_LEN: usize = 40;
+/// The version written, and the oldest read, which is the first with the structure block's size.
+const VERSION: u32 = 17;
+const LAST_COMP_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FdtError {
+    /// Not a device tree, or a corrupted one.
+    Invalid,
+    /// A version of the format we can't read.
+    Unsupported,
+    /// The node to edit doesn't exist, or the path isn't absolute.
+    NotFound,
+    /// More edits than an [`Editor`] holds.
+    TooManyEdits,
+    /// The output buffer is too small.
+    Full,
+}
+
+pub fn is_fdt(blob: &[u8]) -> bool {
+    blob.starts_with(&MAGIC.to_be_bytes())
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Result<u32, FdtError> {
+    let bytes = bytes.get(offset..offset + 4).ok_or(FdtError::Invalid)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// A NUL-terminated string at `offset`.
+fn string(bytes: &[u8], offset: usize) -> Result<&str, FdtError> {
+    let rest = bytes.get(offset..).ok_or(FdtError::Invalid)?;
+    let len = rest.iter().position(|&b| b == 0).ok_or(FdtError::Invalid)?;
+    str::from_utf8(&rest[..len]).map_err(|_| FdtError::Invalid)
+}
+
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// A number of cells, as in `reg`, most significant first.
+fn cells(bytes: &[u8]) -> u64 {
+    bytes.chunks_exact(4).fold(0, |value, cell| {
+        value << 32 | u64::from(be32(cell, 0).unwrap())
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(Property<'a>),
+    Nop,
+    End,
+}
+
+/// A device tree blob, checked to be well formed when created.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// The size of the blob that starts with `header`, of which only the first 8 bytes are read.
+    pub fn total_size(header: &[u8]) -> Result<usize, FdtError> {
+        if !is_fdt(header) {
+            return Err(FdtError::Invalid);
+        }
+        Ok(be32(header, 4)? as usize)
+    }
+
+    pub fn new(blob: &'a [u8]) -> Result<Fdt<'a>, FdtError> {
+        let blob = blob
+            .get(..Self::total_size(blob)?)
+            .ok_or(FdtError::Invalid)?;
+        if be32(blob, 20)? < VERSION || be32(blob, 24)? > VERSION {
+            return Err(FdtError::Unsupported);
+        }
+        let block = |offset: usize, size: usize| -> Result<&'a [u8], FdtError> {
+            let offset = be32(blob, offset)? as usize;
+            let size = be32(blob, size)? as usize;
+            offset
+                .checked_add(size)
+                .and_then(|end| blob.get(offset..end))
+                .ok_or(FdtError::Invalid)
+        };
+        let fdt = Fdt {
+            blob,
+            structure: block(8, 36)?,
+            strings: block(12, 32)?,
+        };
+        fdt.check()?;
+        Ok(fdt)
+    }
+
+    /// Walk the whole tree once, so that nodes and properties can be read without errors.
+    fn check(&self) -> Result<(), FdtError> {
+        let mut offset = be32(self.blob, 16)? as usize;
+        loop {
+            let entry = offset
+                .checked_add(16)
+                .and_then(|end| self.blob.get(offset..end))
+                .ok_or(FdtError::Invalid)?;
+            offset += 16;
+            if entry.iter().all(|&b| b == 0) {
+                break;
+            }
+        }
+
+        let mut offset = self.skip_nops(0)?;
+        let Token::BeginNode("") = self.token(offset)?.0 else {
+            return Err(FdtError::Invalid);
+        };
+        offset = self.skip_node(offset)?;
+        match self.token(self.skip_nops(offset)?)?.0 {
+            Token::End => Ok(()),
+            _ => Err(FdtError::Invalid),
+        }
+    }
+
+    fn skip_nops(&self, mut offset: usize) -> Result<usize, FdtError> {
+        while let (Token::Nop, next) = self.token(offset)? {
+            offset = next;
+        }
+        Ok(offset)
+    }
+
+    /// The offset after the node whose `FDT_BEGIN_NODE` is at `offset`, checking that properties
+    /// come before children throughout.
+    fn skip_node(&self, offset: usize) -> Result<usize, FdtError> {
+        let (_, mut offset) = self.token(offset)?;
+        let mut depth = 1;
+        // Whether the node we're in has had a child yet.
+        let mut children = false;
+        loop {
+            let (token, next) = self.token(offset)?;
+            match token {
+                Token::BeginNode(_) => {
+                    depth += 1;
+                    children = false;
+                }
+                Token::EndNode => {
+                    depth -= 1;
+                    children = true;
+                    if depth == 0 {
+                        return Ok(next);
+                    }
+                }
+                Token::Prop(_) if children => return Err(FdtError::Invalid),
+                Token::Prop(_) | Token::Nop => {}
+                Token::End => return Err(FdtError::Invalid),
+            }
+            offset = next;
+        }
+    }
+
+    /// The token at `offset` in the structure block, and the offset of the next one.
+    fn token(&self, offset: usize) -> Result<(Token<'a>, usize), FdtError> {
+        let s = self.structure;
+        let token = be32(s, offset)?;
+        let offset = offset + 4;
+        Ok(match token {
+            FDT_BEGIN_NODE => {
+                let name = string(s, offset)?;
+                (Token::BeginNode(name), offset + align4(name.len() + 1))
+            }
+            FDT_END_NODE => (Token::EndNode, offset),
+            FDT_PROP => {
+                let len = be32(s, offset)? as usize;
+                let name = string(self.strings, be32(s, offset + 4)? as usize)?;
+                let start = offset + 8;
+                let value = start
+                    .checked_add(len)
+                    .and_then(|end| s.get(start..end))
+                    .ok_or(FdtError::Invalid)?;
+                (Token::Prop(Property { name, value }), start + align4(len))
+            }
+            FDT_NOP => (Token::Nop, offset),
+            FDT_END => (Token::End, offset),
+            _ => return Err(FdtError::Invalid),
+        })
+    }
+
+    /// The physical ID of the CPU the previous stage booted on.
+    pub fn boot_cpuid(&self) -> u32 {
+        be32(self.blob, 28).unwrap()
+    }
+
+    /// The memory reservation block, as address and size.
+    pub fn reservations(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let offset = be32(self.blob, 16).unwrap() as usize;
+        self.blob[offset..]
+            .chunks_exact(16)
+            .map(|entry| (cells(&entry[..8]), cells(&entry[8..])))
+            .take_while(|&entry| entry != (0, 0))
+    }
+
+    pub fn root(&self) -> Node<'a> {
+        let offset = self.skip_nops(0).unwrap();
+        Node {
+            fdt: *self,
+            name: "",
+            body: self.token(offset).unwrap().1,
+            cells: (2, 1),
+        }
+    }
+
+    /// The node at `path`, which is absolute or starts with an alias.
+    pub fn find(&self, path: &str) -> Option<Node<'a>> {
+        let (node, rest) = match path.strip_prefix('/') {
+            Some(rest) => (self.root(), rest),
+            None => {
+                let (alias, rest) = path.split_once('/').unwrap_or((path, ""));
+                let target = self.root().child("aliases")?.property(alias)?.as_str()?;
+                if !target.starts_with('/') {
+                    return None;
+                }
+                (self.find(target)?, rest)
+            }
+        };
+        rest.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(node, |node, name| node.child(name))
+    }
+
+    pub fn chosen(&self) -> Option<Node<'a>> {
+        self.root().child("chosen")
+    }
+
+    /// The console named by `/chosen/stdout-path`, with the options after its `:`, such as
+    /// `115200n8`.
+    pub fn stdout(&self) -> Option<(Node<'a>, Option<&'a str>)> {
+        let chosen = self.chosen()?;
+        let path = chosen
+            .property("stdout-path")
+            .or_else(|| chosen.property("linux,stdout-path"))?
+            .as_str()?;
+        let (path, options) = match path.split_once(':') {
+            Some((path, options)) => (path, Some(options)),
+            None => (path, None),
+        };
+        Some((self.find(path)?, options))
+    }
+
+    /// RAM, from the `memory` nodes.
+    pub fn memory(&self) -> impl Iterator<Item = Range<u64>> + 'a {
+        self.root()
+            .children()
+            .filter(|node| node.device_type() == Some("memory") && node.is_enabled())
+            .flat_map(|node| node.reg())
+            .map(|(address, size)| address..address.saturating_add(size))
+    }
+
+    /// Memory in use by something other than the OS: the reservation block, and the children of
+    /// `/reserved-memory` with a `reg`.
+    pub fn reserved_memory(&self) -> impl Iterator<Item = Range<u64>> + 'a {
+        let nodes = self.root().child("reserved-memory").into_iter();
+        self.reservations()
+            .chain(
+                nodes
+                    .flat_map(|node| node.children())
+                    .flat_map(|node| node.reg()),
+            )
+            .map(|(address, size)| address..address.saturating_add(size))
+    }
+
+    /// The `timebase-frequency` of `/cpus`, or of the first CPU if that's where it is.
+    pub fn timebase_frequency(&self) -> Option<u64> {
+        let cpus = self.root().child("cpus")?;
+        match cpus.property("timebase-frequency") {
+            Some(frequency) => frequency.as_u64(),
+            None => self
+                .cpus()
+                .next()?
+                .node
+                .property("timebase-frequency")?
+                .as_u64(),
+        }
+    }
+
+    /// The children of `/cpus` that are CPUs, including disabled ones.
+    pub fn cpus(&self) -> impl Iterator<Item = Cpu<'a>> + 'a {
+        self.root()
+            .child("cpus")
+            .into_iter()
+            .flat_map(|cpus| cpus.children())
+            .filter(|node| node.device_type() == Some("cpu"))
+            .filter_map(|node| {
+                let (id, _) = node.reg().next()?;
+                Some(Cpu { node, id })
+            })
+    }
+}
+
+/// A CPU, a hart on RISC-V.
+#[derive(Clone, Copy)]
+pub struct Cpu<'a> {
+    pub node: Node<'a>,
+    /// The physical ID, its `reg`, which is the hart ID on RISC-V.
+    pub id: u64,
+}
+
+impl Cpu<'_> {
+    pub fn is_enabled(&self) -> bool {
+        self.node.is_enabled()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the token after `FDT_BEGIN_NODE` in the structure block.
+    body: usize,
+    /// The parent's `#address-cells` and `#size-cells`, which `reg` is in.
+    cells: (usize, usize),
+}
+
+impl<'a> Node<'a> {
+    /// The name, with the unit address if any.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.body,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn children(&self) -> Children<'a> {
+        Children {
+            fdt: self.fdt,
+            offset: Some(self.body),
+            cells: (self.address_cells(), self.size_cells()),
+        }
+    }
+
+    /// The child called `name`, which can leave out the unit address if there's no `@` in it.
+    pub fn child(&self, name: &str) -> Option<Node<'a>> {
+        let unit = name.contains('@');
+        self.children().find(|child| {
+            child.name == name
+                || !unit
+                    && child
+                        .name
+                        .split_once('@')
+                        .is_some_and(|(base, _)| base == name)
+        })
+    }
+
+    /// `#address-cells`, for the children's `reg`.
+    pub fn address_cells(&self) -> usize {
+        self.property("#address-cells")
+            .and_then(|cells| cells.as_u32())
+            .map_or(2, |cells| cells as usize)
+    }
+
+    /// `#size-cells`, for the children's `reg`.
+    pub fn size_cells(&self) -> usize {
+        self.property("#size-cells")
+            .and_then(|cells| cells.as_u32())
+            .map_or(1, |cells| cells as usize)
+    }
+
+    /// The address and size pairs of `reg`, with the size 0 where the parent has no size cells.
+    pub fn reg(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let (address_cells, size_cells) = self.cells;
+        let len = (address_cells + size_cells) * 4;
+        let value = match self.property("reg") {
+            Some(reg) if len > 0 => reg.value,
+            _ => &[],
+        };
+        value.chunks_exact(len.max(1)).map(move |entry| {
+            let (address, size) = entry.split_at(address_cells * 4);
+            (cells(address), cells(size))
+        })
+    }
+
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.property("compatible")
+            .into_iter()
+            .flat_map(|p| p.strings())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    pub fn device_type(&self) -> Option<&'a str> {
+        self.property("device_type")?.as_str()
+    }
+
+    /// Whether `status` is missing or says it's usable.
+    pub fn is_enabled(&self) -> bool {
+        match self.property("status").and_then(|status| status.as_str()) {
+            Some(status) => status == "okay" || status == "ok",
+            None => true,
+        }
+    }
+}
+
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        loop {
+            let (token, next) = self.fdt.token(self.offset).ok()?;
+            match token {
+                Token::Prop(property) => {
+                    self.offset = next;
+                    return Some(property);
+                }
+                Token::Nop => self.offset = next,
+                _ => return None,
+            }
+        }
+    }
+}
+
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    /// `None` once the parent's `FDT_END_NODE` is reached.
+    offset: Option<usize>,
+    cells: (usize, usize),
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            let offset = self.offset?;
+            let (token, next) = self.fdt.token(offset).ok()?;
+            match token {
+                Token::BeginNode(name) => {
+                    self.offset = self.fdt.skip_node(offset).ok();
+                    return Some(Node {
+                        fdt: self.fdt,
+                        name,
+                        body: next,
+                        cells: self.cells,
+                    });
+                }
+                Token::Prop(_) | Token::Nop => self.offset = Some(next),
+                Token::EndNode | Token::End => self.offset = None,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub fn as_u32(&self) -> Option<u32> {
+        match self.value.len() {
+            4 => be32(self.value, 0).ok(),
+            _ => None,
+        }
+    }
+
+    /// One or two cells.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.value.len() {
+            4 | 8 => Some(cells(self.value)),
+            _ => None,
+        }
+    }
+
+    /// The first string in the value.
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.strings().next()
+    }
+
+    /// The NUL-terminated strings in the value, as in `compatible`.
+    pub fn strings(&self) -> impl Iterator<Item = &'a str> + 'a {
+        let value = match self.value.last() {
+            Some(0) => &self.value[..self.value.len() - 1],
+            _ => &[],
+        };
+        value
+            .split(|&b| b == 0)
+            .take_while(move |_| !value.is_empty())
+            .map_while(|s| str::from_utf8(s).ok())
+    }
+}
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Replace the trees here with the one QEMU's virt machine generates and the upstream VisionFive 2
+# one, then check the tests still hold. Needs qemu-system-riscv64 and dtc.
+#
+# Give a Linux build directory, with the dtbs built, as the argument. The upstream tree lacks
+# what U-Boot SPL fills in, the memory size, the reservation for OpenSBI and the UART clock, so
+# the VisionFive 2 tests expecting those need updating along with it.
+
+dtb=arch/riscv/boot/dts/starfive/jh7110-starfive-visionfive-2-v1.3b.dtb
+if [ ! "$1" ]; then echo "Linux build directory required"; exit 1; fi
+if [ ! -f "$1/$dtb" ]; then echo "$1/$dtb not found, run make dtbs"; exit 1; fi
+cd "$(dirname "$0")" || exit 1
+
+qemu-system-riscv64 -machine virt,dumpdtb=qemu-virt.dtb -smp 1 -m 128M -nographic || exit 1
+cp "$1/$dtb" . || exit 1
+
+for tree in qemu-virt jh7110-starfive-visionfive-2-v1.3b; do
+	dtc -q -I dtb -O dts -o $tree.dts $tree.dtb || exit 1
+done
@@ -0,0 +1,217 @@
+use fdt::{Editor, Fdt, FdtError};
+
+const QEMU_VIRT: &[u8] = include_bytes!("dtbs/qemu-virt.dtb");
+const VISIONFIVE2: &[u8] = include_bytes!("dtbs/jh7110-starfive-visionfive-2-v1.3b.dtb");
+
+fn write(editor: &Editor) -> Vec<u8> {
+    let mut out = vec![0; 8192];
+    let len = editor.write(&mut out).unwrap();
+    out.truncate(len);
+    out
+}
+
+fn names<'a>(node: fdt::Node<'a>) -> Vec<&'a str> {
+    node.properties().map(|p| p.name).collect()
+}
+
+#[test]
+fn unchanged() {
+    for blob in [QEMU_VIRT, VISIONFIVE2] {
+        let editor = Editor::new(Fdt::new(blob).unwrap());
+        assert_eq!(write(&editor), blob);
+    }
+}
+
+#[test]
+fn chosen() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let mut editor = Editor::new(fdt);
+    let start = 0x84000000u64.to_be_bytes();
+    let end = 0x84100000u64.to_be_bytes();
+    editor
+        .set_property("/chosen", "bootargs", b"console=ttyS0\0")
+        .unwrap();
+    editor
+        .set_property("/chosen", "linux,initrd-start", &start)
+        .unwrap();
+    editor
+        .set_property("/chosen", "linux,initrd-end", &end)
+        .unwrap();
+    editor
+        .set_property("/chosen", "stdout-path", b"serial0\0")
+        .unwrap();
+    editor.remove_property("/chosen", "rng-seed").unwrap();
+    let out = write(&editor);
+
+    let fdt = Fdt::new(&out).unwrap();
+    let chosen = fdt.chosen().unwrap();
+    // Changed where they were, added after.
+    assert_eq!(
+        names(chosen),
+        [
+            "stdout-path",
+            "bootargs",
+            "linux,initrd-start",
+            "linux,initrd-end"
+        ]
+    );
+    let property = |name| chosen.property(name).unwrap();
+    assert_eq!(property("bootargs").as_str(), Some("console=ttyS0"));
+    assert_eq!(property("linux,initrd-start").as_u64(), Some(0x84000000));
+    assert_eq!(property("linux,initrd-end").as_u64(), Some(0x84100000));
+    assert_eq!(property("stdout-path").as_str(), Some("serial0"));
+    // The rest is as it was.
+    assert_eq!(fdt.timebase_frequency(), Some(10000000));
+    assert!(fdt.memory().eq(Some(0x80000000..0x88000000)));
+}
+
+#[test]
+fn reserved_memory() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let mut editor = Editor::new(fdt);
+    let cells = [2u32.to_be_bytes(), 2u32.to_be_bytes()];
+    let reg: Vec<u8> = [0u32, 0x80000000, 0, 0x200000]
+        .iter()
+        .flat_map(|cell| cell.to_be_bytes())
+        .collect();
+    editor.add_node("/reserved-memory").unwrap();
+    editor
+        .set_property("/reserved-memory", "#address-cells", &cells[0])
+        .unwrap();
+    editor
+        .set_property("/reserved-memory", "#size-cells", &cells[1])
+        .unwrap();
+    editor
+        .set_property("/reserved-memory", "ranges", b"")
+        .unwrap();
+    editor
+        .add_node("/reserved-memory/chainload@80000000")
+        .unwrap();
+    editor
+        .set_property("/reserved-memory/chainload@80000000", "reg", &reg)
+        .unwrap();
+    editor
+        .set_property("/reserved-memory/chainload@80000000", "no-map", b"")
+        .unwrap();
+    editor.add_reservation(0x87f00000, 0x100000).unwrap();
+    let out = write(&editor);
+
+    let fdt = Fdt::new(&out).unwrap();
+    assert!(fdt.reservations().eq([(0x87f00000, 0x100000)]));
+    assert!(fdt
+        .reserved_memory()
+        .eq([0x87f00000..0x88000000, 0x80000000..0x80200000]));
+    let node = fdt.find("/reserved-memory/chainload@80000000").unwrap();
+    assert_eq!(names(node), ["reg", "no-map"]);
+    assert_eq!(
+        fdt.root().children().last().unwrap().name(),
+        "reserved-memory"
+    );
+}
+
+#[test]
+fn existing_reserved_memory() {
+    let fdt = Fdt::new(VISIONFIVE2).unwrap();
+    let mut editor = Editor::new(fdt);
+    let reg: Vec<u8> = [0u32, 0x40000000, 0, 0x200000]
+        .iter()
+        .flat_map(|cell| cell.to_be_bytes())
+        .collect();
+    // Already there, so only its child is added.
+    editor.add_node("/reserved-memory").unwrap();
+    editor
+        .add_node("/reserved-memory/chainload@40000000")
+        .unwrap();
+    editor
+        .set_property("/reserved-memory/chainload@40000000", "reg", &reg)
+        .unwrap();
+    let out = write(&editor);
+
+    let fdt = Fdt::new(&out).unwrap();
+    assert!(fdt.reservations().eq([(0x40000000, 0x80000)]));
+    let children: Vec<_> = fdt
+        .find("/reserved-memory")
+        .unwrap()
+        .children()
+        .map(|node| node.name())
+        .collect();
+    assert_eq!(children, ["e24@6ce00000", "chainload@40000000"]);
+    assert!(fdt.reserved_memory().eq([
+        0x40000000..0x40080000,
+        0x6ce00000..0x6e400000,
+        0x40000000..0x40200000
+    ]));
+}
+
+/// Properties added to a node with children go before them, as the format requires.
+#[test]
+fn before_children() {
+    let fdt = Fdt::new(VISIONFIVE2).unwrap();
+    let mut editor = Editor::new(fdt);
+    let clock = 1500000000u32.to_be_bytes();
+    editor.set_property("/", "serial-number", b"VF2\0").unwrap();
+    editor
+        .set_property("/cpus/cpu@0", "status", b"okay\0")
+        .unwrap();
+    editor
+        .set_property("/cpus/cpu@0", "clock-frequency", &clock)
+        .unwrap();
+    let out = write(&editor);
+
+    let fdt = Fdt::new(&out).unwrap();
+    assert_eq!(
+        fdt.root().property("serial-number").unwrap().as_str(),
+        Some("VF2")
+    );
+    let cpu = fdt.cpus().next().unwrap();
+    assert!(cpu.is_enabled());
+    assert_eq!(
+        cpu.node.property("clock-frequency").unwrap().as_u32(),
+        Some(1500000000)
+    );
+    assert_eq!(cpu.node.children().count(), 1);
+}
+
+#[test]
+fn errors() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    let mut editor = Editor::new(fdt);
+    assert_eq!(
+        editor.set_property("/nowhere", "a", b""),
+        Err(FdtError::NotFound)
+    );
+    // Names are matched exactly.
+    assert_eq!(
+        editor.set_property("/memory", "a", b""),
+        Err(FdtError::NotFound)
+    );
+    assert_eq!(
+        editor.set_property("chosen", "a", b""),
+        Err(FdtError::NotFound)
+    );
+    assert_eq!(editor.add_node("/a/b"), Err(FdtError::NotFound));
+
+    assert_eq!(editor.set_property("/chosen", "bootargs", b"a\0"), Ok(()));
+    let mut out = [0; 64];
+    assert_eq!(editor.write(&mut out), Err(FdtError::Full));
+
+    // Setting the same property again replaces the edit rather than taking another.
+    for _ in 0..32 {
+        editor.set_property("/chosen", "bootargs", b"b\0").unwrap();
+    }
+    let names = [
+        "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o",
+    ];
+    for name in names {
+        editor.set_property("/chosen", name, b"").unwrap();
+    }
+    assert_eq!(
+        editor.set_property("/chosen", "p", b""),
+        Err(FdtError::TooManyEdits)
+    );
+    let out = write(&editor);
+    let fdt = Fdt::new(&out).unwrap();
+    let chosen = fdt.chosen().unwrap();
+    assert_eq!(chosen.property("bootargs").unwrap().as_str(), Some("b"));
+    assert_eq!(chosen.properties().count(), 2 + 1 + names.len());
+}
@@ -0,0 +1,123 @@
+//! Reading the trees in `dtbs`. These are built from the `.dts` files next to them, which stand
+//! in for the trees `dtbs/dump.sh` dumps from QEMU and copies from a Linux build.
+
+use fdt::{Fdt, FdtError};
+
+const QEMU_VIRT: &[u8] = include_bytes!("dtbs/qemu-virt.dtb");
+const VISIONFIVE2: &[u8] = include_bytes!("dtbs/jh7110-starfive-visionfive-2-v1.3b.dtb");
+
+#[test]
+fn qemu_virt() {
+    let fdt = Fdt::new(QEMU_VIRT).unwrap();
+    assert_eq!(Fdt::total_size(QEMU_VIRT), Ok(QEMU_VIRT.len()));
+    assert_eq!(fdt.boot_cpuid(), 0);
+    assert_eq!(fdt.reservations().count(), 0);
+    assert!(fdt.memory().eq(Some(0x80000000..0x88000000)));
+    assert_eq!(fdt.reserved_memory().count(), 0);
+    assert_eq!(fdt.timebase_frequency(), Some(10000000));
+
+    let cpus: Vec<_> = fdt.cpus().map(|cpu| (cpu.id, cpu.is_enabled())).collect();
+    assert_eq!(cpus, [(0, true)]);
+
+    let (uart, options) = fdt.stdout().unwrap();
+    assert_eq!(uart.name(), "serial@10000000");
+    assert_eq!(options, None);
+    assert!(uart.is_compatible("ns16550a"));
+    assert!(uart.reg().eq([(0x10000000, 0x100)]));
+    let clock = uart.property("clock-frequency").unwrap();
+    assert_eq!(clock.as_u32(), Some(3686400));
+    assert!(uart.property("reg-shift").is_none());
+}
+
+#[test]
+fn visionfive2() {
+    let fdt = Fdt::new(VISIONFIVE2).unwrap();
+    assert!(fdt.reservations().eq([(0x40000000, 0x80000)]));
+    assert!(fdt.memory().eq(Some(0x40000000..0x240000000)));
+    assert!(fdt
+        .reserved_memory()
+        .eq([0x40000000..0x40080000, 0x6ce00000..0x6e400000]));
+    assert_eq!(fdt.timebase_frequency(), Some(4000000));
+
+    let cpus: Vec<_> = fdt.cpus().map(|cpu| (cpu.id, cpu.is_enabled())).collect();
+    assert_eq!(
+        cpus,
+        [(0, false), (1, true), (2, true), (3, true), (4, true)]
+    );
+    let s7 = fdt.cpus().next().unwrap().node;
+    assert!(s7.compatible().eq(["sifive,s7", "riscv"]));
+
+    // Through the serial0 alias.
+    let (uart, options) = fdt.stdout().unwrap();
+    assert_eq!(uart.name(), "serial@10000000");
+    assert_eq!(options, Some("115200n8"));
+    assert!(uart.is_enabled());
+    assert!(uart.reg().eq([(0x10000000, 0x10000)]));
+    let property = |name| uart.property(name).and_then(|p| p.as_u32());
+    assert_eq!(property("clock-frequency"), Some(24000000));
+    assert_eq!(property("reg-shift"), Some(2));
+    assert_eq!(property("reg-io-width"), Some(4));
+}
+
+#[test]
+fn find() {
+    let fdt = Fdt::new(VISIONFIVE2).unwrap();
+    assert_eq!(fdt.find("/").unwrap().name(), "");
+    assert_eq!(fdt.find("/cpus/cpu@3").unwrap().name(), "cpu@3");
+    // Without the unit address, the first match.
+    assert_eq!(fdt.find("/memory").unwrap().name(), "memory@40000000");
+    assert_eq!(fdt.find("/soc/serial").unwrap().name(), "serial@10000000");
+    assert_eq!(fdt.find("serial3").unwrap().name(), "serial@12000000");
+    assert!(!fdt.find("serial3").unwrap().is_enabled());
+    assert_eq!(
+        fdt.find("/cpus/cpu@1/interrupt-controller")
+            .unwrap()
+            .property("compatible")
+            .unwrap()
+            .as_str(),
+        Some("riscv,cpu-intc")
+    );
+    assert!(fdt.find("/soc/serial@11000000").is_none());
+    assert!(fdt.find("serial1").is_none());
+
+    let names: Vec<_> = fdt.root().children().map(|node| node.name()).collect();
+    assert_eq!(
+        names,
+        [
+            "aliases",
+            "chosen",
+            "cpus",
+            "memory@40000000",
+            "reserved-memory",
+            "soc"
+        ]
+    );
+    let intc = fdt.find("/cpus/cpu@0/interrupt-controller").unwrap();
+    assert_eq!(intc.property("interrupt-controller").unwrap().value, b"");
+}
+
+#[test]
+fn invalid() {
+    assert_eq!(Fdt::new(b"").err(), Some(FdtError::Invalid));
+    assert_eq!(Fdt::new(&QEMU_VIRT[4..]).err(), Some(FdtError::Invalid));
+    assert_eq!(
+        Fdt::new(&QEMU_VIRT[..QEMU_VIRT.len() - 1]).err(),
+        Some(FdtError::Invalid)
+    );
+
+    let mut blob = QEMU_VIRT.to_vec();
+    blob[20..24].copy_from_slice(&16u32.to_be_bytes());
+    assert_eq!(Fdt::new(&blob).err(), Some(FdtError::Unsupported));
+
+    // Cut the structure block short of the root's FDT_END_NODE.
+    let mut blob = QEMU_VIRT.to_vec();
+    let struct_len = u32::from_be_bytes(blob[36..40].try_into().unwrap());
+    blob[36..40].copy_from_slice(&(struct_len - 8).to_be_bytes());
+    assert_eq!(Fdt::new(&blob).err(), Some(FdtError::Invalid));
+
+    // A property name outside of the strings block.
+    let mut blob = QEMU_VIRT.to_vec();
+    let strings_len = u32::from_be_bytes(blob[32..36].try_into().unwrap());
+    blob[32..36].copy_from_slice(&(strings_len / 2).to_be_bytes());
+    assert_eq!(Fdt::new(&blob).err(), Some(FdtError::Invalid));
+}
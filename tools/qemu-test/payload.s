@@ -0,0 +1,31 @@
+// The payload sent by qemu-test, linked at the qemu-virt load address. It prints a line, which
+// qemu-test looks for, and passes through the SiFive test device, which exits QEMU.
+
+#define UART_BASE       0x10000000
+#define UART_LSR        5
+#define LSR_THRE        0x20
+#define TEST_DEVICE     0x100000
+#define TEST_PASS       0x5555
+
+.section .text,"ax",@progbits
+.global _start
+_start:
+        li              t0, UART_BASE
+        lla             t1, message
+1:      lbu             t2, (t1)
+        beqz            t2, 3f
+2:      lbu             t3, UART_LSR(t0)
+        andi            t3, t3, LSR_THRE
+        beqz            t3, 2b
+        sb              t2, (t0)
+        addi            t1, t1, 1
+        j               1b
+3:      li              t0, TEST_DEVICE
+        li              t1, TEST_PASS
+        sw              t1, (t0)
+4:      wfi
+        j               4b
+
+// Kept in .text so the flat binary is a single section.
+message:
+        .asciz          "payload running\r\n"
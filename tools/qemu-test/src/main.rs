@@ -0,0 +1,222 @@
+//! Boots chainload on QEMU's `virt` machine, sends it a payload with ZMODEM and checks that the
+//! payload runs.
+//!
+//! ```text
+//! qemu-test CHAINLOAD PAYLOAD
+//! ```
+//!
+//! `CHAINLOAD` is the flat binary built for the `qemu-virt` platform, booted with `-bios`, and
+//! `PAYLOAD` is built from `payload.s`; `make qemu-test` in `chainload` builds both and runs
+//! this. The console is on a pty, and QEMU starts paused until it's opened, so that nothing is
+//! lost. The payload passes by printing `PAYLOAD_MESSAGE` and exiting QEMU through the test
+//! device with status 0. Set `QEMU` to use another `qemu-system-riscv64`.
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    process::{Child, ChildStdin, Command, ExitCode, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+use zmodem::SerialDevice;
+
+const USAGE: &str = "usage: qemu-test CHAINLOAD PAYLOAD";
+
+/// Printed by chainload once it's waiting for an image.
+const BANNER: &[u8] = b"waiting for an image";
+const PAYLOAD_MESSAGE: &[u8] = b"payload running";
+
+/// How long QEMU has to start and chainload to print its banner.
+const BOOT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the payload has to run once sent, including chainload's delay before jumping to it.
+const RUN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Killed when dropped, so QEMU doesn't outlive a failed test.
+struct Qemu(Child);
+
+impl Qemu {
+    fn spawn(chainload: &str) -> Result<Qemu, String> {
+        let qemu = env::var("QEMU").unwrap_or_else(|_| "qemu-system-riscv64".into());
+        let child = Command::new(&qemu)
+            .args(["-machine", "virt", "-display", "none", "-S"])
+            .args(["-monitor", "stdio", "-serial", "pty", "-bios", chainload])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("{qemu}: {e}"))?;
+        Ok(Qemu(child))
+    }
+
+    /// Find the pty QEMU reports for the serial port, forwarding its other output to ours.
+    fn pty(&mut self, deadline: Instant) -> Result<String, String> {
+        let (tx, rx) = mpsc::channel();
+        forward_lines(self.0.stdout.take().unwrap(), tx.clone());
+        forward_lines(self.0.stderr.take().unwrap(), tx);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = rx
+                .recv_timeout(timeout)
+                .map_err(|_| "qemu didn't report a pty")?;
+            match line.split_once("char device redirected to ") {
+                Some((_, rest)) => return Ok(rest.split_whitespace().next().unwrap_or("").into()),
+                None => eprintln!("qemu: {line}"),
+            }
+        }
+    }
+
+    fn monitor(&mut self) -> &mut ChildStdin {
+        self.0.stdin.as_mut().unwrap()
+    }
+
+    fn wait(&mut self, deadline: Instant) -> Result<ExitStatus, String> {
+        loop {
+            if let Some(status) = self.0.try_wait().map_err(|e| format!("qemu: {e}"))? {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                return Err("qemu didn't exit".into());
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Send lines of QEMU's output to `tx`, or print them once it's gone. This keeps reading after
+/// the pty is found, so QEMU never blocks on a full pipe.
+fn forward_lines(output: impl Read + Send + 'static, tx: Sender<String>) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            if let Err(SendError(line)) = tx.send(line) {
+                eprintln!("qemu: {line}");
+            }
+        }
+    });
+}
+
+impl Drop for Qemu {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+/// The serial console, read by a thread so that `recv` can time out.
+struct Console {
+    pty: File,
+    rx: Receiver<u8>,
+    /// What was received outside of the transfer, which is also echoed to stdout.
+    log: Vec<u8>,
+}
+
+impl Console {
+    fn open(path: &str) -> Result<Console, String> {
+        // Raw and without echo, or the line discipline would mangle the transfer.
+        let status = Command::new("stty")
+            .args(["-F", path, "raw", "-echo"])
+            .status()
+            .map_err(|e| format!("stty: {e}"))?;
+        if !status.success() {
+            return Err(format!("stty: {status}"));
+        }
+        let pty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("{path}: {e}"))?;
+        let mut reader = pty.try_clone().map_err(|e| format!("{path}: {e}"))?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            // Reads fail once QEMU exits and closes the pty.
+            while let Ok(len @ 1..) = reader.read(&mut buf) {
+                if buf[..len].iter().any(|&byte| tx.send(byte).is_err()) {
+                    break;
+                }
+            }
+        });
+        Ok(Console {
+            pty,
+            rx,
+            log: Vec::new(),
+        })
+    }
+
+    /// Log what's received until it ends with `pattern`, or the deadline passes.
+    fn wait_for(&mut self, pattern: &[u8], deadline: Instant) -> bool {
+        while !self.log.ends_with(pattern) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let Ok(byte) = self.rx.recv_timeout(timeout) else {
+                return false;
+            };
+            self.log.push(byte);
+            io::stdout().write_all(&[byte]).ok();
+        }
+        io::stdout().flush().ok();
+        true
+    }
+}
+
+/// Borrowed, so the console can be read again once the transfer is over.
+impl SerialDevice for &mut Console {
+    type Error = io::Error;
+
+    fn send(&mut self, byte: u8) -> io::Result<()> {
+        self.pty.write_all(&[byte])
+    }
+
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(byte) => Ok(Some(byte)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [chainload, payload] = &args[..] else {
+        return Err(USAGE.into());
+    };
+    let payload = fs::read(payload).map_err(|e| format!("{payload}: {e}"))?;
+
+    let deadline = Instant::now() + BOOT_TIMEOUT;
+    let mut qemu = Qemu::spawn(chainload)?;
+    let pty = qemu.pty(deadline)?;
+    let mut console = Console::open(&pty)?;
+    qemu.monitor()
+        .write_all(b"cont\n")
+        .map_err(|e| format!("qemu: {e}"))?;
+    if !console.wait_for(BANNER, deadline) {
+        return Err("chainload didn't start".into());
+    }
+
+    zmodem::send(&mut console, "payload.bin", &payload)
+        .map_err(|e| format!("transfer failed: {e:?}"))?;
+
+    let deadline = Instant::now() + RUN_TIMEOUT;
+    if !console.wait_for(PAYLOAD_MESSAGE, deadline) {
+        return Err("the payload didn't run".into());
+    }
+    let status = qemu.wait(deadline)?;
+    if !status.success() {
+        return Err(format!("qemu exited with {status}"));
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => {
+            println!("\npass");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("\nfail: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
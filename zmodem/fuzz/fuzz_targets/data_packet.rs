@@ -0,0 +1,39 @@
+//! Data subpackets in arbitrary input, into buffers of any size up to the largest subpacket.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zmodem::{
+    proto::{FrameEncoding, PacketType},
+    recv::Receiver,
+    Error, MAX_SUBPACKET_LEN,
+};
+use zmodem_fuzz::{escape_mode, MemoryDevice};
+
+fuzz_target!(|data: &[u8]| {
+    let (escape, data) = escape_mode(data);
+    let Some((&[flags, len_lo, len_hi], data)) = data.split_first_chunk() else {
+        return;
+    };
+    let encoding = match flags & 1 {
+        0 => FrameEncoding::BIN16,
+        _ => FrameEncoding::BIN32,
+    };
+    let len = usize::from(u16::from_le_bytes([len_lo, len_hi])) % (MAX_SUBPACKET_LEN + 1);
+
+    let mut receiver = Receiver::new(MemoryDevice::new(data)).set_escape(escape);
+    let mut buf = vec![0; len];
+    loop {
+        match receiver.receive_data_packet(encoding, &mut buf) {
+            Ok((packet_type, packet)) => {
+                assert!(matches!(
+                    packet_type,
+                    PacketType::ZCRCE | PacketType::ZCRCG | PacketType::ZCRCQ | PacketType::ZCRCW
+                ));
+                assert!(packet.len() <= len);
+            }
+            Err(Error::Device(_)) => break,
+            Err(_) => continue,
+        }
+    }
+});
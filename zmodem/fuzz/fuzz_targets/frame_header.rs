@@ -0,0 +1,23 @@
+//! Headers in arbitrary input, with the receiver resynchronising after each error.
+
+#![no_main]
+
+use core::time::Duration;
+use libfuzzer_sys::fuzz_target;
+use zmodem::{proto::FrameEncoding, recv::Receiver, Error};
+use zmodem_fuzz::{escape_mode, MemoryDevice};
+
+fuzz_target!(|data: &[u8]| {
+    let (escape, data) = escape_mode(data);
+    let mut receiver = Receiver::new(MemoryDevice::new(data)).set_escape(escape);
+    loop {
+        match receiver.receive_frame_header(Duration::ZERO) {
+            Ok(frame) => assert!(matches!(
+                frame.encoding,
+                FrameEncoding::HEX | FrameEncoding::BIN16 | FrameEncoding::BIN32
+            )),
+            Err(Error::Device(_)) => break,
+            Err(_) => continue,
+        }
+    }
+});
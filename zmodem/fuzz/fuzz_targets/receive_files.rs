@@ -0,0 +1,21 @@
+//! A whole receiving session driven by arbitrary input, into a small buffer.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use zmodem::{recv::Receiver, sink::SliceSink};
+use zmodem_fuzz::{escape_mode, MemoryDevice};
+
+const OUTPUT_LEN: usize = 4096;
+
+fuzz_target!(|data: &[u8]| {
+    let (escape, data) = escape_mode(data);
+    let mut output = [0; OUTPUT_LEN];
+    let mut sink = SliceSink::new(&mut output);
+    let mut receiver = Receiver::new(MemoryDevice::new(data))
+        .set_escape(escape)
+        .set_verify(true);
+    // Any error is fine, as long as the session ends once the input does.
+    receiver.receive_files(&mut sink).ok();
+    assert!(sink.len() <= OUTPUT_LEN);
+});
@@ -0,0 +1,67 @@
+//! Shared pieces of the fuzz targets, which are run from `zmodem/` with
+//! `cargo +nightly fuzz run <target>`.
+
+use core::time::Duration;
+use zmodem::{proto::EscapeMode, SerialDevice};
+
+/// Reads that time out after the end of the input, before the device fails.
+const TIMEOUTS: usize = 3;
+
+/// The error returned once the input is exhausted.
+#[derive(Debug)]
+pub struct Eof;
+
+/// A serial device that receives the fuzzer's input, times out a few times, then fails.
+///
+/// The receiver waits for a sender indefinitely, so the failure is what ends the session. It
+/// must stop there, reading again panics.
+pub struct MemoryDevice<'a> {
+    input: &'a [u8],
+    timeouts: usize,
+}
+
+impl<'a> MemoryDevice<'a> {
+    pub fn new(input: &'a [u8]) -> MemoryDevice<'a> {
+        Self { input, timeouts: 0 }
+    }
+}
+
+impl SerialDevice for MemoryDevice<'_> {
+    type Error = Eof;
+
+    fn send(&mut self, _byte: u8) -> Result<(), Eof> {
+        Ok(())
+    }
+
+    fn recv(&mut self, _timeout: Duration) -> Result<Option<u8>, Eof> {
+        match self.input.split_first() {
+            Some((byte, rest)) => {
+                self.input = rest;
+                Ok(Some(*byte))
+            }
+            None => {
+                self.timeouts += 1;
+                assert!(self.timeouts <= TIMEOUTS + 1, "read after EOF");
+                match self.timeouts {
+                    ..=TIMEOUTS => Ok(None),
+                    _ => Err(Eof),
+                }
+            }
+        }
+    }
+}
+
+/// Split the escape mode to use from the start of `data`.
+pub fn escape_mode(data: &[u8]) -> (EscapeMode, &[u8]) {
+    match data.split_first() {
+        Some((byte, rest)) => {
+            let mode = match byte % 3 {
+                0 => EscapeMode::Clean,
+                1 => EscapeMode::Control,
+                _ => EscapeMode::EightBit,
+            };
+            (mode, rest)
+        }
+        None => (EscapeMode::Clean, data),
+    }
+}
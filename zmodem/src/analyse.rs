@@ -0,0 +1,333 @@
+//! Decoding captured traffic into a timeline of headers and subpackets.
+//!
+//! An [`Analyser`] follows the bytes sent in one direction, and turns them into [`Record`]s,
+//! which display as annotated lines such as `ZDATA BIN32 at 1024`. Anything that isn't part of a
+//! header or subpacket is reported as skipped, and data that doesn't continue where the previous
+//! subpacket ended as a gap.
+
+use crate::{
+    codec::{CodecError, HeaderDecoder, SubpacketDecoder},
+    proto::{
+        consts::*, CommandFlags, EscapeMode, FileOptions, FrameEncoding, FrameHeader, FrameType,
+        PacketType, TransmitterFlags,
+    },
+    MAX_SUBPACKET_LEN,
+};
+use core::fmt;
+use std::vec::Vec;
+
+/// Something found in the captured bytes, starting at `offset` in them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Record {
+    /// Bytes outside any header or subpacket, such as a shell prompt or line noise.
+    Skipped {
+        offset: usize,
+        len: usize,
+    },
+    Header {
+        offset: usize,
+        header: FrameHeader,
+    },
+    /// A header that couldn't be decoded.
+    InvalidHeader {
+        offset: usize,
+        error: CodecError,
+    },
+    /// A data subpacket with `len` bytes of data, at `position` in the file for ZDATA. Fails with
+    /// `Incomplete` if a header or the end of the capture came first.
+    Subpacket {
+        offset: usize,
+        len: usize,
+        position: Option<u32>,
+        result: Result<PacketType, CodecError>,
+    },
+    /// Data resumed at `position` rather than where the previous subpacket ended.
+    Gap {
+        offset: usize,
+        expected: u32,
+        position: u32,
+    },
+}
+
+impl Record {
+    pub fn offset(&self) -> usize {
+        match *self {
+            Record::Skipped { offset, .. }
+            | Record::Header { offset, .. }
+            | Record::InvalidHeader { offset, .. }
+            | Record::Subpacket { offset, .. }
+            | Record::Gap { offset, .. } => offset,
+        }
+    }
+}
+
+/// Writes the arguments of a header, as its type uses them.
+struct Arguments<'a>(&'a FrameHeader);
+
+impl fmt::Display for Arguments<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = self.0;
+        let [_, _, _, zf0] = header.data;
+        match header.r#type {
+            FrameType::ZRINIT => {
+                let (capabilities, buffer_len) = header.zrinit();
+                write!(f, "{capabilities:?} buffer {buffer_len}")
+            }
+            FrameType::ZSINIT => write!(f, "{:?}", TransmitterFlags::from_bits_retain(zf0)),
+            FrameType::ZFILE => {
+                let options = FileOptions::from_header(header);
+                write!(
+                    f,
+                    "{:?} {:?} {:?} {:?}",
+                    options.conversion, options.management, options.transport, options.extended
+                )
+            }
+            FrameType::ZCOMMAND => write!(f, "{:?}", CommandFlags::from_bits_retain(zf0)),
+            FrameType::ZRPOS
+            | FrameType::ZDATA
+            | FrameType::ZEOF
+            | FrameType::ZACK
+            | FrameType::ZCRC
+            | FrameType::ZFREECNT => write!(f, "at {}", header.count()),
+            _ => write!(f, "{:08x}", header.flags()),
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Record::Skipped { len, .. } => write!(f, "skipped {len} bytes"),
+            Record::Header { header, .. } => write!(
+                f,
+                "{:?} {:?} {}",
+                header.r#type,
+                header.encoding,
+                Arguments(header)
+            ),
+            Record::InvalidHeader { error, .. } => write!(f, "invalid header: {error:?}"),
+            Record::Subpacket {
+                len,
+                position,
+                result,
+                ..
+            } => {
+                match result {
+                    Ok(packet_type) => write!(f, "{packet_type:?} subpacket, {len} bytes")?,
+                    Err(error) => write!(f, "invalid subpacket after {len} bytes: {error:?}")?,
+                }
+                match position {
+                    Some(position) => write!(f, " at {position}"),
+                    None => Ok(()),
+                }
+            }
+            Record::Gap {
+                expected, position, ..
+            } => write!(f, "gap: data resumed at {position}, expected {expected}"),
+        }
+    }
+}
+
+/// Follows the bytes sent in one direction of a session.
+pub struct Analyser {
+    mode: EscapeMode,
+    // Offset of the next byte, and of the first one not part of a record yet.
+    offset: usize,
+    mark: usize,
+    header: HeaderDecoder,
+    // Where the ZPADs before the current header start.
+    pad: Option<usize>,
+    // Subpacket data decoded before them.
+    pad_len: usize,
+    // Set while the subpackets following a header are decoded.
+    subpacket: Option<SubpacketDecoder>,
+    buf: Vec<u8>,
+    // Position of the next ZDATA subpacket, and where the last one ended.
+    position: Option<u32>,
+    expected: Option<u32>,
+    // Bytes expected after the last record, such as the HEX trailer.
+    trailer: &'static [u8],
+}
+
+impl Analyser {
+    /// Analyse bytes escaped with `mode`, which must be the strictest the link might use.
+    pub fn new(mode: EscapeMode) -> Analyser {
+        Self {
+            mode,
+            offset: 0,
+            mark: 0,
+            header: HeaderDecoder::new(mode),
+            pad: None,
+            pad_len: 0,
+            subpacket: None,
+            buf: std::vec![0; MAX_SUBPACKET_LEN],
+            position: None,
+            expected: None,
+            trailer: &[],
+        }
+    }
+
+    /// Analyse the next captured bytes, returning what they completed.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Record> {
+        let mut records = Vec::new();
+        for byte in bytes {
+            self.push_byte(*byte, &mut records);
+            self.offset += 1;
+        }
+        records
+    }
+
+    /// Report anything left once the capture ends.
+    pub fn finish(&mut self) -> Vec<Record> {
+        let mut records = Vec::new();
+        if let Some(subpacket) = self.subpacket.take() {
+            if self.mark < self.offset {
+                records.push(Record::Subpacket {
+                    offset: self.mark,
+                    len: subpacket.len(),
+                    position: self.position,
+                    result: Err(CodecError::Incomplete),
+                });
+            }
+        } else if self.header.is_started() {
+            let start = self.pad.unwrap_or(self.mark);
+            let offset = self.skip_to(start, &mut records);
+            records.push(Record::InvalidHeader {
+                offset,
+                error: CodecError::Incomplete,
+            });
+        } else {
+            self.skip_to(self.offset, &mut records);
+        }
+        self.mark = self.offset;
+        records
+    }
+
+    /// Report the bytes from the mark to `offset` as skipped, returning `offset`.
+    fn skip_to(&mut self, offset: usize, records: &mut Vec<Record>) -> usize {
+        if offset > self.mark {
+            records.push(Record::Skipped {
+                offset: self.mark,
+                len: offset - self.mark,
+            });
+        }
+        self.mark = offset;
+        offset
+    }
+
+    fn push_byte(&mut self, byte: u8, records: &mut Vec<Record>) {
+        if let Some((expected, rest)) = self.trailer.split_first() {
+            if byte & 0x7f == *expected {
+                self.trailer = rest;
+                self.mark = self.offset + 1;
+                return;
+            }
+            self.trailer = &[];
+        }
+
+        // Headers are looked for within subpackets too, as a sender starts over with one after
+        // a ZRPOS without ending the subpacket. Their CRC keeps data from being taken for one.
+        if byte & 0x7f == ZPAD && !self.header.is_started() && self.pad.is_none() {
+            self.pad = Some(self.offset);
+            self.pad_len = self.subpacket.as_ref().map_or(0, SubpacketDecoder::len);
+        }
+        match self.header.push(byte) {
+            Ok(Some(header)) => return self.push_header(header, records),
+            Err(error) if self.subpacket.is_none() => {
+                let start = self.pad.take().unwrap_or(self.mark);
+                let offset = self.skip_to(start, records);
+                records.push(Record::InvalidHeader { offset, error });
+                self.mark = self.offset + 1;
+                return;
+            }
+            _ => {}
+        }
+        if !self.header.is_started() && byte & 0x7f != ZPAD {
+            self.pad = None;
+        }
+
+        let Some(subpacket) = &mut self.subpacket else {
+            return;
+        };
+        let len = subpacket.len();
+        let result = match subpacket.push(byte, &mut self.buf) {
+            Ok(None) => return,
+            Ok(Some((packet_type, _))) => Ok(packet_type),
+            Err(error) => Err(error),
+        };
+        records.push(Record::Subpacket {
+            offset: self.mark,
+            len,
+            position: self.position,
+            result,
+        });
+        self.mark = self.offset + 1;
+        self.header = HeaderDecoder::new(self.mode);
+        self.pad = None;
+        // The next subpacket starts after a bad CRC, otherwise nothing more can be made of the
+        // data until the next header.
+        if matches!(result, Ok(_) | Err(CodecError::InvalidCrc)) {
+            if let Some(position) = &mut self.position {
+                *position = position.wrapping_add(len as u32);
+                self.expected = Some(*position);
+            }
+        }
+        match result {
+            Ok(PacketType::ZCRCW) => {
+                self.trailer = &[XON];
+                self.subpacket = None;
+            }
+            Ok(PacketType::ZCRCE) => self.subpacket = None,
+            Ok(_) | Err(CodecError::InvalidCrc) => {}
+            Err(_) => self.subpacket = None,
+        }
+    }
+
+    fn push_header(&mut self, header: FrameHeader, records: &mut Vec<Record>) {
+        let start = self.pad.take().unwrap_or(self.mark);
+        if self.subpacket.take().is_some() && start > self.mark {
+            records.push(Record::Subpacket {
+                offset: self.mark,
+                len: self.pad_len,
+                position: self.position,
+                result: Err(CodecError::Incomplete),
+            });
+            self.mark = start;
+        }
+        let offset = self.skip_to(start, records);
+        self.mark = self.offset + 1;
+
+        let mut position = None;
+        match header.r#type {
+            FrameType::ZDATA => {
+                position = Some(header.count());
+                match self.expected {
+                    Some(expected) if expected != header.count() => records.push(Record::Gap {
+                        offset,
+                        expected,
+                        position: header.count(),
+                    }),
+                    _ => {}
+                }
+            }
+            FrameType::ZFILE => self.expected = None,
+            _ => {}
+        }
+        records.push(Record::Header { offset, header });
+
+        self.position = position;
+        self.subpacket = match header.r#type {
+            FrameType::ZDATA
+            | FrameType::ZFILE
+            | FrameType::ZSINIT
+            | FrameType::ZCOMMAND
+            | FrameType::ZSTDERR => Some(SubpacketDecoder::new(header.encoding, self.mode)),
+            _ => None,
+        };
+        self.trailer = match (header.encoding, header.r#type) {
+            (FrameEncoding::HEX, FrameType::ZACK | FrameType::ZFIN) => &[CR, LF],
+            (FrameEncoding::HEX, _) => &[CR, LF, XON],
+            _ => &[],
+        };
+    }
+}
@@ -0,0 +1,205 @@
+//! Decodes a captured session into an annotated timeline of headers and subpackets.
+//!
+//! ```text
+//! zmsniff [--escape clean|control|8bit] [--csv] A [B]
+//! zmsniff [--escape clean|control|8bit] --transcript FILE
+//! ```
+//!
+//! `A` and `B` hold the bytes sent in each direction, such as from a pty tee. They are raw, or
+//! with `--csv` lines of the time in seconds and the byte in decimal or `0x` hex, such as logic
+//! analyser exports; further columns and lines not starting with a time are ignored. Transcripts
+//! are read as written by `zmodem::transcript::Recorder`.
+
+use std::{env, fs, process::ExitCode, time::Duration};
+use zmodem::{
+    analyse::{Analyser, Record},
+    proto::EscapeMode,
+    transcript::{self, Entry},
+};
+
+const USAGE: &str = "usage: zmsniff [--escape clean|control|8bit] [--csv] A [B]
+       zmsniff [--escape clean|control|8bit] --transcript FILE";
+
+/// Most skipped bytes shown.
+const PREVIEW_LEN: usize = 32;
+
+/// A captured byte, or `None` for a timeout in a transcript, sent by `side`.
+struct Event {
+    time: Option<Duration>,
+    side: usize,
+    byte: Option<u8>,
+}
+
+struct Side {
+    name: String,
+    analyser: Analyser,
+    bytes: Vec<u8>,
+    times: Vec<Option<Duration>>,
+}
+
+impl Side {
+    fn print(&self, records: Vec<Record>) {
+        for record in records {
+            let offset = record.offset();
+            let time = format_time(self.times.get(offset).copied().flatten());
+            print!("{time} {:<4} {offset:>8}  {record}", self.name);
+            if let Record::Skipped { len, .. } = record {
+                let end = offset + len.min(PREVIEW_LEN);
+                print!(": \"{}\"", self.bytes[offset..end].escape_ascii());
+            }
+            println!();
+        }
+    }
+}
+
+fn format_time(time: Option<Duration>) -> String {
+    match time {
+        Some(time) => format!("{:>12.6}", time.as_secs_f64()),
+        None => " ".repeat(12),
+    }
+}
+
+fn parse_csv(path: &str, side: usize) -> Result<Vec<Event>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut events = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let mut fields = line.split(',').map(|field| field.trim().trim_matches('"'));
+        let Some(time) = fields.next().and_then(|time| time.parse::<f64>().ok()) else {
+            continue;
+        };
+        let value = fields.next().unwrap_or_default();
+        let byte = match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        };
+        let (Some(byte), Ok(time)) = (byte, Duration::try_from_secs_f64(time)) else {
+            return Err(format!("{path}:{}: invalid line", i + 1));
+        };
+        events.push(Event {
+            time: Some(time),
+            side,
+            byte: Some(byte),
+        });
+    }
+    Ok(events)
+}
+
+fn parse_raw(path: &str, side: usize) -> Result<Vec<Event>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    Ok(bytes
+        .into_iter()
+        .map(|byte| Event {
+            time: None,
+            side,
+            byte: Some(byte),
+        })
+        .collect())
+}
+
+fn parse_transcript(path: &str) -> Result<Vec<Event>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let entries = transcript::parse(&text).map_err(|e| format!("{path}: {e}"))?;
+    let mut events = Vec::new();
+    for entry in entries {
+        let (time, side, bytes) = match entry {
+            Entry::Sent(time, bytes) => (time, 0, bytes),
+            Entry::Received(time, bytes) => (time, 1, bytes),
+            Entry::Timeout(time) => (time, 0, Vec::new()),
+        };
+        if bytes.is_empty() {
+            events.push(Event {
+                time: Some(time),
+                side,
+                byte: None,
+            });
+        }
+        events.extend(bytes.into_iter().map(|byte| Event {
+            time: Some(time),
+            side,
+            byte: Some(byte),
+        }));
+    }
+    Ok(events)
+}
+
+fn run() -> Result<(), String> {
+    let mut mode = EscapeMode::Clean;
+    let mut csv = false;
+    let mut transcript = None;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--escape" => {
+                mode = match args.next().as_deref() {
+                    Some("clean") => EscapeMode::Clean,
+                    Some("control") => EscapeMode::Control,
+                    Some("8bit") => EscapeMode::EightBit,
+                    _ => return Err(USAGE.into()),
+                }
+            }
+            "--csv" => csv = true,
+            "--transcript" => transcript = Some(args.next().ok_or(USAGE)?),
+            _ if arg.starts_with('-') => return Err(USAGE.into()),
+            _ => paths.push(arg),
+        }
+    }
+
+    let (names, mut events) = match (transcript, paths.len()) {
+        (Some(path), 0) if !csv => (vec!["tx", "rx"], parse_transcript(&path)?),
+        (None, 1 | 2) => {
+            let mut events = Vec::new();
+            for (side, path) in paths.iter().enumerate() {
+                events.extend(match csv {
+                    true => parse_csv(path, side)?,
+                    false => parse_raw(path, side)?,
+                });
+            }
+            (vec!["A", "B"], events)
+        }
+        _ => return Err(USAGE.into()),
+    };
+    // Stable, so raw captures without times are shown one direction after the other.
+    events.sort_by_key(|event| event.time);
+
+    let mut sides: Vec<Side> = names
+        .into_iter()
+        .map(|name| Side {
+            name: name.into(),
+            analyser: Analyser::new(mode),
+            bytes: Vec::new(),
+            times: Vec::new(),
+        })
+        .collect();
+    for event in events {
+        let side = &mut sides[event.side];
+        let Some(byte) = event.byte else {
+            println!(
+                "{} {:<4} {:>8}  timeout",
+                format_time(event.time),
+                side.name,
+                ""
+            );
+            continue;
+        };
+        side.bytes.push(byte);
+        side.times.push(event.time);
+        let records = side.analyser.push(&[byte]);
+        side.print(records);
+    }
+    for side in &mut sides {
+        let records = side.analyser.finish();
+        side.print(records);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Stopping sessions from outside, such as from a Cancel button or a key press.
+
+use crate::{proto::consts::CAN, SerialDevice};
+use core::time::Duration;
+
+/// Backspace, to erase the CANs if the other end is a terminal.
+const BS: u8 = 0x08;
+
+/// Sent when a session is cancelled: CAN repeated, which both ZMODEM and XMODEM take as an
+/// abort, then as many backspaces.
+pub const ABORT_SEQUENCE: [u8; 20] = [
+    CAN, CAN, CAN, CAN, CAN, CAN, CAN, CAN, CAN, CAN, BS, BS, BS, BS, BS, BS, BS, BS, BS, BS,
+];
+
+/// Longest wait before checking for cancellation again.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A [`SerialDevice`] that cancels the session once `cancelled` returns true.
+///
+/// `cancelled` is called before every byte, and at least every 100 ms while waiting for one.
+pub struct Cancellable<D, F> {
+    dev: D,
+    cancelled: F,
+}
+
+impl<D, F: FnMut() -> bool> Cancellable<D, F> {
+    pub fn new(dev: D, cancelled: F) -> Cancellable<D, F> {
+        Self { dev, cancelled }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.dev
+    }
+}
+
+impl<D: SerialDevice, F: FnMut() -> bool> SerialDevice for Cancellable<D, F> {
+    type Error = D::Error;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.dev.send(byte)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        let mut remaining = timeout;
+        loop {
+            let wait = remaining.min(POLL_INTERVAL);
+            if let Some(byte) = self.dev.recv(wait)? {
+                return Ok(Some(byte));
+            }
+            remaining -= wait;
+            if remaining.is_zero() || self.is_cancelled() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn is_cancelled(&mut self) -> bool {
+        (self.cancelled)() || self.dev.is_cancelled()
+    }
+}
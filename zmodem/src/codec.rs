@@ -0,0 +1,598 @@
+//! Encoding and decoding of headers and data subpackets, without a device.
+//!
+//! The encoders write to slices or return iterators, and the decoders take one byte at a time,
+//! with functions to decode from slices built on them. Sessions use the same code, so frames
+//! built here are exactly what a `Sender` or `Receiver` would send.
+
+use crate::{
+    crc16, crc32, from_hex,
+    proto::{consts::*, EscapeMode, FrameEncoding, FrameHeader, FrameType, PacketType},
+    to_hex, Error,
+};
+use core::{array, iter, option, slice};
+
+type Bytes<'a> = iter::Copied<slice::Iter<'a, u8>>;
+
+/// Longest encoding of a single byte: ZDLE ZHIGH for the 8th bit, then the escaped low bits.
+const MAX_ESCAPED_LEN: usize = 4;
+
+/// Longest encoded header, a BIN32 header with every byte escaped.
+pub const MAX_HEADER_LEN: usize = 3 + (1 + 4 + 4) * MAX_ESCAPED_LEN;
+
+/// Longest encoded subpacket holding `len` bytes.
+pub const fn max_encoded_subpacket_len(len: usize) -> usize {
+    (len + 4) * MAX_ESCAPED_LEN + 3
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CodecError {
+    /// The input ends before the header or subpacket does.
+    Incomplete,
+    /// The output is too small, for encoding, or for the data of a subpacket being decoded.
+    BufferTooSmall,
+    InvalidFrameEncoding(FrameEncoding),
+    InvalidHex(u8),
+    InvalidEscape(u8),
+    InvalidCrc,
+}
+
+impl<D> From<CodecError> for Error<D> {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::Incomplete => Error::TimedOut,
+            CodecError::BufferTooSmall => Error::SubpacketTooLong,
+            CodecError::InvalidFrameEncoding(encoding) => Error::InvalidFrameEncoding(encoding),
+            CodecError::InvalidHex(byte) => Error::InvalidHex(byte),
+            CodecError::InvalidEscape(byte) => Error::InvalidEscape(byte),
+            CodecError::InvalidCrc => Error::InvalidCrc,
+        }
+    }
+}
+
+/// Escape a byte, returning the bytes to send and how many there are.
+fn escape_byte(byte: u8, mode: EscapeMode) -> ([u8; MAX_ESCAPED_LEN], usize) {
+    let mut bytes = [0; MAX_ESCAPED_LEN];
+    let mut len = 0;
+    let mut byte = byte;
+    if mode == EscapeMode::EightBit && byte & 0x80 != 0 {
+        bytes[..2].copy_from_slice(&[ZDLE, ZHIGH]);
+        len = 2;
+        byte &= 0x7f;
+    }
+    if !mode.needs_escape(byte) {
+        bytes[len] = byte;
+        return (bytes, len + 1);
+    }
+    bytes[len] = ZDLE;
+    bytes[len + 1] = match byte {
+        0x7f => ZRUB0,
+        0xff => ZRUB1,
+        _ => byte ^ 0x40,
+    };
+    (bytes, len + 2)
+}
+
+/// Iterator over the escaped form of bytes, see [`escape`].
+#[derive(Clone, Debug)]
+pub struct Escape<I> {
+    bytes: I,
+    mode: EscapeMode,
+    pending: [u8; MAX_ESCAPED_LEN],
+    pos: usize,
+    len: usize,
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Escape<I> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.len {
+            (self.pending, self.len) = escape_byte(self.bytes.next()?, self.mode);
+            self.pos = 0;
+        }
+        self.pos += 1;
+        Some(self.pending[self.pos - 1])
+    }
+}
+
+fn escape_iter<I: Iterator<Item = u8>>(bytes: I, mode: EscapeMode) -> Escape<I> {
+    Escape {
+        bytes,
+        mode,
+        pending: [0; MAX_ESCAPED_LEN],
+        pos: 0,
+        len: 0,
+    }
+}
+
+/// Escape `data` with ZDLE, as in binary headers and data subpackets.
+pub fn escape(data: &[u8], mode: EscapeMode) -> Escape<Bytes<'_>> {
+    escape_iter(data.iter().copied(), mode)
+}
+
+/// Drop what the link may have added: the 8th bit of 7-bit links, and control characters,
+/// which are always escaped unless `mode` is `Clean`.
+fn filter(byte: u8, mode: EscapeMode) -> Option<u8> {
+    let byte = match mode {
+        EscapeMode::EightBit => byte & 0x7f,
+        _ => byte,
+    };
+    if mode != EscapeMode::Clean && byte & 0x60 == 0 && byte != ZDLE {
+        return None;
+    }
+    Some(byte)
+}
+
+/// A byte of a binary header or data subpacket, after unescaping.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unescaped {
+    Byte(u8),
+    /// The end of a data subpacket.
+    End(PacketType),
+}
+
+/// Unescapes bytes one at a time.
+#[derive(Clone, Debug)]
+pub struct Unescaper {
+    mode: EscapeMode,
+    zdle: bool,
+    high: u8,
+}
+
+impl Unescaper {
+    pub fn new(mode: EscapeMode) -> Unescaper {
+        Self {
+            mode,
+            zdle: false,
+            high: 0,
+        }
+    }
+
+    /// Whether the bytes pushed so far end in the middle of an escape sequence.
+    pub fn is_pending(&self) -> bool {
+        self.zdle || self.high != 0
+    }
+
+    /// Unescape the next received byte, returning `None` if more are needed.
+    pub fn push(&mut self, byte: u8) -> Result<Option<Unescaped>, CodecError> {
+        let Some(byte) = filter(byte, self.mode) else {
+            return Ok(None);
+        };
+        if !self.zdle {
+            if byte == ZDLE {
+                self.zdle = true;
+                return Ok(None);
+            }
+            return Ok(Some(Unescaped::Byte(
+                byte | core::mem::take(&mut self.high),
+            )));
+        }
+        self.zdle = false;
+        let byte = match byte {
+            byte if byte & 0x60 == 0x40 => byte ^ 0x40,
+            ZRUB0 => 0x7f,
+            ZRUB1 => 0xff,
+            ZHIGH if self.high == 0 => {
+                self.high = 0x80;
+                return Ok(None);
+            }
+            ZCRCE | ZCRCG | ZCRCQ | ZCRCW if self.high == 0 => {
+                return Ok(Some(Unescaped::End(PacketType(byte))));
+            }
+            byte => {
+                self.high = 0;
+                return Err(CodecError::InvalidEscape(byte));
+            }
+        };
+        Ok(Some(Unescaped::Byte(
+            byte | core::mem::take(&mut self.high),
+        )))
+    }
+}
+
+/// Iterator over the unescaped form of bytes, see [`unescape`].
+#[derive(Clone, Debug)]
+pub struct Unescape<'a> {
+    input: slice::Iter<'a, u8>,
+    unescaper: Unescaper,
+}
+
+impl Iterator for Unescape<'_> {
+    type Item = Result<Unescaped, CodecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for byte in self.input.by_ref() {
+            match self.unescaper.push(*byte) {
+                Ok(None) => continue,
+                Ok(Some(unescaped)) => return Some(Ok(unescaped)),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        if !self.unescaper.is_pending() {
+            return None;
+        }
+        self.unescaper = Unescaper::new(self.unescaper.mode);
+        Some(Err(CodecError::Incomplete))
+    }
+}
+
+/// Unescape `input`, which may contain the ends of data subpackets.
+pub fn unescape(input: &[u8], mode: EscapeMode) -> Unescape<'_> {
+    Unescape {
+        input: input.iter(),
+        unescaper: Unescaper::new(mode),
+    }
+}
+
+/// Appends to a slice.
+struct Writer<'a> {
+    output: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn write(&mut self, bytes: impl IntoIterator<Item = u8>) -> Result<(), CodecError> {
+        for byte in bytes {
+            *self
+                .output
+                .get_mut(self.len)
+                .ok_or(CodecError::BufferTooSmall)? = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Encode `header` to the start of `output`, returning the encoded length.
+///
+/// HEX headers include their CR LF trailer, and the XON following all but ZACK and ZFIN.
+/// [`MAX_HEADER_LEN`] bytes are always enough.
+pub fn encode_header(
+    header: &FrameHeader,
+    mode: EscapeMode,
+    output: &mut [u8],
+) -> Result<usize, CodecError> {
+    let mut writer = Writer { output, len: 0 };
+    let buf = bytemuck::bytes_of(header);
+    match header.encoding {
+        FrameEncoding::HEX => {
+            writer.write([ZPAD, ZPAD, ZDLE, buf[0]])?;
+            let crc = crc16(&buf[1..], None).to_be_bytes();
+            for byte in buf[1..].iter().chain(&crc) {
+                writer.write(to_hex(*byte))?;
+            }
+            // The LF has its 8th bit set, unless the link may not carry it.
+            match mode {
+                EscapeMode::EightBit => writer.write([CR, LF])?,
+                _ => writer.write([CR, 0x80 | LF])?,
+            }
+            if !matches!(header.r#type, FrameType::ZACK | FrameType::ZFIN) {
+                writer.write([XON])?;
+            }
+        }
+        FrameEncoding::BIN16 => {
+            writer.write([ZPAD, ZDLE, buf[0]])?;
+            let crc = crc16(&buf[1..], None).to_be_bytes();
+            writer.write(escape_iter(buf[1..].iter().chain(&crc).copied(), mode))?;
+        }
+        FrameEncoding::BIN32 => {
+            writer.write([ZPAD, ZDLE, buf[0]])?;
+            let crc = crc32(&buf[1..], None).to_le_bytes();
+            writer.write(escape_iter(buf[1..].iter().chain(&crc).copied(), mode))?;
+        }
+        encoding => return Err(CodecError::InvalidFrameEncoding(encoding)),
+    }
+    Ok(writer.len)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum HeaderState {
+    /// Looking for ZPAD.
+    Idle,
+    /// After one or more ZPAD, looking for ZDLE.
+    Pad,
+    /// After ZPAD ZDLE, before the encoding.
+    Start,
+    /// Receiving the type, data and CRC.
+    Body,
+}
+
+/// Decodes headers one byte at a time, skipping anything before them.
+#[derive(Clone, Debug)]
+pub struct HeaderDecoder {
+    mode: EscapeMode,
+    state: HeaderState,
+    encoding: FrameEncoding,
+    // Type, data and CRC.
+    bytes: [u8; 9],
+    len: usize,
+    unescaper: Unescaper,
+    // First digit of a HEX byte.
+    hex: Option<u8>,
+}
+
+impl HeaderDecoder {
+    pub fn new(mode: EscapeMode) -> HeaderDecoder {
+        Self {
+            mode,
+            state: HeaderState::Idle,
+            encoding: FrameEncoding::HEX,
+            bytes: [0; 9],
+            len: 0,
+            unescaper: Unescaper::new(mode),
+            hex: None,
+        }
+    }
+
+    /// Whether the start of a header, ZPAD ZDLE, has been received.
+    pub fn is_started(&self) -> bool {
+        matches!(self.state, HeaderState::Start | HeaderState::Body)
+    }
+
+    fn body_len(&self) -> usize {
+        match self.encoding {
+            FrameEncoding::BIN32 => 9,
+            _ => 7,
+        }
+    }
+
+    /// Decode the next received byte, returning the header once it is complete.
+    ///
+    /// The HEX trailer isn't part of the header, it is skipped with anything else before the
+    /// next one. After an error, the decoder looks for the next header.
+    pub fn push(&mut self, byte: u8) -> Result<Option<FrameHeader>, CodecError> {
+        let result = self.decode(byte);
+        if !matches!(result, Ok(None)) {
+            self.state = HeaderState::Idle;
+        }
+        result
+    }
+
+    fn decode(&mut self, byte: u8) -> Result<Option<FrameHeader>, CodecError> {
+        let body = match self.state {
+            HeaderState::Body => byte,
+            _ => {
+                let Some(byte) = filter(byte, self.mode) else {
+                    return Ok(None);
+                };
+                self.state = match (self.state, byte) {
+                    (HeaderState::Idle | HeaderState::Pad, ZPAD) => HeaderState::Pad,
+                    (HeaderState::Pad, ZDLE) => HeaderState::Start,
+                    (HeaderState::Start, _) => {
+                        self.encoding = FrameEncoding(byte);
+                        if !matches!(
+                            self.encoding,
+                            FrameEncoding::HEX | FrameEncoding::BIN16 | FrameEncoding::BIN32
+                        ) {
+                            return Err(CodecError::InvalidFrameEncoding(self.encoding));
+                        }
+                        self.len = 0;
+                        self.hex = None;
+                        self.unescaper = Unescaper::new(self.mode);
+                        HeaderState::Body
+                    }
+                    _ => HeaderState::Idle,
+                };
+                return Ok(None);
+            }
+        };
+
+        let byte = if self.encoding == FrameEncoding::HEX {
+            let Some(byte) = filter(body, self.mode) else {
+                return Ok(None);
+            };
+            // Hex digits ignore parity.
+            let byte = byte & 0x7f;
+            match self.hex.take() {
+                None => {
+                    self.hex = Some(byte);
+                    return Ok(None);
+                }
+                Some(hi) => {
+                    from_hex([hi, byte]).map_err(|error| CodecError::InvalidHex(error.0))?
+                }
+            }
+        } else {
+            match self.unescaper.push(body)? {
+                None => return Ok(None),
+                Some(Unescaped::Byte(byte)) => byte,
+                Some(Unescaped::End(packet_type)) => {
+                    return Err(CodecError::InvalidEscape(packet_type.0))
+                }
+            }
+        };
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.body_len() {
+            return Ok(None);
+        }
+
+        let header = FrameHeader {
+            encoding: self.encoding,
+            r#type: FrameType(self.bytes[0]),
+            data: [self.bytes[1], self.bytes[2], self.bytes[3], self.bytes[4]],
+        };
+        let valid = match self.encoding {
+            FrameEncoding::BIN32 => crc32(&self.bytes[..5], None).to_le_bytes() == self.bytes[5..9],
+            _ => crc16(&self.bytes[..5], None).to_be_bytes() == self.bytes[5..7],
+        };
+        if !valid {
+            return Err(CodecError::InvalidCrc);
+        }
+        Ok(Some(header))
+    }
+}
+
+/// Decode the first header in `input`, returning it with the number of bytes it took up to its
+/// end, including a HEX header's trailer.
+pub fn decode_header(input: &[u8], mode: EscapeMode) -> Result<(FrameHeader, usize), CodecError> {
+    let mut decoder = HeaderDecoder::new(mode);
+    for (i, byte) in input.iter().enumerate() {
+        let Some(header) = decoder.push(*byte)? else {
+            continue;
+        };
+        let mut len = i + 1;
+        if header.encoding == FrameEncoding::HEX {
+            let trailer: &[u8] = match header.r#type {
+                FrameType::ZACK | FrameType::ZFIN => &[CR, LF],
+                _ => &[CR, LF, XON],
+            };
+            for expected in trailer {
+                match input.get(len) {
+                    Some(byte) if byte & 0x7f == *expected => len += 1,
+                    _ => break,
+                }
+            }
+        }
+        return Ok((header, len));
+    }
+    Err(CodecError::Incomplete)
+}
+
+/// The escaped CRC of a subpacket.
+type CrcBytes = Escape<iter::Take<array::IntoIter<u8, 4>>>;
+
+/// Iterator over the bytes of a data subpacket, see [`subpacket`].
+pub type Subpacket<'a> = iter::Chain<
+    iter::Chain<iter::Chain<Escape<Bytes<'a>>, array::IntoIter<u8, 2>>, CrcBytes>,
+    option::IntoIter<u8>,
+>;
+
+/// The bytes of a data subpacket holding `data`, ending with `packet_type` and the CRC for
+/// `encoding`, followed by XON for ZCRCW.
+pub fn subpacket<'a>(
+    encoding: FrameEncoding,
+    packet_type: PacketType,
+    data: &'a [u8],
+    mode: EscapeMode,
+) -> Subpacket<'a> {
+    let (crc, len) = if encoding == FrameEncoding::BIN32 {
+        (crc32(data, Some(packet_type.0)).to_le_bytes(), 4)
+    } else {
+        let [hi, lo] = crc16(data, Some(packet_type.0)).to_be_bytes();
+        ([hi, lo, 0, 0], 2)
+    };
+    let xon = (packet_type == PacketType::ZCRCW).then_some(XON);
+    escape(data, mode)
+        .chain([ZDLE, packet_type.0])
+        .chain(escape_iter(crc.into_iter().take(len), mode))
+        .chain(xon)
+}
+
+/// Encode a data subpacket to the start of `output`, returning the encoded length.
+///
+/// [`max_encoded_subpacket_len`] gives the length `output` needs at most.
+pub fn encode_subpacket(
+    encoding: FrameEncoding,
+    packet_type: PacketType,
+    data: &[u8],
+    mode: EscapeMode,
+    output: &mut [u8],
+) -> Result<usize, CodecError> {
+    let mut writer = Writer { output, len: 0 };
+    writer.write(subpacket(encoding, packet_type, data, mode))?;
+    Ok(writer.len)
+}
+
+/// Decodes data subpackets one byte at a time.
+#[derive(Clone, Debug)]
+pub struct SubpacketDecoder {
+    encoding: FrameEncoding,
+    unescaper: Unescaper,
+    len: usize,
+    end: Option<PacketType>,
+    crc: [u8; 4],
+    crc_len: usize,
+}
+
+impl SubpacketDecoder {
+    /// Decode subpackets following a header with the given `encoding`.
+    pub fn new(encoding: FrameEncoding, mode: EscapeMode) -> SubpacketDecoder {
+        Self {
+            encoding,
+            unescaper: Unescaper::new(mode),
+            len: 0,
+            end: None,
+            crc: [0; 4],
+            crc_len: 0,
+        }
+    }
+
+    /// Number of data bytes decoded so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Decode the next received byte into `output`, which must be the same for all bytes of a
+    /// subpacket. Returns the packet type and the data length once the subpacket is complete.
+    ///
+    /// The decoder starts over with the next subpacket after it completes or fails.
+    pub fn push(
+        &mut self,
+        byte: u8,
+        output: &mut [u8],
+    ) -> Result<Option<(PacketType, usize)>, CodecError> {
+        let result = self.decode(byte, output);
+        if !matches!(result, Ok(None)) {
+            *self = Self::new(self.encoding, self.unescaper.mode);
+        }
+        result
+    }
+
+    fn decode(
+        &mut self,
+        byte: u8,
+        output: &mut [u8],
+    ) -> Result<Option<(PacketType, usize)>, CodecError> {
+        let Some(unescaped) = self.unescaper.push(byte)? else {
+            return Ok(None);
+        };
+        let Some(packet_type) = self.end else {
+            match unescaped {
+                Unescaped::Byte(byte) => {
+                    *output.get_mut(self.len).ok_or(CodecError::BufferTooSmall)? = byte;
+                    self.len += 1;
+                }
+                Unescaped::End(packet_type) => self.end = Some(packet_type),
+            }
+            return Ok(None);
+        };
+
+        let Unescaped::Byte(byte) = unescaped else {
+            return Err(CodecError::InvalidEscape(packet_type.0));
+        };
+        self.crc[self.crc_len] = byte;
+        self.crc_len += 1;
+        let data = &output[..self.len];
+        let valid = match (self.encoding, self.crc_len) {
+            (FrameEncoding::BIN32, 4) => crc32(data, Some(packet_type.0)).to_le_bytes() == self.crc,
+            (FrameEncoding::BIN32, _) | (_, 1) => return Ok(None),
+            _ => crc16(data, Some(packet_type.0)).to_be_bytes() == self.crc[..2],
+        };
+        if !valid {
+            return Err(CodecError::InvalidCrc);
+        }
+        Ok(Some((packet_type, self.len)))
+    }
+}
+
+/// Decode the first data subpacket in `input` into `output`, returning its type, its data, and
+/// the number of bytes it took in `input`.
+pub fn decode_subpacket<'o>(
+    encoding: FrameEncoding,
+    input: &[u8],
+    mode: EscapeMode,
+    output: &'o mut [u8],
+) -> Result<(PacketType, &'o [u8], usize), CodecError> {
+    let mut decoder = SubpacketDecoder::new(encoding, mode);
+    for (i, byte) in input.iter().enumerate() {
+        if let Some((packet_type, len)) = decoder.push(*byte, output)? {
+            return Ok((packet_type, &output[..len], i + 1));
+        }
+    }
+    Err(CodecError::Incomplete)
+}
@@ -0,0 +1,11 @@
+/// Exit status of commands that can't be run, like a missing command in a shell.
+pub const COMMAND_NOT_FOUND: u32 = 127;
+
+/// Runs commands sent by the remote end with ZCOMMAND.
+pub trait CommandHandler {
+    /// Run `command`, returning its exit status.
+    ///
+    /// If the sender asked for an acknowledgement first (ZCACK1), it has already been told
+    /// the command completed, and the returned status is ignored.
+    fn run(&mut self, command: &str) -> u32;
+}
@@ -0,0 +1,130 @@
+use crate::{
+    kermit,
+    proto::consts::{NAK, SOH, STX, ZPAD},
+    recv::Receiver,
+    sink::SliceSink,
+    xmodem, Error, SerialDevice,
+};
+use core::time::Duration;
+
+/// Interval at which we repeat our invitations while waiting for a sender.
+const INVITE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Bytes read to tell an XMODEM block from a Kermit packet, the most detection pushes back.
+pub(crate) const PEEK_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    Zmodem,
+    Ymodem,
+    Xmodem,
+    Kermit,
+}
+
+/// Receive files using whichever protocol the sender speaks.
+///
+/// See [`Receiver::receive_auto`].
+pub fn receive_auto<D: SerialDevice>(
+    dev: D,
+    output: &mut [u8],
+) -> Result<(Protocol, usize), Error<D::Error>> {
+    Receiver::new(dev).receive_auto(output)
+}
+
+impl<D: SerialDevice> Receiver<'_, D> {
+    /// Receive files using whichever protocol the sender speaks.
+    ///
+    /// Until the sender starts, we repeat our ZRINIT to invite ZMODEM transfers, followed
+    /// alternately by 'C' and NAK to invite XMODEM/YMODEM transfers with CRCs or checksums, and
+    /// watch the line for the sender's reply:
+    ///
+    /// - a ZMODEM header or `rz\r` starts a ZMODEM session,
+    /// - an XMODEM block starts an XMODEM transfer, or YMODEM if it is block 0,
+    /// - a Kermit Send-Init packet starts a Kermit transfer.
+    ///
+    /// Receiver settings only apply to ZMODEM sessions.
+    ///
+    /// Returns the detected protocol and the total number of bytes received into `output`.
+    pub fn receive_auto(mut self, output: &mut [u8]) -> Result<(Protocol, usize), Error<D::Error>> {
+        let mut last = [0; 2];
+        let mut crc = false;
+
+        'detect: loop {
+            self.send_zrinit()?;
+            let dev = self.device();
+            crc = !crc;
+            dev.send(if crc { b'C' } else { NAK })?;
+
+            loop {
+                let byte = match dev.recv(INVITE_INTERVAL) {
+                    Ok(byte) => byte,
+                    Err(Error::TimedOut) => break,
+                    Err(error) => return Err(error),
+                };
+
+                match byte {
+                    ZPAD => {
+                        dev.unread(&[byte]);
+                        break 'detect;
+                    }
+                    b'\r' if last == *b"rz" => break 'detect,
+                    SOH | STX => {
+                        // Read enough of the packet to tell an XMODEM block from a Kermit packet.
+                        let mut packet = [0; PEEK_LEN];
+                        packet[0] = byte;
+                        let mut len = 1;
+                        let protocol = loop {
+                            packet[len] = match dev.recv(INVITE_INTERVAL) {
+                                Ok(byte) => byte,
+                                Err(Error::TimedOut) => {
+                                    dev.unread(&packet[1..len]);
+                                    continue 'detect;
+                                }
+                                Err(error) => return Err(error),
+                            };
+                            len += 1;
+                            match packet {
+                                // The block number and its complement.
+                                [_, 0, 0xff, _] if len == 3 => break Some(Protocol::Ymodem),
+                                [_, b1, b2, _] if len == 3 && b1 ^ b2 == 0xff => {
+                                    break Some(Protocol::Xmodem)
+                                }
+                                // A Kermit Send-Init packet: MARK, LEN, SEQ (always 0), TYPE.
+                                [SOH, _, b' ', b'S'] => break Some(Protocol::Kermit),
+                                _ if len == packet.len() => break None,
+                                _ => (),
+                            }
+                        };
+
+                        match protocol {
+                            Some(Protocol::Kermit) => {
+                                dev.unread(&packet);
+                                let len = kermit::Receiver::from_device(self.into_device())
+                                    .receive(output)?;
+                                return Ok((Protocol::Kermit, len));
+                            }
+                            Some(protocol) => {
+                                dev.unread(&packet[..len]);
+                                // Either invitation may be the one answered, so the first
+                                // block tells whether it ends with a CRC or a checksum.
+                                let len = xmodem::Receiver::from_device(self.into_device())
+                                    .set_detect_check()
+                                    .receive_files(output, false)?;
+                                return Ok((protocol, len));
+                            }
+                            // Not a packet after all, so look at its other bytes again.
+                            None => dev.unread(&packet[1..]),
+                        }
+                    }
+                    _ => (),
+                }
+
+                last = [last[1], byte];
+            }
+        }
+
+        let mut sink = SliceSink::new(output);
+        self.receive_files(&mut sink)?;
+        Ok((Protocol::Zmodem, sink.len()))
+    }
+}
@@ -0,0 +1,139 @@
+//! [`SerialDevice`] for drivers implementing the `embedded-io` or `embedded-hal-nb` traits.
+//!
+//! Both poll the driver until a byte arrives, using a [`Clock`] for timeouts.
+
+use crate::SerialDevice;
+use core::time::Duration;
+#[cfg(feature = "embedded-hal-nb")]
+use embedded_hal_nb::{
+    nb,
+    serial::{Error, ErrorKind},
+};
+
+/// A source of time for timeouts.
+///
+/// Implemented by closures returning the time since any fixed point, such as boot.
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+impl<F: Fn() -> Duration> Clock for F {
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+/// Poll `ready` until it returns `Some`, or `timeout` expires.
+fn poll<T, E>(
+    clock: &impl Clock,
+    timeout: Duration,
+    mut ready: impl FnMut() -> Result<Option<T>, E>,
+) -> Result<Option<T>, E> {
+    let start = clock.now();
+    loop {
+        if let Some(value) = ready()? {
+            return Ok(Some(value));
+        }
+        if clock.now().saturating_sub(start) >= timeout {
+            return Ok(None);
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// A [`SerialDevice`] for drivers implementing `embedded_io::{Read, ReadReady, Write}`.
+#[cfg(feature = "embedded-io")]
+pub struct IoDevice<T, C> {
+    io: T,
+    clock: C,
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T, C> IoDevice<T, C> {
+    pub fn new(io: T, clock: C) -> IoDevice<T, C> {
+        Self { io, clock }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<T, C> SerialDevice for IoDevice<T, C>
+where
+    T: embedded_io::Read + embedded_io::ReadReady + embedded_io::Write,
+    C: Clock,
+{
+    type Error = T::Error;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.io.write_all(&[byte])
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        // Anything buffered must go out before we wait for the answer.
+        self.io.flush()?;
+        let io = &mut self.io;
+        poll(&self.clock, timeout, || {
+            if !io.read_ready()? {
+                return Ok(None);
+            }
+            let mut byte = 0;
+            match io.read(core::slice::from_mut(&mut byte))? {
+                0 => Ok(None),
+                _ => Ok(Some(byte)),
+            }
+        })
+    }
+}
+
+/// A [`SerialDevice`] for drivers implementing `embedded_hal_nb::serial::{Read, Write}`.
+///
+/// Bytes lost to overruns, parity or framing errors are dropped, the protocol recovers from
+/// them like from any other corruption.
+#[cfg(feature = "embedded-hal-nb")]
+pub struct NbDevice<T, C> {
+    serial: T,
+    clock: C,
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T, C> NbDevice<T, C> {
+    pub fn new(serial: T, clock: C) -> NbDevice<T, C> {
+        Self { serial, clock }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.serial
+    }
+}
+
+#[cfg(feature = "embedded-hal-nb")]
+impl<T, C> SerialDevice for NbDevice<T, C>
+where
+    T: embedded_hal_nb::serial::Read + embedded_hal_nb::serial::Write,
+    C: Clock,
+{
+    type Error = T::Error;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        nb::block!(self.serial.write(byte))
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        nb::block!(self.serial.flush())?;
+        let serial = &mut self.serial;
+        poll(&self.clock, timeout, || match serial.read() {
+            Ok(byte) => Ok(Some(byte)),
+            Err(nb::Error::WouldBlock) => Ok(None),
+            Err(nb::Error::Other(error)) => match error.kind() {
+                ErrorKind::Overrun
+                | ErrorKind::Parity
+                | ErrorKind::Noise
+                | ErrorKind::FrameFormat => Ok(None),
+                _ => Err(error),
+            },
+        })
+    }
+}
@@ -0,0 +1,7 @@
+/// Something that happened during a session, reported to the event handler.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// Text the sender wants shown on our standard error (ZSTDERR).
+    Stderr(&'a [u8]),
+}
@@ -0,0 +1,116 @@
+//! Frame-level I/O shared by the sender and the receiver.
+
+use crate::{
+    codec::{self, HeaderDecoder, SubpacketDecoder, MAX_HEADER_LEN},
+    proto::{consts::*, EscapeMode, FrameEncoding, FrameHeader, FrameType, PacketType},
+    Device, Error, SerialDevice, TIMEOUT_DURATION,
+};
+use core::time::Duration;
+
+/// Consecutive CANs taken as an abort, which never appear in escaped data.
+const ABORT_CANS: usize = 5;
+
+/// Count consecutive CANs in `cans`, failing once the other end has aborted.
+fn count_cans<D>(cans: &mut usize, byte: u8) -> Result<(), Error<D>> {
+    *cans = if byte == CAN { *cans + 1 } else { 0 };
+    if *cans >= ABORT_CANS {
+        return Err(Error::Aborted);
+    }
+    Ok(())
+}
+
+impl<D: SerialDevice> Device<D> {
+    pub(crate) fn send_frame(&mut self, frame: FrameHeader) -> Result<(), Error<D::Error>> {
+        // println!(
+        //     "tx frame: {:?}, {:?}, {:02x?}",
+        //     frame.encoding, frame.r#type, frame.data
+        // );
+
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = codec::encode_header(&frame, self.escape, &mut buf)?;
+        for byte in &buf[..len] {
+            self.send(*byte)?;
+        }
+        Ok(())
+    }
+
+    /// Send a data subpacket, following a header with the given `encoding`.
+    pub(crate) fn send_data_packet(
+        &mut self,
+        encoding: FrameEncoding,
+        packet_type: PacketType,
+        data: &[u8],
+    ) -> Result<(), Error<D::Error>> {
+        for byte in codec::subpacket(encoding, packet_type, data, self.escape) {
+            self.send(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Receive a raw byte (no unescaping or hex-decoding), dropping the 8th bit on 7-bit links.
+    pub(crate) fn recv_raw(&mut self, timeout: Duration) -> Result<u8, Error<D::Error>> {
+        let byte = self.recv(timeout)?;
+        if self.escape == EscapeMode::EightBit {
+            Ok(byte & 0x7f)
+        } else {
+            Ok(byte)
+        }
+    }
+
+    pub(crate) fn receive_data_packet<'buf>(
+        &mut self,
+        encoding: FrameEncoding,
+        buf: &'buf mut [u8],
+    ) -> Result<(PacketType, &'buf [u8]), Error<D::Error>> {
+        let mut decoder = SubpacketDecoder::new(encoding, self.escape);
+        let mut cans = 0;
+        loop {
+            let byte = self.recv(TIMEOUT_DURATION)?;
+            count_cans(&mut cans, byte)?;
+            if let Some((packet_type, len)) = decoder.push(byte, buf)? {
+                // println!("data: {len} bytes, {packet_type:?}");
+                return Ok((packet_type, &buf[..len]));
+            }
+        }
+    }
+
+    pub(crate) fn receive_frame_header(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<FrameHeader, Error<D::Error>> {
+        let mut decoder = HeaderDecoder::new(self.escape);
+        let mut cans = 0;
+        let frame = loop {
+            // Only wait `timeout` for a header to start.
+            let timeout = if decoder.is_started() {
+                TIMEOUT_DURATION
+            } else {
+                timeout
+            };
+            let byte = self.recv(timeout)?;
+            count_cans(&mut cans, byte)?;
+            if let Some(frame) = decoder.push(byte)? {
+                break frame;
+            }
+        };
+        let frame_type = frame.r#type;
+
+        if frame.encoding == FrameEncoding::HEX {
+            if self.recv(TIMEOUT_DURATION)? != CR {
+                println!("missing CR on hex frame");
+            }
+            if self.recv(TIMEOUT_DURATION)? & 0x7f != LF {
+                println!("missing LF on hex frame");
+            }
+            if !matches!(frame_type, FrameType::ZACK | FrameType::ZFIN)
+                && self.recv(TIMEOUT_DURATION)? != XON
+            {
+                println!("missing XON on hex frame");
+            }
+        }
+
+        // println!("rx frame: {encoding:?}, {frame_type:?}, {data:02x?}");
+
+        Ok(frame)
+    }
+}
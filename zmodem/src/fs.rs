@@ -0,0 +1,307 @@
+//! Receiving files into a directory on the host.
+
+use crate::{
+    proto::FileInfo,
+    sink::{ExistingFile, OpenMode, Sink, SinkError},
+    CRC32,
+};
+use std::{
+    format,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// A file being received, written to a temporary file next to its destination.
+struct Incoming {
+    file: File,
+    temp: PathBuf,
+    path: PathBuf,
+    mode: OpenMode,
+    permissions: Option<u32>,
+    mtime: Option<u64>,
+}
+
+impl Incoming {
+    /// Apply the file's metadata, and move it to its destination.
+    fn finish(self) -> io::Result<()> {
+        let Incoming {
+            file,
+            temp,
+            path,
+            mode,
+            permissions,
+            mtime,
+        } = self;
+        if let Some(mtime) = mtime {
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+        #[cfg(unix)]
+        if let Some(permissions) = permissions {
+            use std::os::unix::fs::PermissionsExt;
+            // Never set the setuid, setgid or sticky bits from the sender.
+            file.set_permissions(fs::Permissions::from_mode(permissions & 0o777))?;
+        }
+        #[cfg(not(unix))]
+        let _ = permissions;
+        file.sync_all()?;
+        drop(file);
+
+        // The file may have appeared while we were receiving it, and linking never replaces it.
+        if mode == OpenMode::Create {
+            fs::hard_link(&temp, &path)?;
+            return fs::remove_file(&temp);
+        }
+        fs::rename(&temp, &path)
+    }
+}
+
+/// A sink that stores files under a root directory.
+///
+/// File names from the sender are checked so files can't be written outside the root, and
+/// each file only replaces its destination once it has been completely received. Symlinks under
+/// the root are never followed, so files behind them are neither read nor written. Existing files
+/// are only replaced when the policy decides so, which by default takes a sender asking for
+/// `ZMCLOB`. Use a policy with `ZMPROT` to never replace them.
+pub struct FileSink {
+    root: PathBuf,
+    subdirectories: bool,
+    incoming: Option<Incoming>,
+    // Used to name temporary files.
+    count: u32,
+}
+
+fn io_error(error: io::Error) -> SinkError {
+    match error.kind() {
+        ErrorKind::StorageFull | ErrorKind::QuotaExceeded | ErrorKind::FileTooLarge => {
+            SinkError::Full
+        }
+        _ => SinkError::Io,
+    }
+}
+
+impl FileSink {
+    pub fn new(root: impl Into<PathBuf>) -> FileSink {
+        Self {
+            root: root.into(),
+            subdirectories: false,
+            incoming: None,
+            count: 0,
+        }
+    }
+
+    /// Accept file names with directories, which are created under the root as needed.
+    pub fn set_subdirectories(mut self, subdirectories: bool) -> Self {
+        self.subdirectories = subdirectories;
+        self
+    }
+
+    /// Where a file named `name` is stored, or `None` if the name isn't allowed.
+    ///
+    /// Names can't be absolute, contain NULs, or refer to a parent directory, and can only
+    /// contain directories if enabled with [`set_subdirectories`](Self::set_subdirectories).
+    /// Both `/` and `\` separate directories.
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        if name.contains('\0') || name.starts_with(['/', '\\']) {
+            return None;
+        }
+        // A DOS drive, as in "C:".
+        if name.as_bytes().get(1) == Some(&b':') {
+            return None;
+        }
+        let mut path = self.root.clone();
+        let mut components = 0;
+        for component in name.split(['/', '\\']) {
+            match component {
+                "" | "." => continue,
+                ".." => return None,
+                _ => {
+                    path.push(component);
+                    components += 1;
+                }
+            }
+        }
+        if components == 0 || (components > 1 && !self.subdirectories) {
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Check the directories leading to `path` under the root are directories and not symlinks,
+    /// creating the missing ones if `create`, and that `path` isn't a symlink.
+    fn check(&self, path: &Path, create: bool) -> Result<(), SinkError> {
+        let relative = path
+            .strip_prefix(&self.root)
+            .map_err(|_| SinkError::Refused)?;
+        let mut dir = self.root.clone();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            dir.push(component);
+            let last = components.peek().is_none();
+            let metadata = match fs::symlink_metadata(&dir) {
+                Err(error) if error.kind() == ErrorKind::NotFound && last => return Ok(()),
+                Err(error) if error.kind() == ErrorKind::NotFound && create => {
+                    match fs::create_dir(&dir) {
+                        Err(error) if error.kind() != ErrorKind::AlreadyExists => {
+                            return Err(io_error(error))
+                        }
+                        _ => fs::symlink_metadata(&dir).map_err(io_error)?,
+                    }
+                }
+                result => result.map_err(io_error)?,
+            };
+            if metadata.file_type().is_symlink() || (!last && !metadata.is_dir()) {
+                return Err(SinkError::Refused);
+            }
+        }
+        Ok(())
+    }
+
+    /// Open the existing file at `path`, which [`check`](Self::check) has found isn't a symlink.
+    fn open_existing(path: &Path) -> io::Result<File> {
+        let file = File::open(path)?;
+        // Make sure it wasn't swapped for a symlink in between.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let (opened, found) = (file.metadata()?, fs::symlink_metadata(path)?);
+            if (opened.dev(), opened.ino()) != (found.dev(), found.ino()) {
+                return Err(ErrorKind::PermissionDenied.into());
+            }
+        }
+        Ok(file)
+    }
+
+    /// Create a temporary file in the same directory as `path`, so it can be renamed over it.
+    fn create_temp(&mut self, path: &Path) -> io::Result<(File, PathBuf)> {
+        let dir = path.parent().unwrap_or(&self.root);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        loop {
+            self.count = self.count.wrapping_add(1);
+            let temp = dir.join(format!(".{name}.{}.{}.part", process::id(), self.count));
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&temp)
+            {
+                Ok(file) => return Ok((file, temp)),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Drop a partially received file.
+    fn discard(&mut self) {
+        if let Some(incoming) = self.incoming.take() {
+            fs::remove_file(incoming.temp).ok();
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn existing(&mut self, info: &FileInfo) -> Option<ExistingFile> {
+        let path = self.path(info.name)?;
+        self.check(&path, false).ok()?;
+        let metadata = fs::symlink_metadata(path).ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .map(|mtime| mtime.as_secs());
+        Some(ExistingFile {
+            size: metadata.len(),
+            mtime,
+        })
+    }
+
+    fn crc32(&mut self, info: &FileInfo, len: u64) -> Option<u32> {
+        let path = self.path(info.name)?;
+        self.check(&path, false).ok()?;
+        let file = Self::open_existing(&path).ok()?;
+        let mut file = file.take(len);
+        let mut digest = CRC32.digest();
+        let mut buf = [0; 8192];
+        loop {
+            match file.read(&mut buf).ok()? {
+                0 => return Some(digest.finalize()),
+                n => digest.update(&buf[..n]),
+            }
+        }
+    }
+
+    fn open(&mut self, info: &FileInfo, mode: OpenMode) -> Result<(), SinkError> {
+        self.discard();
+        let path = self.path(info.name).ok_or(SinkError::Refused)?;
+        self.check(&path, true)?;
+        if mode == OpenMode::Create && fs::symlink_metadata(&path).is_ok() {
+            return Err(SinkError::Refused);
+        }
+
+        let (mut file, temp) = self.create_temp(&path).map_err(io_error)?;
+        // Appending and resuming start from a copy of the existing file.
+        if matches!(mode, OpenMode::Append | OpenMode::Resume) {
+            let copied = Self::open_existing(&path)
+                .and_then(|mut existing| io::copy(&mut existing, &mut file));
+            if let Err(error) = copied {
+                fs::remove_file(&temp).ok();
+                return Err(io_error(error));
+            }
+        }
+
+        self.incoming = Some(Incoming {
+            file,
+            temp,
+            path,
+            mode,
+            permissions: info.mode,
+            mtime: info.mtime,
+        });
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), SinkError> {
+        let incoming = self.incoming.as_mut().ok_or(SinkError::Io)?;
+        incoming
+            .file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| incoming.file.write_all(data))
+            .map_err(io_error)
+    }
+
+    fn hole(&mut self, offset: u64, len: u64) -> Result<(), SinkError> {
+        // Extending the file leaves a hole on filesystems that support them, later writes seek
+        // past the rest.
+        let incoming = self.incoming.as_mut().ok_or(SinkError::Io)?;
+        let end = offset + len;
+        let file_len = incoming.file.metadata().map_err(io_error)?.len();
+        if file_len < end {
+            incoming.file.set_len(end).map_err(io_error)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), SinkError> {
+        let incoming = self.incoming.take().ok_or(SinkError::Io)?;
+        let temp = incoming.temp.clone();
+        incoming.finish().map_err(|error| {
+            fs::remove_file(temp).ok();
+            match error.kind() {
+                ErrorKind::AlreadyExists => SinkError::Refused,
+                _ => io_error(error),
+            }
+        })
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        self.discard();
+    }
+}
@@ -0,0 +1,257 @@
+use crate::{
+    proto::consts::{CR, SOH},
+    Device, Error, SerialDevice,
+};
+use core::time::Duration;
+
+const PACKET_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ERRORS: usize = 10;
+
+/// Longest packet we accept, as advertised in our Send-Init acknowledgement.
+const MAXL: u8 = 94;
+
+fn tochar(byte: u8) -> u8 {
+    byte + 32
+}
+
+fn unchar(byte: u8) -> u8 {
+    byte.wrapping_sub(32)
+}
+
+fn ctl(byte: u8) -> u8 {
+    byte ^ 64
+}
+
+/// Type 1 block check over the bytes from LEN to the end of the data field.
+fn check1(bytes: impl IntoIterator<Item = u8>) -> u8 {
+    let sum = bytes.into_iter().fold(0u32, |sum, byte| sum + byte as u32);
+    tochar(((sum + ((sum & 0xc0) >> 6)) & 0x3f) as u8)
+}
+
+/// Parameters announced by the sender in its Send-Init packet.
+#[derive(Clone, Copy, Debug)]
+struct Params {
+    npad: u8,
+    padc: u8,
+    eol: u8,
+    qctl: u8,
+    qbin: Option<u8>,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            npad: 0,
+            padc: 0,
+            eol: CR,
+            qctl: b'#',
+            qbin: None,
+        }
+    }
+}
+
+struct Packet {
+    seq: u8,
+    r#type: u8,
+    len: usize,
+}
+
+pub struct Receiver<D: SerialDevice> {
+    dev: Device<D>,
+    params: Params,
+}
+
+impl<D: SerialDevice> Receiver<D> {
+    pub fn new(dev: D) -> Receiver<D> {
+        Self::from_device(Device::new(dev))
+    }
+
+    pub(crate) fn from_device(dev: Device<D>) -> Receiver<D> {
+        Self {
+            dev,
+            params: Params::default(),
+        }
+    }
+
+    /// Receive a packet, with its data field stored in `buf`.
+    ///
+    /// Returns `None` if a corrupted packet was received.
+    fn receive_packet(
+        &mut self,
+        buf: &mut [u8; MAXL as usize],
+        timeout: Duration,
+    ) -> Result<Option<Packet>, Error<D::Error>> {
+        while self.dev.recv(timeout)? != SOH {}
+
+        let len = self.dev.recv(PACKET_TIMEOUT)?;
+        let seq = self.dev.recv(PACKET_TIMEOUT)?;
+        let r#type = self.dev.recv(PACKET_TIMEOUT)?;
+
+        // We never agree to extended-length packets, so anything shorter than SEQ, TYPE
+        // and CHECK is corrupt.
+        let data_len = match unchar(len) {
+            len @ 3..=MAXL => (len - 3) as usize,
+            _ => return Ok(None),
+        };
+
+        for byte in &mut buf[..data_len] {
+            *byte = self.dev.recv(PACKET_TIMEOUT)?;
+        }
+        let check = self.dev.recv(PACKET_TIMEOUT)?;
+
+        let fields = [len, seq, r#type].into_iter();
+        if check != check1(fields.chain(buf[..data_len].iter().copied())) {
+            return Ok(None);
+        }
+
+        Ok(Some(Packet {
+            seq: unchar(seq),
+            r#type,
+            len: data_len,
+        }))
+    }
+
+    fn send_packet(&mut self, seq: u8, r#type: u8, data: &[u8]) -> Result<(), Error<D::Error>> {
+        let mut bytes = [0; MAXL as usize + 3];
+        bytes[0] = tochar(data.len() as u8 + 3);
+        bytes[1] = tochar(seq);
+        bytes[2] = r#type;
+        bytes[3..][..data.len()].copy_from_slice(data);
+        let bytes = &bytes[..3 + data.len()];
+
+        for _ in 0..self.params.npad {
+            self.dev.send(self.params.padc)?;
+        }
+        self.dev.send(SOH)?;
+        for byte in bytes {
+            self.dev.send(*byte)?;
+        }
+        self.dev.send(check1(bytes.iter().copied()))?;
+        self.dev.send(self.params.eol)?;
+        Ok(())
+    }
+
+    /// Acknowledge the Send-Init packet in `data` with our own parameters.
+    fn accept_send_init(&mut self, seq: u8, data: &[u8]) -> Result<(), Error<D::Error>> {
+        let field = |i: usize| data.get(i).copied().filter(|byte| *byte != b' ');
+
+        let mut params = Params::default();
+        if let Some(npad) = field(2) {
+            params.npad = unchar(npad);
+        }
+        if let Some(padc) = field(3) {
+            params.padc = ctl(padc);
+        }
+        if let Some(eol) = field(4) {
+            params.eol = unchar(eol);
+        }
+        if let Some(qctl) = field(5) {
+            params.qctl = qctl;
+        }
+        // The sender asks for 8th-bit prefixing by naming the prefix character.
+        params.qbin = field(6).filter(|qbin| matches!(qbin, 33..=62 | 96..=126));
+        self.params = params;
+
+        // MAXL, TIME, NPAD, PADC, EOL, QCTL, QBIN, CHKT, REPT
+        let ours = [
+            tochar(MAXL),
+            tochar(10),
+            tochar(0),
+            ctl(0),
+            tochar(CR),
+            b'#',
+            params.qbin.unwrap_or(b'Y'),
+            b'1',
+            b' ',
+        ];
+        self.send_packet(seq, b'Y', &ours)
+    }
+
+    /// Decode the data field of a D packet into `output`, returning the number of bytes written.
+    fn decode(&self, data: &[u8], output: &mut [u8]) -> Option<usize> {
+        let Params { qctl, qbin, .. } = self.params;
+        let mut len = 0;
+        let mut bytes = data.iter().copied();
+        while let Some(mut byte) = bytes.next() {
+            let mut high = 0;
+            if Some(byte) == qbin {
+                high = 0x80;
+                byte = bytes.next()?;
+            }
+            if byte == qctl {
+                byte = bytes.next()?;
+                if matches!(byte & 0x7f, 0x3f..=0x5f) {
+                    byte = ctl(byte);
+                }
+            }
+            *output.get_mut(len)? = byte | high;
+            len += 1;
+        }
+        Some(len)
+    }
+
+    /// Receive files into `output`, returning the total number of bytes received.
+    pub fn receive(&mut self, output: &mut [u8]) -> Result<usize, Error<D::Error>> {
+        let mut buf = [0; MAXL as usize];
+        let mut output_offset = 0;
+        let mut expected = 0u8;
+        let mut errors = 0;
+
+        loop {
+            let packet = match self.receive_packet(&mut buf, PACKET_TIMEOUT) {
+                Ok(Some(packet)) => packet,
+                Ok(None) | Err(Error::TimedOut) => {
+                    errors += 1;
+                    if errors >= MAX_ERRORS {
+                        return Err(Error::TooManyErrors);
+                    }
+                    self.send_packet(expected, b'N', &[])?;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            // A repeated packet means our acknowledgement was lost.
+            if packet.seq == expected.wrapping_sub(1) & 63 {
+                match packet.r#type {
+                    b'S' => self.accept_send_init(packet.seq, &buf[..packet.len])?,
+                    _ => self.send_packet(packet.seq, b'Y', &[])?,
+                }
+                continue;
+            }
+            if packet.seq != expected && packet.r#type != b'S' {
+                self.send_packet(expected, b'N', &[])?;
+                continue;
+            }
+            errors = 0;
+
+            let data = &buf[..packet.len];
+            match packet.r#type {
+                b'S' => {
+                    self.accept_send_init(packet.seq, data)?;
+                    expected = packet.seq;
+                }
+                b'D' => {
+                    let Some(len) = self.decode(data, &mut output[output_offset..]) else {
+                        self.send_packet(packet.seq, b'E', b"output full")?;
+                        return Err(Error::OutputFull);
+                    };
+                    output_offset += len;
+                    self.send_packet(packet.seq, b'Y', &[])?;
+                }
+                // File header, attributes and end of file need nothing more than an ACK.
+                b'F' | b'A' | b'Z' => self.send_packet(packet.seq, b'Y', &[])?,
+                b'B' => {
+                    self.send_packet(packet.seq, b'Y', &[])?;
+                    return Ok(output_offset);
+                }
+                b'E' => return Err(Error::Aborted),
+                _ => {
+                    self.send_packet(packet.seq, b'E', b"unsupported packet type")?;
+                    return Err(Error::Aborted);
+                }
+            }
+            expected = (expected + 1) & 63;
+        }
+    }
+}
@@ -21,13 +21,42 @@ fn print(args: core::fmt::Arguments) {
 #[cfg(not(feature = "std"))]
 fn print(_args: core::fmt::Arguments) {}
 
+#[cfg(feature = "std")]
+pub mod analyse;
+pub mod cancel;
+pub mod codec;
+pub mod command;
+pub mod detect;
+#[cfg(any(feature = "embedded-io", feature = "embedded-hal-nb"))]
+pub mod embedded;
+pub mod event;
+mod frame;
+#[cfg(feature = "std")]
+pub mod fs;
+pub mod kermit;
+#[cfg(feature = "lzw")]
+pub mod lzw;
+pub mod policy;
 pub mod proto;
 pub mod recv;
+pub mod send;
+pub mod server;
+pub mod sink;
+pub mod source;
+#[cfg(feature = "std")]
+pub mod telnet;
+#[cfg(feature = "std")]
+pub mod transcript;
+pub mod xmodem;
 
+pub use detect::{receive_auto, Protocol};
 pub use recv::receive;
+pub use send::send;
 
 use core::{fmt, time::Duration};
-use proto::{FrameEncoding, FrameHeader};
+use proto::{EscapeMode, FrameEncoding, FrameHeader};
+use sink::SinkError;
+use source::SourceError;
 
 pub trait SerialDevice {
     type Error: fmt::Debug;
@@ -40,6 +69,14 @@ pub trait SerialDevice {
     /// Returns `Some` if a byte is received before the timeout expires, `None` otherwise,
     /// or `Err` if an error occurs.
     fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error>;
+
+    /// Whether the session should stop, checked before every byte sent or received.
+    ///
+    /// The session then sends [`ABORT_SEQUENCE`](cancel::ABORT_SEQUENCE) and fails with
+    /// [`Error::Cancelled`]. See [`Cancellable`](cancel::Cancellable) to cancel any device.
+    fn is_cancelled(&mut self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -48,26 +85,113 @@ pub enum Error<D> {
     UnexpectedFrame(FrameHeader),
     InvalidHex(u8),
     InvalidEscape(u8),
+    InvalidCrc,
+    /// A data subpacket is longer than our limit.
+    SubpacketTooLong,
+    /// A compressed subpacket couldn't be decompressed.
+    InvalidCompression,
     TimedOut,
+    /// The remote end cancelled the transfer.
+    Aborted,
+    /// The session was cancelled through [`SerialDevice::is_cancelled`].
+    Cancelled,
+    /// Too many consecutive errors occurred.
+    TooManyErrors,
+    /// The output buffer is too small to hold the received data.
+    OutputFull,
+    /// The received file doesn't match the sender's file CRC.
+    VerificationFailed,
+    Sink(SinkError),
+    Source(SourceError),
     Device(D),
 }
 
+impl<D> Error<D> {
+    /// Whether the error was caused by corrupted data, which the sender can be asked to resend.
+    fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidFrameEncoding(_)
+                | Error::InvalidHex(_)
+                | Error::InvalidEscape(_)
+                | Error::InvalidCrc
+                | Error::SubpacketTooLong
+                | Error::InvalidCompression
+        )
+    }
+}
+
 // Interal wrapper around `SerialDevice` to translate `None` into our `Error::TimedOut`.
 struct Device<D: SerialDevice> {
     dev: D,
+    // Bytes pushed back by `unread`, returned by `recv` before the device is read again.
+    unread: [u8; detect::PEEK_LEN],
+    unread_len: usize,
+    escape: EscapeMode,
+    // Whether the peer's ESCHIGH or TESCHIGH are taken at their word.
+    high_escapes: bool,
+    cancelled: bool,
 }
 
 impl<D: SerialDevice> Device<D> {
+    fn new(dev: D) -> Device<D> {
+        Self {
+            dev,
+            unread: [0; detect::PEEK_LEN],
+            unread_len: 0,
+            escape: EscapeMode::Clean,
+            high_escapes: false,
+            cancelled: false,
+        }
+    }
+
+    /// Fail with `Cancelled` if the device asks for it, sending the abort sequence once.
+    fn check_cancelled(&mut self) -> Result<(), Error<D::Error>> {
+        if !self.cancelled && self.dev.is_cancelled() {
+            self.cancelled = true;
+            for byte in cancel::ABORT_SEQUENCE {
+                self.dev.send(byte).map_err(Error::Device)?;
+            }
+        }
+        if self.cancelled {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Push `bytes` back so they are received again, in order, by the following calls to `recv`.
+    ///
+    /// At most [`detect::PEEK_LEN`] bytes can be pending: detection pushes back no more than
+    /// it peeked at, and only reads the device again once what it pushed back is used up.
+    fn unread(&mut self, bytes: &[u8]) {
+        assert!(
+            self.unread_len + bytes.len() <= self.unread.len(),
+            "pushed back more than {} bytes",
+            self.unread.len()
+        );
+        for byte in bytes.iter().rev() {
+            self.unread[self.unread_len] = *byte;
+            self.unread_len += 1;
+        }
+    }
+
     fn send(&mut self, byte: u8) -> Result<(), Error<D::Error>> {
         // println!("tx: {byte:02x} ({:?})", byte as char);
+        self.check_cancelled()?;
         self.dev.send(byte).map_err(Error::Device)
     }
 
     #[allow(clippy::let_and_return)]
     fn recv(&mut self, timeout: Duration) -> Result<u8, Error<D::Error>> {
+        if self.unread_len > 0 {
+            self.unread_len -= 1;
+            return Ok(self.unread[self.unread_len]);
+        }
+        self.check_cancelled()?;
         let result = match self.dev.recv(timeout) {
             Ok(Some(byte)) => Ok(byte),
-            Ok(None) => Err(Error::TimedOut),
+            // The device may have given up waiting because it was cancelled.
+            Ok(None) => self.check_cancelled().and(Err(Error::TimedOut)),
             Err(error) => Err(Error::Device(error)),
         };
         // match &result {
@@ -80,9 +204,14 @@ impl<D: SerialDevice> Device<D> {
 
 const TIMEOUT_DURATION: Duration = Duration::from_secs(600);
 
+/// Largest data subpacket we send or receive (ZMODEM-8k).
+pub const MAX_SUBPACKET_LEN: usize = 8192;
+
+static CRC16: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_XMODEM);
+pub(crate) static CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
 pub fn crc16(buf: &[u8], frameend: Option<u8>) -> u16 {
-    let crc = crc::Crc::<u16>::new(&crc::CRC_16_XMODEM);
-    let mut digest = crc.digest();
+    let mut digest = CRC16.digest();
     digest.update(buf);
     if let Some(frameend) = frameend {
         digest.update(&[frameend]);
@@ -91,8 +220,7 @@ pub fn crc16(buf: &[u8], frameend: Option<u8>) -> u16 {
 }
 
 pub fn crc32(buf: &[u8], frameend: Option<u8>) -> u32 {
-    let crc = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
-    let mut digest = crc.digest();
+    let mut digest = CRC32.digest();
     digest.update(buf);
     if let Some(frameend) = frameend {
         digest.update(&[frameend]);
@@ -0,0 +1,226 @@
+//! LZW compression for the ZTLZW transport option.
+//!
+//! Codes are 9 to 12 bits wide and packed LSB first, as in Unix `compress`. The dictionary
+//! starts fresh with each ZDATA header, so the receiver can follow the sender after an error,
+//! and stops growing once its 4096 codes are used. Each subpacket holds the codes for a whole
+//! number of bytes and is padded to a byte boundary, so it can be decoded on its own.
+
+/// Number of codes in the dictionary, the first 256 being the single bytes.
+const MAX_CODES: usize = 4096;
+const FIRST_CODE: usize = 256;
+
+/// Size of the encoder's hash table, a prime comfortably above `MAX_CODES`.
+const HASH_LEN: usize = 5003;
+
+/// Width of the codes while `codes` codes are in use.
+fn width(codes: usize) -> u32 {
+    (usize::BITS - (codes - 1).leading_zeros()).max(9)
+}
+
+/// Compresses the subpackets of a frame, which share its dictionary.
+pub struct Encoder {
+    // Open-addressed table mapping `(prefix << 8 | byte) + 1` to a code, 0 marking free slots.
+    keys: [u32; HASH_LEN],
+    codes: [u16; HASH_LEN],
+    next_code: usize,
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Self {
+            keys: [0; HASH_LEN],
+            codes: [0; HASH_LEN],
+            next_code: FIRST_CODE,
+        }
+    }
+
+    /// Start over with an empty dictionary.
+    pub fn reset(&mut self) {
+        self.keys = [0; HASH_LEN];
+        self.next_code = FIRST_CODE;
+    }
+
+    /// Find the code for `key`, or the free slot to store it in.
+    fn lookup(&self, key: u32) -> Result<u16, usize> {
+        let mut slot = key as usize % HASH_LEN;
+        loop {
+            match self.keys[slot] {
+                0 => return Err(slot),
+                k if k == key => return Ok(self.codes[slot]),
+                _ => slot = (slot + 1) % HASH_LEN,
+            }
+        }
+    }
+
+    /// Compress `input` into `output`, returning the compressed length.
+    ///
+    /// This is at most 1.5 times the length of `input`, rounded up, which `output` must hold.
+    pub fn encode(&mut self, input: &[u8], output: &mut [u8]) -> usize {
+        let mut writer = BitWriter {
+            output,
+            len: 0,
+            bits: 0,
+            count: 0,
+        };
+        let Some((first, rest)) = input.split_first() else {
+            return 0;
+        };
+        let mut prefix = *first as u16;
+        for byte in rest {
+            let key = ((prefix as u32) << 8 | *byte as u32) + 1;
+            match self.lookup(key) {
+                Ok(code) => prefix = code,
+                Err(slot) => {
+                    writer.write(prefix, width(self.next_code));
+                    if self.next_code < MAX_CODES {
+                        self.keys[slot] = key;
+                        self.codes[slot] = self.next_code as u16;
+                        self.next_code += 1;
+                    }
+                    prefix = *byte as u16;
+                }
+            }
+        }
+        writer.write(prefix, width(self.next_code));
+        writer.finish()
+    }
+}
+
+struct BitWriter<'a> {
+    output: &'a mut [u8],
+    len: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter<'_> {
+    fn write(&mut self, code: u16, width: u32) {
+        self.bits |= (code as u32) << self.count;
+        self.count += width;
+        while self.count >= 8 {
+            self.output[self.len] = self.bits as u8;
+            self.len += 1;
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(self) -> usize {
+        if self.count > 0 {
+            self.output[self.len] = self.bits as u8;
+            self.len + 1
+        } else {
+            self.len
+        }
+    }
+}
+
+/// Decompresses the subpackets of a frame, in the order they were compressed.
+pub struct Decoder {
+    // The string for each code is the one for its prefix followed by its suffix.
+    prefixes: [u16; MAX_CODES],
+    suffixes: [u8; MAX_CODES],
+    next_code: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Self {
+            prefixes: [0; MAX_CODES],
+            suffixes: [0; MAX_CODES],
+            next_code: FIRST_CODE,
+        }
+    }
+
+    /// Start over with an empty dictionary.
+    pub fn reset(&mut self) {
+        self.next_code = FIRST_CODE;
+    }
+
+    /// Write the string for `code` to the start of `output`, returning its first byte and length.
+    fn expand(&self, mut code: usize, output: &mut [u8]) -> Option<(u8, usize)> {
+        let mut len = 1;
+        let mut prefix = code;
+        while prefix >= FIRST_CODE {
+            prefix = self.prefixes[prefix] as usize;
+            len += 1;
+        }
+        let output = output.get_mut(..len)?;
+        for byte in output.iter_mut().rev() {
+            if code >= FIRST_CODE {
+                *byte = self.suffixes[code];
+                code = self.prefixes[code] as usize;
+            } else {
+                *byte = code as u8;
+            }
+        }
+        Some((output[0], len))
+    }
+
+    /// Decompress the subpacket `input` into `output`, returning the decompressed length.
+    ///
+    /// Returns `None` if `input` isn't valid, or doesn't fit in `output`.
+    pub fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Option<usize> {
+        let mut input = input.iter();
+        let mut bits = 0u32;
+        let mut count = 0;
+        let mut len = 0;
+        let mut prev: Option<usize> = None;
+        loop {
+            // The encoder adds the entry for the previous code before writing the next one,
+            // while we can only add it once we've read that code.
+            let codes = (self.next_code + prev.is_some() as usize).min(MAX_CODES);
+            let width = width(codes);
+            while count < width {
+                match input.next() {
+                    Some(byte) => {
+                        bits |= (*byte as u32) << count;
+                        count += 8;
+                    }
+                    // Anything left is padding.
+                    None => return Some(len),
+                }
+            }
+            let code = (bits & ((1 << width) - 1)) as usize;
+            bits >>= width;
+            count -= width;
+
+            let first = match prev {
+                _ if code < self.next_code => {
+                    let (first, n) = self.expand(code, &mut output[len..])?;
+                    len += n;
+                    first
+                }
+                // The code being defined, the previous string followed by its own first byte.
+                Some(prev) if code == self.next_code => {
+                    let (first, n) = self.expand(prev, &mut output[len..])?;
+                    len += n;
+                    *output.get_mut(len)? = first;
+                    len += 1;
+                    first
+                }
+                _ => return None,
+            };
+            if let Some(prev) = prev {
+                if self.next_code < MAX_CODES {
+                    self.prefixes[self.next_code] = prev as u16;
+                    self.suffixes[self.next_code] = first;
+                    self.next_code += 1;
+                }
+            }
+            prev = Some(code);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    proto::{ConversionOption, FileInfo, FileOptions, ManagementFlags, ManagementOption},
+    sink::ExistingFile,
+};
+
+/// What to do with an offered file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Receive the file, replacing any existing file.
+    Accept,
+    /// Refuse the file with ZSKIP.
+    Skip,
+    /// Receive the file, appending it to the existing file.
+    Append,
+    /// Continue an interrupted transfer from the end of the existing file.
+    Resume,
+    /// Receive the file, unless the existing file has the same CRC as the sender's.
+    CompareCrc,
+}
+
+/// Receiver-side policy deciding what to do with each offered file.
+pub trait Policy {
+    fn decide(
+        &mut self,
+        info: &FileInfo,
+        options: &FileOptions,
+        existing: Option<&ExistingFile>,
+    ) -> Action;
+}
+
+/// Applies the ZFILE options as described by the ZMODEM specification.
+///
+/// Without a management option, an existing file is kept and the incoming one skipped. Set
+/// `management` to `ZMCLOB` to replace existing files whatever the sender asks for.
+///
+/// Options set here are the receiver's own, and take precedence over the sender's.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultPolicy {
+    pub conversion: Option<ConversionOption>,
+    pub management: Option<ManagementOption>,
+}
+
+impl DefaultPolicy {
+    fn conversion(&self, options: &FileOptions) -> ConversionOption {
+        // A ZCBIN from the sender overrides the receiver's conversion option, except ZCRESUM.
+        match (self.conversion, options.conversion) {
+            (Some(ConversionOption::ZCRESUM), _) => ConversionOption::ZCRESUM,
+            (_, ConversionOption::ZCBIN) => ConversionOption::ZCBIN,
+            (Some(ours), _) => ours,
+            (None, theirs) => theirs,
+        }
+    }
+}
+
+impl Policy for DefaultPolicy {
+    fn decide(
+        &mut self,
+        info: &FileInfo,
+        options: &FileOptions,
+        existing: Option<&ExistingFile>,
+    ) -> Action {
+        let Some(existing) = existing else {
+            if options
+                .management_flags
+                .contains(ManagementFlags::ZMSKNOLOC)
+            {
+                return Action::Skip;
+            }
+            return Action::Accept;
+        };
+
+        // A text file can't be resumed, as its line ends may have been converted.
+        if self.conversion(options) == ConversionOption::ZCRESUM
+            && options.conversion != ConversionOption::ZCNL
+            && info.size.is_none_or(|size| existing.size <= size)
+        {
+            return Action::Resume;
+        }
+
+        let newer = match (info.mtime, existing.mtime) {
+            (Some(incoming), Some(existing)) => incoming > existing,
+            _ => true,
+        };
+        let longer = info.size.is_none_or(|size| size > existing.size);
+        let same_size = info.size == Some(existing.size);
+        let same_date = info.mtime.is_some() && info.mtime == existing.mtime;
+
+        match self.management.unwrap_or(options.management) {
+            ManagementOption::ZMNEWL if !newer && !longer => Action::Skip,
+            ManagementOption::ZMNEW if !newer => Action::Skip,
+            ManagementOption::ZMDIFF if same_size && same_date => Action::Skip,
+            ManagementOption::ZMPROT => Action::Skip,
+            ManagementOption::ZMAPND => Action::Append,
+            ManagementOption::ZMCRC if same_size => Action::CompareCrc,
+            // Existing files are only replaced when asked to.
+            ManagementOption(0) => Action::Skip,
+            _ => Action::Accept,
+        }
+    }
+}
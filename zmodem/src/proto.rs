@@ -1,4 +1,17 @@
+//! Constants, headers and flags of the ZMODEM protocol.
+//!
+//! # The ZHIGH escape
+//!
+//! The specification has no way to send bytes with the 8th bit set over a 7-bit link. As a
+//! private extension, ZDLE [`ZHIGH`](consts::ZHIGH) sets the 8th bit of the byte after it. A
+//! receiver advertises it with [`ESCHIGH`](ReceiverCapabilities::ESCHIGH) in ZRINIT, and a sender
+//! with [`TESCHIGH`](TransmitterFlags::TESCHIGH) in ZSINIT. Both use bits the specification
+//! reserves, which other implementations may set for their own reasons, so the extension is off
+//! unless enabled with `set_high_escapes` on the `Sender` or `Receiver`, or by asking for
+//! [`EscapeMode::EightBit`]. Peers setting the bits are otherwise treated as not having set them.
+
 use bytemuck::{Pod, Zeroable};
+use core::fmt::Write;
 
 pub mod consts {
     pub const SOH: u8 = 0x01;
@@ -13,6 +26,7 @@ pub mod consts {
     pub const XOFF: u8 = 0x13;
     pub const NAK: u8 = 0x15;
     pub const CAN: u8 = 0x18;
+    pub const SUB: u8 = 0x1a; // CP/M end of file
 
     pub const ZPAD: u8 = 0x2a;
     pub const ZDLE: u8 = CAN;
@@ -31,6 +45,9 @@ pub mod consts {
     pub const ZVBINR32: u8 = 0x64;
 
     pub const ZRESC: u8 = 0x7e;
+    // Not part of the specification: the next byte has the 8th bit set, for 7-bit links. Only
+    // sent to peers that advertise ESCHIGH or TESCHIGH.
+    pub const ZHIGH: u8 = 0x6e;
 }
 
 macro_rules! enum_struct {
@@ -103,6 +120,31 @@ enum_struct! {
         const ZSTDERR    = 0x13;
     }
 
+    /// ZFILE conversion option (ZF0).
+    pub struct ConversionOption : u8 {
+        const ZCBIN   = 1;
+        const ZCNL    = 2;
+        const ZCRESUM = 3;
+    }
+
+    /// ZFILE management option (ZF1, masked with `ZMMASK`).
+    pub struct ManagementOption : u8 {
+        const ZMNEWL = 1;
+        const ZMCRC  = 2;
+        const ZMAPND = 3;
+        const ZMCLOB = 4;
+        const ZMNEW  = 5;
+        const ZMDIFF = 6;
+        const ZMPROT = 7;
+    }
+
+    /// ZFILE transport option (ZF2).
+    pub struct TransportOption : u8 {
+        const ZTLZW   = 1;
+        const ZTCRYPT = 2;
+        const ZTRLE   = 3;
+    }
+
     pub struct PacketType : u8 {
         const ZCRCE = 0x68;
         const ZCRCG = 0x69;
@@ -125,11 +167,275 @@ bitflags::bitflags! {
         const CANFC32 = 0x20;
         const ESCCTL  = 0x40;
         const ESC8    = 0x80;
+        /// Not part of the specification: the receiver understands ZHIGH escapes.
+        const ESCHIGH = 0x8000;
+    }
+}
+
+bitflags::bitflags! {
+    /// ZFILE management flags (ZF1), besides the management option itself.
+    #[repr(transparent)]
+    #[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+    pub struct ManagementFlags : u8 {
+        /// Skip the file if the receiver doesn't already have it.
+        const ZMSKNOLOC = 0x80;
+    }
+
+    /// ZFILE extended options (ZF3).
+    #[repr(transparent)]
+    #[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+    pub struct ExtendedOptions : u8 {
+        const ZXSPARS = 0x40;
+    }
+
+    /// ZCOMMAND flags (ZF0).
+    #[repr(transparent)]
+    #[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+    pub struct CommandFlags : u8 {
+        /// Acknowledge the command before running it.
+        const ZCACK1 = 0x01;
+    }
+
+    /// ZSINIT flags (ZF0).
+    #[repr(transparent)]
+    #[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+    pub struct TransmitterFlags : u8 {
+        /// The sender expects control characters to be escaped.
+        const TESCCTL = 0x40;
+        /// The sender expects the 8th bit to be escaped.
+        const TESC8   = 0x80;
+        /// Not part of the specification: the sender understands ZHIGH escapes.
+        const TESCHIGH = 0x01;
+    }
+}
+
+/// Which bytes are escaped in binary headers and data subpackets.
+///
+/// Each end asks for a mode in its ZRINIT or ZSINIT, and both then use the strictest of the two.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub enum EscapeMode {
+    /// Only ZDLE and the flow control characters are escaped.
+    #[default]
+    Clean,
+    /// All control characters are escaped, and any unescaped ones received are dropped.
+    Control,
+    /// Control characters and bytes with the 8th bit set are escaped, and the 8th bit of
+    /// received bytes is ignored.
+    ///
+    /// The specification has no escape for the 8th bit, so this is only used with peers that
+    /// ask for ESC8 or TESC8 along with the [ZHIGH escape](self#the-zhigh-escape), when it is
+    /// enabled. Other peers asking for ESC8 or TESC8 get `Control`.
+    EightBit,
+}
+
+impl EscapeMode {
+    pub fn from_capabilities(capabilities: ReceiverCapabilities) -> EscapeMode {
+        if capabilities.contains(ReceiverCapabilities::ESC8 | ReceiverCapabilities::ESCHIGH) {
+            EscapeMode::EightBit
+        } else if capabilities.intersects(ReceiverCapabilities::ESCCTL | ReceiverCapabilities::ESC8)
+        {
+            EscapeMode::Control
+        } else {
+            EscapeMode::Clean
+        }
+    }
+
+    pub fn capabilities(self) -> ReceiverCapabilities {
+        match self {
+            EscapeMode::Clean => ReceiverCapabilities::empty(),
+            EscapeMode::Control => ReceiverCapabilities::ESCCTL,
+            EscapeMode::EightBit => {
+                ReceiverCapabilities::ESCCTL
+                    | ReceiverCapabilities::ESC8
+                    | ReceiverCapabilities::ESCHIGH
+            }
+        }
+    }
+
+    pub fn from_transmitter_flags(flags: TransmitterFlags) -> EscapeMode {
+        if flags.contains(TransmitterFlags::TESC8 | TransmitterFlags::TESCHIGH) {
+            EscapeMode::EightBit
+        } else if flags.intersects(TransmitterFlags::TESCCTL | TransmitterFlags::TESC8) {
+            EscapeMode::Control
+        } else {
+            EscapeMode::Clean
+        }
+    }
+
+    pub fn transmitter_flags(self) -> TransmitterFlags {
+        match self {
+            EscapeMode::Clean => TransmitterFlags::empty(),
+            EscapeMode::Control => TransmitterFlags::TESCCTL,
+            EscapeMode::EightBit => {
+                TransmitterFlags::TESCCTL | TransmitterFlags::TESC8 | TransmitterFlags::TESCHIGH
+            }
+        }
+    }
+
+    /// Whether `byte` must be escaped with ZDLE, besides the 8th bit in `EightBit` mode.
+    pub(crate) fn needs_escape(self, byte: u8) -> bool {
+        use consts::*;
+        match self {
+            EscapeMode::Clean => matches!(byte & 0x7f, ZDLE | DLE | XON | XOFF),
+            _ => byte & 0x60 == 0 || byte & 0x7f == 0x7f,
+        }
+    }
+}
+
+/// Mask for the management option in ZF1.
+pub const ZMMASK: u8 = 0x1f;
+
+/// The options of a ZFILE header.
+#[derive(Clone, Copy, Debug)]
+pub struct FileOptions {
+    pub conversion: ConversionOption,
+    pub management: ManagementOption,
+    pub management_flags: ManagementFlags,
+    pub transport: TransportOption,
+    pub extended: ExtendedOptions,
+}
+
+impl FileOptions {
+    pub fn from_header(header: &FrameHeader) -> FileOptions {
+        let [zf3, zf2, zf1, zf0] = header.data;
+        Self {
+            conversion: ConversionOption(zf0),
+            management: ManagementOption(zf1 & ZMMASK),
+            management_flags: ManagementFlags::from_bits_retain(zf1 & !ZMMASK),
+            transport: TransportOption(zf2),
+            extended: ExtendedOptions::from_bits_retain(zf3),
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        u32::from_be_bytes([
+            self.extended.bits(),
+            self.transport.0,
+            self.management.0 | self.management_flags.bits(),
+            self.conversion.0,
+        ])
+    }
+}
+
+impl Default for FileOptions {
+    fn default() -> FileOptions {
+        Self {
+            conversion: ConversionOption(0),
+            management: ManagementOption(0),
+            management_flags: ManagementFlags::empty(),
+            transport: TransportOption(0),
+            extended: ExtendedOptions::empty(),
+        }
+    }
+}
+
+/// File information sent in the data subpacket following a ZFILE header.
+///
+/// Only the name is mandatory, the remaining fields are `None` if the sender omitted them.
+#[derive(Clone, Copy, Debug)]
+pub struct FileInfo<'a> {
+    pub name: &'a str,
+    pub size: Option<u64>,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+    pub mode: Option<u32>,
+    pub serial: Option<u32>,
+    pub files_remaining: Option<u32>,
+    pub bytes_remaining: Option<u64>,
+}
+
+impl<'a> FileInfo<'a> {
+    pub fn new(name: &'a str) -> FileInfo<'a> {
+        Self {
+            name,
+            size: None,
+            mtime: None,
+            mode: None,
+            serial: None,
+            files_remaining: None,
+            bytes_remaining: None,
+        }
+    }
+
+    /// Parse the contents of a ZFILE data subpacket.
+    ///
+    /// Returns `None` if the file name is missing or isn't valid UTF-8.
+    pub fn parse(buf: &'a [u8]) -> Option<FileInfo<'a>> {
+        let mut parts = buf.splitn(2, |b| *b == 0);
+        let name = core::str::from_utf8(parts.next()?).ok()?;
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut info = Self::new(name);
+        let meta = parts.next().unwrap_or_default();
+        let meta = meta.split(|b| *b == 0).next().unwrap_or_default();
+        let mut fields = meta.split(|b| *b == b' ').filter(|field| !field.is_empty());
+        let mut next = |radix| {
+            let field = core::str::from_utf8(fields.next()?).ok()?;
+            u64::from_str_radix(field, radix).ok()
+        };
+
+        info.size = next(10);
+        info.mtime = next(8);
+        info.mode = next(8).map(|mode| mode as u32);
+        info.serial = next(8).map(|serial| serial as u32);
+        info.files_remaining = next(10).map(|files| files as u32);
+        info.bytes_remaining = next(10);
+
+        Some(info)
+    }
+
+    /// Encode the file information as the contents of a ZFILE data subpacket.
+    ///
+    /// Returns the number of bytes written to `buf`, or `None` if it doesn't fit.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        struct Cursor<'a> {
+            buf: &'a mut [u8],
+            len: usize,
+        }
+
+        impl core::fmt::Write for Cursor<'_> {
+            fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                let end = self.len + s.len();
+                let buf = self.buf.get_mut(self.len..end).ok_or(core::fmt::Error)?;
+                buf.copy_from_slice(s.as_bytes());
+                self.len = end;
+                Ok(())
+            }
+        }
+
+        let mut cursor = Cursor { buf, len: 0 };
+        write!(cursor, "{}\0", self.name).ok()?;
+
+        // Fields may not be skipped, so stop at the first one that is missing.
+        let fields = [
+            (self.size, 10),
+            (self.mtime, 8),
+            (self.mode.map(u64::from), 8),
+            (self.serial.map(u64::from), 8),
+            (self.files_remaining.map(u64::from), 10),
+            (self.bytes_remaining, 10),
+        ];
+        let fields = fields
+            .into_iter()
+            .map_while(|(field, radix)| Some((field?, radix)));
+        for (i, (field, radix)) in fields.enumerate() {
+            let sep = if i > 0 { " " } else { "" };
+            match radix {
+                8 => write!(cursor, "{sep}{field:o}"),
+                _ => write!(cursor, "{sep}{field}"),
+            }
+            .ok()?;
+        }
+        write!(cursor, "\0").ok()?;
+
+        Some(cursor.len)
     }
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Pod, Zeroable)]
 pub struct FrameHeader {
     pub encoding: FrameEncoding,
     pub r#type: FrameType,
@@ -154,4 +460,27 @@ impl FrameHeader {
         self.data = count.to_le_bytes();
         self
     }
+
+    pub fn flags(&self) -> u32 {
+        u32::from_be_bytes(self.data)
+    }
+
+    pub fn count(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+
+    /// Set the capabilities (ZF0, ZF1) and buffer size (ZP0, ZP1) of a ZRINIT header.
+    pub fn set_zrinit(mut self, capabilities: ReceiverCapabilities, buffer_len: u16) -> Self {
+        let [zf0, zf1] = capabilities.bits().to_le_bytes();
+        let [zp0, zp1] = buffer_len.to_le_bytes();
+        self.data = [zp0, zp1, zf1, zf0];
+        self
+    }
+
+    /// The capabilities and buffer size of a ZRINIT header.
+    pub fn zrinit(&self) -> (ReceiverCapabilities, u16) {
+        let [zp0, zp1, zf1, zf0] = self.data;
+        let capabilities = ReceiverCapabilities::from_bits_retain(u16::from_le_bytes([zf0, zf1]));
+        (capabilities, u16::from_le_bytes([zp0, zp1]))
+    }
 }
@@ -1,59 +1,50 @@
+#[cfg(feature = "lzw")]
+use crate::lzw::Decoder;
 use crate::{
-    crc16, crc32, from_hex,
-    proto::{consts::*, FrameEncoding, FrameHeader, FrameType, PacketType},
-    to_hex, Device, Error, SerialDevice, TIMEOUT_DURATION,
+    command::{CommandHandler, COMMAND_NOT_FOUND},
+    event::Event,
+    policy::{Action, DefaultPolicy, Policy},
+    proto::{
+        consts::{CR, LF, SUB},
+        CommandFlags, ConversionOption, EscapeMode, ExtendedOptions, FileInfo, FileOptions,
+        FrameEncoding, FrameHeader, FrameType, PacketType, ReceiverCapabilities, TransmitterFlags,
+        TransportOption,
+    },
+    sink::{OpenMode, Sink, SinkError, SliceSink},
+    Device, Error, SerialDevice, CRC32, MAX_SUBPACKET_LEN, TIMEOUT_DURATION,
 };
-use bytemuck::{Pod, Zeroable};
 use core::time::Duration;
+use crc::Digest;
 
-pub struct Receiver<D: SerialDevice> {
+const MAX_ERRORS: usize = 10;
+
+pub struct Receiver<'a, D: SerialDevice> {
     dev: Device<D>,
+    policy: Option<&'a mut dyn Policy>,
+    commands: Option<&'a mut dyn CommandHandler>,
+    events: Option<&'a mut dyn FnMut(Event)>,
+    verify: bool,
+    buffer_len: u16,
+    max_subpacket_len: usize,
 }
 
-impl<D: SerialDevice> Receiver<D> {
-    fn send_hex(&mut self, byte: u8) -> Result<(), Error<D::Error>> {
-        let bytes = to_hex(byte);
-        self.dev.send(bytes[0])?;
-        self.dev.send(bytes[1])?;
-        Ok(())
-    }
-
-    fn send(&mut self, byte: u8, hex: bool) -> Result<(), Error<D::Error>> {
-        if hex {
-            self.send_hex(byte)
-        } else {
-            self.dev.send(byte)
-        }
-    }
-
+impl<D: SerialDevice> Receiver<'_, D> {
     pub fn send_frame(&mut self, frame: FrameHeader) -> Result<(), Error<D::Error>> {
-        let buf = bytemuck::bytes_of(&frame);
-        let crc = crc16(&buf[1..], None);
-
-        // println!(
-        //     "tx frame: {:?}, {:?}, {:02x?}",
-        //     frame.encoding, frame.r#type, frame.data
-        // );
-
-        self.send(ZPAD, false)?;
-        self.send(ZPAD, false)?;
-        self.send(ZDLE, false)?;
-        self.send(buf[0], false)?;
-        for byte in &buf[1..] {
-            self.send(*byte, frame.encoding == FrameEncoding::HEX)?;
-        }
-        for byte in crc.to_be_bytes() {
-            self.send(byte, frame.encoding == FrameEncoding::HEX)?;
-        }
-        self.send(CR, false)?;
-        self.send(0x80 | LF, false)?;
-        self.send(XON, false)?;
-
-        Ok(())
+        self.dev.send_frame(frame)
     }
 
     pub fn send_zrinit(&mut self) -> Result<(), Error<D::Error>> {
-        self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZRINIT))
+        let mut capabilities = ReceiverCapabilities::CANFDX
+            | ReceiverCapabilities::CANOVIO
+            | ReceiverCapabilities::CANFC32;
+        if cfg!(feature = "lzw") {
+            capabilities |= ReceiverCapabilities::CANLZW;
+        }
+        capabilities |= self.dev.escape.capabilities();
+        self.send_frame(
+            FrameHeader::new(FrameEncoding::HEX, FrameType::ZRINIT)
+                .set_zrinit(capabilities, self.buffer_len),
+        )
     }
 
     pub fn send_zrpos(&mut self, pos: u32) -> Result<(), Error<D::Error>> {
@@ -63,93 +54,124 @@ impl<D: SerialDevice> Receiver<D> {
     pub fn send_zfin(&mut self) -> Result<(), Error<D::Error>> {
         self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZFIN))
     }
-}
 
-#[derive(Clone, Copy, Debug)]
-enum RecvEnc {
-    Hex,
-    Esc,
-    Raw,
+    pub fn send_zack(&mut self, pos: u32) -> Result<(), Error<D::Error>> {
+        self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZACK).set_count(pos))
+    }
+
+    pub fn send_znak(&mut self) -> Result<(), Error<D::Error>> {
+        self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZNAK))
+    }
+
+    pub fn send_zskip(&mut self) -> Result<(), Error<D::Error>> {
+        self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZSKIP))
+    }
+
+    pub fn send_zferror(&mut self) -> Result<(), Error<D::Error>> {
+        self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZFERROR))
+    }
+
+    pub fn send_zcompl(&mut self, status: u32) -> Result<(), Error<D::Error>> {
+        self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZCOMPL).set_count(status))
+    }
+
+    /// Ask the sender for the CRC-32 of the first `len` bytes of the offered file, or of the
+    /// whole file if `len` is 0.
+    pub fn request_crc(&mut self, len: u32) -> Result<u32, Error<D::Error>> {
+        let mut errors = 0;
+        loop {
+            self.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZCRC).set_count(len))?;
+            match self.receive_frame_header(TIMEOUT_DURATION) {
+                Ok(frame) if frame.r#type == FrameType::ZCRC => return Ok(frame.count()),
+                // Most likely a repeated ZFILE, as our request was lost.
+                Ok(_) | Err(Error::TimedOut) => count_error(&mut errors)?,
+                Err(error) if error.is_corruption() => count_error(&mut errors)?,
+                Err(error) => return Err(error),
+            }
+        }
+    }
 }
 
-impl<D: SerialDevice> Receiver<D> {
-    pub fn new(dev: D) -> Receiver<D> {
+impl<'a, D: SerialDevice> Receiver<'a, D> {
+    pub fn new(dev: D) -> Receiver<'a, D> {
+        Self::from_device(Device::new(dev))
+    }
+
+    pub(crate) fn from_device(dev: Device<D>) -> Receiver<'a, D> {
         Self {
-            dev: Device { dev },
+            dev,
+            policy: None,
+            commands: None,
+            events: None,
+            verify: false,
+            buffer_len: 0,
+            max_subpacket_len: MAX_SUBPACKET_LEN,
         }
     }
 
-    /// Receive a raw byte (no unescaping or hex-decoding)
-    fn recv_raw(&mut self, timeout: Duration) -> Result<u8, Error<D::Error>> {
-        self.dev.recv(timeout)
+    /// Use `policy` to decide what to do with offered files, instead of [`DefaultPolicy`].
+    pub fn set_policy(mut self, policy: &'a mut dyn Policy) -> Self {
+        self.policy = Some(policy);
+        self
     }
 
-    /// Receive an unescaped byte.
-    fn recv_esc(&mut self, timeout: Duration) -> Result<u8, Error<D::Error>> {
-        let byte = match self.dev.recv(timeout)? {
-            ZDLE => match self.dev.recv(timeout)? {
-                byte if byte & 0x60 == 0x40 => byte ^ 0x40,
-                ZRUB0 => 0x7f,
-                ZRUB1 => 0xff,
-                byte => panic!("unhandled ZDLE escape: {byte:02x}"),
-            },
-            byte => byte,
-        };
-        Ok(byte)
+    /// Run commands sent with ZCOMMAND with `handler`.
+    ///
+    /// Without a handler, commands fail with [`COMMAND_NOT_FOUND`].
+    pub fn set_command_handler(mut self, handler: &'a mut dyn CommandHandler) -> Self {
+        self.commands = Some(handler);
+        self
     }
 
-    /// Receive a hex-encoded byte
-    fn recv_hex(&mut self, timeout: Duration) -> Result<u8, Error<D::Error>> {
-        // NOTE: Hex-encoding ignores parity.
-        let hi = self.dev.recv(timeout)? & 0x7f;
-        let lo = self.dev.recv(timeout)? & 0x7f;
-        Ok(from_hex([hi, lo])?)
+    /// Report session events to `handler`.
+    pub fn set_event_handler(mut self, handler: &'a mut dyn FnMut(Event)) -> Self {
+        self.events = Some(handler);
+        self
     }
 
-    /// Receive a byte, decoded according to `enc`
-    fn recv_byte(&mut self, enc: RecvEnc, timeout: Duration) -> Result<u8, Error<D::Error>> {
-        match enc {
-            RecvEnc::Hex => self.recv_hex(timeout),
-            RecvEnc::Esc => self.recv_esc(timeout),
-            RecvEnc::Raw => self.recv_raw(timeout),
-        }
+    /// Ask the sender to wait for us after every `len` bytes, instead of streaming.
+    pub fn set_buffer_len(mut self, len: u16) -> Self {
+        self.buffer_len = len;
+        self
     }
 
-    fn recv<T: Pod + Zeroable>(
-        &mut self,
-        enc: RecvEnc,
-        timeout: Duration,
-    ) -> Result<T, Error<D::Error>> {
-        let mut buf = T::zeroed();
-        for byte in bytemuck::bytes_of_mut(&mut buf) {
-            *byte = self.recv_byte(enc, timeout)?;
-        }
-        Ok(buf)
+    /// Ask the sender to escape the bytes `mode` calls for, for links that aren't 8-bit clean.
+    ///
+    /// `EightBit` also enables the ZHIGH escape, as with
+    /// [`set_high_escapes`](Self::set_high_escapes).
+    pub fn set_escape(mut self, mode: EscapeMode) -> Self {
+        self.dev.escape = mode;
+        self.dev.high_escapes |= mode == EscapeMode::EightBit;
+        self
+    }
+
+    /// Use the [ZHIGH escape](crate::proto#the-zhigh-escape), a private extension, with
+    /// senders that advertise it. Disabled by default.
+    pub fn set_high_escapes(mut self, high_escapes: bool) -> Self {
+        self.dev.high_escapes = high_escapes;
+        self
+    }
+
+    /// Refuse data subpackets longer than `len`, up to [`MAX_SUBPACKET_LEN`].
+    pub fn set_max_subpacket_len(mut self, len: usize) -> Self {
+        self.max_subpacket_len = len.clamp(1, MAX_SUBPACKET_LEN);
+        self
     }
 
-    /// Receive the data portion of a packet.
+    /// Check each received file against the sender's CRC-32 of the whole file.
     ///
-    /// Returns `None` if the entire buffer is filled, otherwise returns the packet type along
-    /// with the number of bytes written to the buffer.
-    fn receive_data(&mut self, buf: &mut [u8]) -> Result<Option<(u8, usize)>, Error<D::Error>> {
-        let mut i = 0;
-        while i < buf.len() {
-            let byte = match self.dev.recv(TIMEOUT_DURATION)? {
-                ZDLE => match self.dev.recv(TIMEOUT_DURATION)? {
-                    byte if byte & 0x60 == 0x40 => byte ^ 0x40,
-                    ZRUB0 => 0x7f,
-                    ZRUB1 => 0xff,
-                    packet_type @ (ZCRCE | ZCRCG | ZCRCQ | ZCRCW) => {
-                        return Ok(Some((packet_type, i)));
-                    }
-                    byte => panic!("unhandled ZDLE escape: {byte:02x}"),
-                },
-                byte => byte,
-            };
-            buf[i] = byte;
-            i += 1;
-        }
-        Ok(None)
+    /// Resumed transfers can't be verified, as we never see the start of the file.
+    pub fn set_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub(crate) fn device(&mut self) -> &mut Device<D> {
+        &mut self.dev
+    }
+
+    pub(crate) fn into_device(self) -> Device<D> {
+        self.dev
     }
 
     pub fn receive_data_packet<'buf>(
@@ -157,190 +179,586 @@ impl<D: SerialDevice> Receiver<D> {
         encoding: FrameEncoding,
         buf: &'buf mut [u8],
     ) -> Result<(PacketType, &'buf [u8]), Error<D::Error>> {
-        let (packet_type, len) = match self.receive_data(&mut buf[..1024])? {
-            Some((packet_type, len)) => (packet_type, len),
-            None => {
-                let byte = self.dev.recv(TIMEOUT_DURATION)?;
-                assert!(byte == ZDLE, "{byte:02x}");
-                (self.dev.recv(TIMEOUT_DURATION)?, 1024)
-            }
-        };
-        let packet_type = PacketType(packet_type);
-
-        let (crc, our_crc) = if encoding == FrameEncoding::BIN32 {
-            let crc = u32::from_be_bytes(self.recv(RecvEnc::Esc, TIMEOUT_DURATION)?);
-            let our_crc = crc32(&buf[..len], Some(packet_type.0));
-            (crc, our_crc)
-        } else {
-            let crc = u16::from_be_bytes(self.recv(RecvEnc::Esc, TIMEOUT_DURATION)?) as u32;
-            let our_crc = crc16(&buf[..len], Some(packet_type.0)) as u32;
-            (crc, our_crc)
-        };
-
-        if crc != our_crc {
-            println!("packet crc error");
-        }
-
-        // println!("data: {len} bytes, {packet_type:?}, crc {crc:#x}",);
-
-        Ok((packet_type, &buf[..len]))
+        self.dev.receive_data_packet(encoding, buf)
     }
 
     pub fn receive_frame_header(
         &mut self,
         timeout: Duration,
     ) -> Result<FrameHeader, Error<D::Error>> {
-        let mut prec_zpad = false;
-        loop {
-            match self.recv_raw(timeout)? {
-                ZPAD => match self.recv_raw(timeout)? {
-                    ZDLE => break,
-                    byte => {
-                        prec_zpad = byte == ZPAD;
+        self.dev.receive_frame_header(timeout)
+    }
+
+    /// Send a data subpacket, as read by [`receive_data_packet`](Self::receive_data_packet).
+    pub fn send_data_packet(
+        &mut self,
+        encoding: FrameEncoding,
+        packet_type: PacketType,
+        data: &[u8],
+    ) -> Result<(), Error<D::Error>> {
+        self.dev.send_data_packet(encoding, packet_type, data)
+    }
+}
+
+pub fn receive<D: SerialDevice>(dev: D, output: &mut [u8]) -> Result<usize, Error<D::Error>> {
+    let mut receiver = Receiver::new(dev);
+    let mut sink = SliceSink::new(output);
+    receiver.send_zrinit()?;
+    receiver.receive_files(&mut sink)?;
+    Ok(sink.len())
+}
+
+fn count_error<D>(errors: &mut usize) -> Result<(), Error<D>> {
+    *errors += 1;
+    if *errors >= MAX_ERRORS {
+        return Err(Error::TooManyErrors);
+    }
+    Ok(())
+}
+
+impl<D: SerialDevice> Receiver<'_, D> {
+    /// Receive a batch of files into `sink`.
+    ///
+    /// Our ZRINIT is only sent on timeout or in response to ZRQINIT, so the caller is expected
+    /// to have either sent it already or to have seen the sender start the session.
+    pub fn receive_files(&mut self, sink: &mut dyn Sink) -> Result<(), Error<D::Error>> {
+        self.receive_session(sink, false).map(|_| ())
+    }
+
+    /// Receive files and run commands until the session ends.
+    ///
+    /// If `reversible`, a ZRINIT from the remote end in place of the "OO" ending the session
+    /// means it wants to receive files from us. The ZRINIT is returned in that case.
+    pub(crate) fn receive_session(
+        &mut self,
+        sink: &mut dyn Sink,
+        reversible: bool,
+    ) -> Result<Option<FrameHeader>, Error<D::Error>> {
+        let mut buf = [0; MAX_SUBPACKET_LEN];
+        let buf = &mut buf[..self.max_subpacket_len];
+        let mut errors = 0;
+        let mut started = false;
+
+        // Status of the last command, repeated until the sender moves on.
+        let mut status = None;
+        // Number of the last command, which isn't run again if the sender repeats it.
+        let mut command = None;
+
+        'main: loop {
+            // Receive the ZFILE header.
+            let zfile = loop {
+                // Use a shorter timeout until a session is started, so we advertise our ZRINIT
+                // more frequently.
+                let timeout = if started {
+                    TIMEOUT_DURATION
+                } else {
+                    Duration::from_millis(500)
+                };
+                let frame = match self.receive_frame_header(timeout) {
+                    Ok(frame) => frame,
+                    Err(Error::TimedOut) => {
+                        match status {
+                            Some(status) => self.send_zcompl(status)?,
+                            None => self.send_zrinit()?,
+                        }
                         continue;
                     }
-                },
-                ZDLE if prec_zpad => break,
-                _ => continue,
+                    Err(error) if error.is_corruption() => {
+                        count_error(&mut errors)?;
+                        self.send_znak()?;
+                        continue;
+                    }
+                    Err(error) => return Err(error),
+                };
+                match frame.r#type {
+                    // Sender is requesting our ZRINIT header.
+                    FrameType::ZRQINIT => self.send_zrinit()?,
+                    // An echo of our own ZRINIT.
+                    FrameType::ZRINIT => continue,
+                    // Begin file transfer.
+                    FrameType::ZFILE => break frame,
+                    FrameType::ZCOMMAND => {
+                        started = true;
+                        // Senders that number their commands in ZP0-ZP2 repeat the number if
+                        // our ZCOMPL was lost. ZP3 is ZF0, holding ZCACK1, and lrzsz leaves
+                        // the number 0, so its commands are always run.
+                        let number = frame.count() & 0x00ff_ffff;
+                        let repeated = number != 0 && command == Some(number);
+                        match self.receive_command(frame, buf, status.filter(|_| repeated))? {
+                            Some(result) => {
+                                status = Some(result);
+                                command = Some(number);
+                            }
+                            None => count_error(&mut errors)?,
+                        }
+                    }
+                    // Finish session.
+                    FrameType::ZFIN => break 'main,
+                    // Our ZRINIT after the last file was lost.
+                    FrameType::ZEOF => self.send_zrinit()?,
+                    _ => match self.receive_aside(frame, sink, buf) {
+                        Ok(()) => (),
+                        Err(error) if error.is_corruption() => {
+                            count_error(&mut errors)?;
+                            self.send_znak()?;
+                        }
+                        Err(error) => return Err(error),
+                    },
+                }
+            };
+
+            started = true;
+            status = None;
+
+            // Receive the data subpacket containing the file metadata.
+            let meta = match self.receive_data_packet(zfile.encoding, buf) {
+                Ok((PacketType::ZCRCW, meta)) => meta,
+                Ok(_) => {
+                    count_error(&mut errors)?;
+                    self.send_znak()?;
+                    continue;
+                }
+                Err(error) if error.is_corruption() => {
+                    count_error(&mut errors)?;
+                    self.send_znak()?;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let Some(info) = FileInfo::parse(meta) else {
+                self.send_zskip()?;
+                continue;
+            };
+
+            let options = FileOptions::from_header(&zfile);
+            let supported = options.transport == TransportOption(0)
+                || options.transport == TransportOption::ZTLZW && cfg!(feature = "lzw");
+            if !supported {
+                self.send_zskip()?;
+                continue;
             }
-        }
+            let existing = sink.existing(&info);
+            let action = match self.policy.as_mut() {
+                Some(policy) => policy.decide(&info, &options, existing.as_ref()),
+                None => DefaultPolicy::default().decide(&info, &options, existing.as_ref()),
+            };
 
-        let encoding = FrameEncoding(self.recv_raw(TIMEOUT_DURATION)?);
-        if !matches!(
-            encoding,
-            FrameEncoding::HEX | FrameEncoding::BIN16 | FrameEncoding::BIN32
-        ) {
-            return Err(Error::InvalidFrameEncoding(encoding));
-        }
+            // Appended data is written after the existing file, while a resumed transfer
+            // continues at the existing file's offset.
+            let existing_size = existing.map_or(0, |existing| existing.size);
+            // Receiving the whole file replaces any existing one.
+            let create = if existing.is_some() {
+                OpenMode::Replace
+            } else {
+                OpenMode::Create
+            };
+            let (mode, base, pos) = match action {
+                Action::Skip => {
+                    self.send_zskip()?;
+                    continue;
+                }
+                Action::Accept => (create, 0, 0),
+                Action::CompareCrc => {
+                    let ours = u32::try_from(existing_size)
+                        .ok()
+                        .and_then(|len| Some((len, sink.crc32(&info, existing_size)?)));
+                    if let Some((len, ours)) = ours {
+                        if self.request_crc(len)? == ours {
+                            self.send_zskip()?;
+                            continue;
+                        }
+                    }
+                    (create, 0, 0)
+                }
+                Action::Append => (OpenMode::Append, existing_size, 0),
+                // Converted line ends leave the existing file shorter than the sender's offset.
+                Action::Resume if options.conversion == ConversionOption::ZCNL => (create, 0, 0),
+                Action::Resume => match u32::try_from(existing_size) {
+                    Ok(pos) => (OpenMode::Resume, 0, pos),
+                    Err(_) => (create, 0, 0),
+                },
+            };
 
-        let enc = match encoding {
-            FrameEncoding::HEX => {
-                if !prec_zpad {
-                    println!("hex frame with single zpad");
+            let expected_crc = if self.verify && pos == 0 {
+                Some(self.request_crc(0)?)
+            } else {
+                None
+            };
+
+            match sink.open(&info, mode) {
+                Ok(()) => (),
+                Err(SinkError::Refused) => {
+                    self.send_zskip()?;
+                    continue;
+                }
+                Err(error) => {
+                    self.send_zferror()?;
+                    return Err(Error::Sink(error));
                 }
-                RecvEnc::Hex
             }
-            _ => RecvEnc::Esc,
-        };
 
-        let frame_type = FrameType(self.recv(enc, TIMEOUT_DURATION)?);
-        let data = self.recv(enc, TIMEOUT_DURATION)?;
+            self.receive_file_data(sink, buf, &options, base, pos, expected_crc)?;
 
-        let frame = FrameHeader {
-            encoding,
-            r#type: frame_type,
-            data,
-        };
+            if let Err(error) = sink.close() {
+                self.send_zferror()?;
+                return Err(Error::Sink(error));
+            }
 
-        let (crc, our_crc) = if encoding == FrameEncoding::BIN32 {
-            let crc = u32::from_be_bytes(self.recv(enc, TIMEOUT_DURATION)?);
-            let our_crc = crc32(&bytemuck::bytes_of(&frame)[1..], None);
-            (crc, our_crc)
-        } else {
-            let crc = u16::from_be_bytes(self.recv(enc, TIMEOUT_DURATION)?) as u32;
-            let our_crc = crc16(&bytemuck::bytes_of(&frame)[1..], None) as u32;
-            (crc, our_crc)
-        };
+            // Ready for the next file.
+            errors = 0;
+            self.send_zrinit()?;
+        }
 
-        if crc != our_crc {
-            println!("frame crc error");
+        self.send_zfin()?;
+
+        // Wait briefly for the sender's "OO", but finish whether it arrives or not.
+        for _ in 0..2 {
+            match self.dev.recv_raw(Duration::from_secs(1)) {
+                Ok(b'O') => (),
+                // The remote end may be ready to receive our files instead.
+                Ok(byte) if reversible => {
+                    self.dev.unread(&[byte]);
+                    match self.receive_frame_header(Duration::from_secs(1)) {
+                        Ok(frame) if frame.r#type == FrameType::ZRINIT => return Ok(Some(frame)),
+                        Ok(_) | Err(Error::TimedOut) => break,
+                        Err(error) if error.is_corruption() => break,
+                        Err(error) => return Err(error),
+                    }
+                }
+                Ok(_) | Err(Error::TimedOut) => break,
+                Err(error) => return Err(error),
+            }
         }
 
-        if encoding == FrameEncoding::HEX {
-            if self.dev.recv(TIMEOUT_DURATION)? != CR {
-                println!("missing CR on hex frame");
+        Ok(None)
+    }
+
+    /// Handle a frame that isn't part of a file transfer, and may arrive at any time.
+    fn receive_aside(
+        &mut self,
+        frame: FrameHeader,
+        sink: &mut dyn Sink,
+        buf: &mut [u8],
+    ) -> Result<(), Error<D::Error>> {
+        match frame.r#type {
+            FrameType::ZCHALLENGE => self.send_zack(frame.count()),
+            FrameType::ZSINIT => {
+                // The sender's attention string is ignored, as we never interrupt it.
+                match self.receive_data_packet(frame.encoding, buf) {
+                    Ok(_) => (),
+                    Err(error) if error.is_corruption() => return self.send_znak(),
+                    Err(error) => return Err(error),
+                }
+                let mut flags = TransmitterFlags::from_bits_retain(frame.data[3]);
+                if !self.dev.high_escapes {
+                    flags.remove(TransmitterFlags::TESCHIGH);
+                }
+                let mode = EscapeMode::from_transmitter_flags(flags);
+                self.dev.escape = self.dev.escape.max(mode);
+                self.send_zack(1)
             }
-            if self.dev.recv(TIMEOUT_DURATION)? & 0x7f != LF {
-                println!("missing LF on hex frame");
+            FrameType::ZFREECNT => {
+                // Unknown free space is reported as unlimited.
+                let free = sink.free_space().unwrap_or(u64::MAX);
+                self.send_zack(u32::try_from(free).unwrap_or(u32::MAX))
             }
-            if !matches!(frame_type, FrameType::ZACK | FrameType::ZFIN)
-                && self.dev.recv(TIMEOUT_DURATION)? != XON
-            {
-                println!("missing XON on hex frame");
+            FrameType::ZSTDERR => {
+                let (packet_type, text) = self.receive_data_packet(frame.encoding, buf)?;
+                if let Some(handler) = self.events.as_mut() {
+                    handler(Event::Stderr(text));
+                }
+                match packet_type {
+                    PacketType::ZCRCQ | PacketType::ZCRCW => self.send_zack(frame.count()),
+                    _ => Ok(()),
+                }
             }
+            _ => Err(Error::UnexpectedFrame(frame)),
         }
+    }
 
-        // println!("rx frame: {encoding:?}, {frame_type:?}, {data:02x?}");
+    /// Receive the command following a ZCOMMAND header, run it, and reply with its status.
+    ///
+    /// A command the sender repeated, which ran with status `repeated`, is only acknowledged.
+    /// Returns `None` if the command was corrupted, and the sender has been asked to repeat it.
+    fn receive_command(
+        &mut self,
+        frame: FrameHeader,
+        buf: &mut [u8],
+        repeated: Option<u32>,
+    ) -> Result<Option<u32>, Error<D::Error>> {
+        let command = match self.receive_data_packet(frame.encoding, buf) {
+            Ok((PacketType::ZCRCW, command)) => command,
+            Ok(_) => {
+                self.send_znak()?;
+                return Ok(None);
+            }
+            Err(error) if error.is_corruption() => {
+                self.send_znak()?;
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+        if let Some(status) = repeated {
+            self.send_zcompl(status)?;
+            return Ok(Some(status));
+        }
+        let command = command.split(|b| *b == 0).next().unwrap_or_default();
+        let command = core::str::from_utf8(command).ok();
+
+        let flags = CommandFlags::from_bits_retain(frame.data[3]);
+        if flags.contains(CommandFlags::ZCACK1) {
+            self.send_zcompl(0)?;
+            if let (Some(handler), Some(command)) = (self.commands.as_mut(), command) {
+                handler.run(command);
+            }
+            return Ok(Some(0));
+        }
 
-        Ok(frame)
+        let status = match (self.commands.as_mut(), command) {
+            (Some(handler), Some(command)) => handler.run(command),
+            _ => COMMAND_NOT_FOUND,
+        };
+        self.send_zcompl(status)?;
+        Ok(Some(status))
     }
-}
 
-pub fn receive<D: SerialDevice>(dev: D, output: &mut [u8]) -> Result<usize, Error<D::Error>> {
-    let mut receiver = Receiver::new(dev);
-    let mut buf = [0; 0x1000];
-    let mut output_offset = 0;
-
-    // Use a shorter timeout for the first iteration of the loop, so we advertise
-    // our ZRINIT more frequently until a session is started.
-    let mut timeout = Duration::from_millis(500);
-
-    'main: loop {
-        // Receive the ZFILE header.
-        let zfile = loop {
-            receiver.send_zrinit()?;
-            let frame = match receiver.receive_frame_header(timeout) {
+    /// Receive the contents of a file, starting at `pos`, until ZEOF.
+    ///
+    /// Data at file offset `pos` is written to the sink at `base + pos`. If `expected_crc` is
+    /// set, the received data must match it.
+    fn receive_file_data(
+        &mut self,
+        sink: &mut dyn Sink,
+        buf: &mut [u8],
+        options: &FileOptions,
+        base: u64,
+        mut pos: u32,
+        expected_crc: Option<u32>,
+    ) -> Result<(), Error<D::Error>> {
+        let mut errors = 0;
+        let mut digest = CRC32.digest();
+
+        #[cfg(feature = "lzw")]
+        let mut decoder = (options.transport == TransportOption::ZTLZW).then(Decoder::new);
+        #[cfg(feature = "lzw")]
+        let mut plain = [0; MAX_SUBPACKET_LEN];
+
+        let text = options.conversion == ConversionOption::ZCNL;
+        let sparse = !text && options.extended.contains(ExtendedOptions::ZXSPARS);
+        // Where the next data goes in the sink, which falls behind `base + pos` once line ends
+        // are converted.
+        let mut offset = base + pos as u64;
+        // Whether a sparse sender may continue further on, after a run of zeros.
+        let mut can_skip = true;
+        let mut text_writer = TextWriter::default();
+
+        // Whether we're skipping the rest of a frame after an error, which may look like
+        // corrupted headers.
+        let mut skipping = false;
+
+        self.send_zrpos(pos)?;
+        loop {
+            let frame = match self.receive_frame_header(TIMEOUT_DURATION) {
                 Ok(frame) => frame,
-                Err(Error::TimedOut) => continue,
+                Err(error) if error.is_corruption() && skipping => continue,
+                Err(error) if error.is_corruption() => {
+                    count_error(&mut errors)?;
+                    self.send_zrpos(pos)?;
+                    continue;
+                }
                 Err(error) => return Err(error),
             };
-            match frame.r#type {
-                // Sender is requesting our ZRINIT header.
-                FrameType::ZRQINIT => continue,
-                // Begin file transfer.
-                FrameType::ZFILE => break frame,
-                // Finish session.
-                FrameType::ZFIN => break 'main,
-                _ => return Err(Error::UnexpectedFrame(frame)),
-            }
-        };
+            skipping = false;
 
-        // Now that we've begun a session, following loops should wait the normal amount
-        // of time before giving up.
-        timeout = TIMEOUT_DURATION;
+            // The sender skipped a run of zeros, which we leave as a hole.
+            if sparse
+                && can_skip
+                && matches!(frame.r#type, FrameType::ZDATA | FrameType::ZEOF)
+                && frame.count() > pos
+            {
+                let len = frame.count() - pos;
+                if let Err(error) = sink.hole(offset, len as u64) {
+                    self.send_zferror()?;
+                    return Err(Error::Sink(error));
+                }
+                if expected_crc.is_some() {
+                    update_zeros(&mut digest, len);
+                }
+                pos += len;
+                offset += len as u64;
+            }
 
-        // Receive the data subpacket containing the file metadata.
-        let (packet_type, _meta) = receiver.receive_data_packet(zfile.encoding, &mut buf)?;
-        if packet_type != PacketType::ZCRCW {
-            panic!("expected ZCRCW packet, got {packet_type:?}");
-        }
-        // for slice in meta.split(|b| *b == 0) {
-        //     if slice.is_empty() {
-        //         continue;
-        //     }
-        //     println!("meta: {}", core::str::from_utf8(slice).unwrap());
-        // }
-
-        let mut pos = 0;
-        receiver.send_zrpos(pos)?;
-        loop {
-            let frame = receiver.receive_frame_header(TIMEOUT_DURATION)?;
             match frame.r#type {
-                FrameType::ZDATA => (),
-                FrameType::ZEOF => break,
-                frame_type => panic!("unknown packet type: {frame_type:?}"),
+                FrameType::ZDATA if frame.count() == pos => {
+                    can_skip = false;
+                    #[cfg(feature = "lzw")]
+                    if let Some(decoder) = &mut decoder {
+                        decoder.reset();
+                    }
+                }
+                // The sender is at the wrong offset, any data that follows is skipped while
+                // we wait for the next header.
+                FrameType::ZDATA => {
+                    count_error(&mut errors)?;
+                    self.send_zrpos(pos)?;
+                    skipping = true;
+                    continue;
+                }
+                FrameType::ZEOF if frame.count() == pos => {
+                    if let Err(error) = text_writer.finish(sink, &mut offset) {
+                        self.send_zferror()?;
+                        return Err(Error::Sink(error));
+                    }
+                    if expected_crc.is_some_and(|crc| crc != digest.finalize()) {
+                        self.send_zferror()?;
+                        return Err(Error::VerificationFailed);
+                    }
+                    return Ok(());
+                }
+                // We haven't received the whole file, another ZDATA is coming.
+                FrameType::ZEOF => continue,
+                // A late answer to a repeated CRC request.
+                FrameType::ZCRC => continue,
+                // Our ZRPOS was lost, and the sender repeated its ZFILE.
+                FrameType::ZFILE => {
+                    self.send_zrpos(pos)?;
+                    continue;
+                }
+                _ => {
+                    match self.receive_aside(frame, sink, buf) {
+                        Ok(()) => (),
+                        Err(error) if error.is_corruption() => {
+                            count_error(&mut errors)?;
+                            self.send_zrpos(pos)?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                    continue;
+                }
             }
 
             loop {
-                let (packet_type, buf) = receiver.receive_data_packet(frame.encoding, &mut buf)?;
-
-                pos += buf.len() as u32;
-                assert!(output_offset + buf.len() <= output.len());
-                output[output_offset..][..buf.len()].copy_from_slice(buf);
-                output_offset += buf.len();
+                let (packet_type, data) = match self.receive_data_packet(frame.encoding, buf) {
+                    Ok(packet) => packet,
+                    Err(error) if error.is_corruption() => {
+                        count_error(&mut errors)?;
+                        self.send_zrpos(pos)?;
+                        skipping = true;
+                        break;
+                    }
+                    Err(error) => return Err(error),
+                };
+                #[cfg(feature = "lzw")]
+                let data = match &mut decoder {
+                    Some(decoder) => match decoder.decode(data, &mut plain) {
+                        Some(len) => &plain[..len],
+                        None => {
+                            count_error(&mut errors)?;
+                            self.send_zrpos(pos)?;
+                            skipping = true;
+                            break;
+                        }
+                    },
+                    None => data,
+                };
+
+                let written = if text {
+                    text_writer.write(sink, &mut offset, data)
+                } else {
+                    write(sink, &mut offset, data)
+                };
+                if let Err(error) = written {
+                    self.send_zferror()?;
+                    return Err(Error::Sink(error));
+                }
+                digest.update(data);
+                pos += data.len() as u32;
+                errors = 0;
 
                 match packet_type {
                     PacketType::ZCRCG => continue,
-                    PacketType::ZCRCE => break,
-                    _ => panic!("unknown packet type: {packet_type:?}"),
+                    PacketType::ZCRCQ => self.send_zack(pos)?,
+                    PacketType::ZCRCW => {
+                        self.send_zack(pos)?;
+                        can_skip = true;
+                        break;
+                    }
+                    _ => break,
                 }
             }
         }
     }
+}
+
+/// Writes the data of a ZCNL text file, with CR LF line ends converted to LF.
+///
+/// Other carriage returns are kept, and anything after a CP/M end of file is dropped.
+#[derive(Default)]
+struct TextWriter {
+    // A CR that ended the last subpacket, written unless the next byte is LF.
+    cr: bool,
+    end: bool,
+}
+
+impl TextWriter {
+    /// Write `data` at `offset`, advancing it by what was written.
+    fn write(
+        &mut self,
+        sink: &mut dyn Sink,
+        offset: &mut u64,
+        data: &[u8],
+    ) -> Result<(), SinkError> {
+        if self.end || data.is_empty() {
+            return Ok(());
+        }
+        let data = match data.iter().position(|byte| *byte == SUB) {
+            Some(end) => {
+                self.end = true;
+                &data[..end]
+            }
+            None => data,
+        };
+        if core::mem::take(&mut self.cr) && data.first() != Some(&LF) {
+            write(sink, offset, &[CR])?;
+        }
+        let mut start = 0;
+        for (i, _) in data.iter().enumerate().filter(|(_, byte)| **byte == CR) {
+            match data.get(i + 1) {
+                Some(&LF) => (),
+                Some(_) => continue,
+                None => self.cr = true,
+            }
+            write(sink, offset, &data[start..i])?;
+            start = i + 1;
+        }
+        write(sink, offset, &data[start..])?;
+        if self.end {
+            self.finish(sink, offset)?;
+        }
+        Ok(())
+    }
 
-    receiver.send_zfin()?;
-    assert!(receiver.recv::<u8>(RecvEnc::Raw, TIMEOUT_DURATION)? == b'O');
-    assert!(receiver.recv::<u8>(RecvEnc::Raw, TIMEOUT_DURATION)? == b'O');
+    /// Write a CR left at the end of the file.
+    fn finish(&mut self, sink: &mut dyn Sink, offset: &mut u64) -> Result<(), SinkError> {
+        if core::mem::take(&mut self.cr) {
+            write(sink, offset, &[CR])?;
+        }
+        Ok(())
+    }
+}
 
-    Ok(output_offset)
+fn write(sink: &mut dyn Sink, offset: &mut u64, data: &[u8]) -> Result<(), SinkError> {
+    sink.write(*offset, data)?;
+    *offset += data.len() as u64;
+    Ok(())
+}
+
+fn update_zeros(digest: &mut Digest<u32>, len: u32) {
+    let zeros = [0; 512];
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(zeros.len() as u32);
+        digest.update(&zeros[..n as usize]);
+        done += n;
+    }
 }
@@ -0,0 +1,679 @@
+#[cfg(feature = "lzw")]
+use crate::lzw::Encoder;
+use crate::{
+    proto::{
+        consts::{CAN, XOFF, XON, ZPAD},
+        CommandFlags, ConversionOption, EscapeMode, ExtendedOptions, FileInfo, FileOptions,
+        FrameEncoding, FrameHeader, FrameType, PacketType, ReceiverCapabilities, TransportOption,
+    },
+    recv::Receiver,
+    source::Source,
+    Device, Error, SerialDevice, CRC32, MAX_SUBPACKET_LEN,
+};
+use core::time::Duration;
+
+const SENDER_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_ERRORS: usize = 10;
+
+/// Size of the data subpackets we send, unless configured otherwise.
+const SUBPACKET_LEN: usize = 1024;
+
+/// Size the data subpackets are never shrunk below.
+const MIN_SUBPACKET_LEN: usize = 32;
+
+/// Number of subpackets sent without errors before the subpacket size is doubled again.
+///
+/// This doubles after each error, up to `MAX_CLEAN_RUN`, so the size doesn't keep growing back
+/// to a size the link can't handle.
+const CLEAN_RUN: usize = 8;
+const MAX_CLEAN_RUN: usize = 256;
+
+/// How file data is paced, see "File Transmission Strategies" in the ZMODEM specification.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Stream data nonstop, sampling the reverse channel for error headers between subpackets.
+    Streaming,
+    /// Stream data nonstop, stopping as soon as the receiver sends anything, like its attention
+    /// sequence.
+    ReverseInterrupt,
+    /// Stream data, with at most this many bytes not acknowledged by the receiver.
+    Window(u32),
+    /// Send data in segments of this many bytes, waiting for the receiver after each one.
+    Segmented(u32),
+}
+
+/// What happened to an offered file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Transfer {
+    Sent,
+    /// The receiver refused the file with ZSKIP.
+    Skipped,
+}
+
+pub struct Sender<D: SerialDevice> {
+    dev: Device<D>,
+    capabilities: ReceiverCapabilities,
+    buffer_len: u16,
+    strategy: Option<Strategy>,
+    flow_control: bool,
+    // The escape mode we ask the receiver for, we use the receiver's if it is stricter.
+    escape: EscapeMode,
+    max_subpacket_len: usize,
+    // Size of the subpackets we currently send, shrunk after errors.
+    subpacket_len: usize,
+    clean_run: usize,
+    // Number of the last command sent, so the receiver can tell a repeated command apart.
+    command: u32,
+}
+
+fn count_error<D>(errors: &mut usize) -> Result<(), Error<D>> {
+    *errors += 1;
+    if *errors >= MAX_ERRORS {
+        return Err(Error::TooManyErrors);
+    }
+    Ok(())
+}
+
+impl<D: SerialDevice> Sender<D> {
+    pub fn new(dev: D) -> Sender<D> {
+        Self::from_device(Device::new(dev))
+    }
+
+    pub(crate) fn from_device(dev: Device<D>) -> Sender<D> {
+        Self {
+            escape: dev.escape,
+            dev,
+            capabilities: ReceiverCapabilities::empty(),
+            buffer_len: 0,
+            strategy: None,
+            flow_control: true,
+            max_subpacket_len: SUBPACKET_LEN,
+            subpacket_len: SUBPACKET_LEN,
+            clean_run: CLEAN_RUN,
+            command: 0,
+        }
+    }
+
+    /// Send data subpackets of up to `len` bytes, up to [`MAX_SUBPACKET_LEN`].
+    ///
+    /// The subpacket size is halved after each error, and doubled again after a run of
+    /// subpackets without errors.
+    pub fn set_max_subpacket_len(mut self, len: usize) -> Self {
+        self.max_subpacket_len = len.clamp(MIN_SUBPACKET_LEN, MAX_SUBPACKET_LEN);
+        self.subpacket_len = self.max_subpacket_len;
+        self
+    }
+
+    /// Use `strategy` instead of picking one from the receiver's capabilities.
+    pub fn set_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    /// Whether to pause on XOFF from the receiver until XON. Enabled by default.
+    pub fn set_flow_control(mut self, flow_control: bool) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// Ask the receiver to escape the bytes `mode` calls for, for links that aren't 8-bit clean.
+    ///
+    /// `EightBit` also enables the ZHIGH escape, as with
+    /// [`set_high_escapes`](Self::set_high_escapes).
+    pub fn set_escape(mut self, mode: EscapeMode) -> Self {
+        self.escape = mode;
+        self.dev.escape = mode;
+        self.dev.high_escapes |= mode == EscapeMode::EightBit;
+        self
+    }
+
+    /// Use the [ZHIGH escape](crate::proto#the-zhigh-escape), a private extension, with
+    /// receivers that advertise it. Disabled by default.
+    pub fn set_high_escapes(mut self, high_escapes: bool) -> Self {
+        self.dev.high_escapes = high_escapes;
+        self
+    }
+
+    /// Continue a session in which the remote end has sent `zrinit`, so it is ready to
+    /// receive files.
+    pub(crate) fn from_zrinit(dev: Device<D>, zrinit: &FrameHeader) -> Sender<D> {
+        let mut sender = Self::from_device(dev);
+        sender.set_capabilities(zrinit);
+        sender
+    }
+
+    /// Hand the session over to the remote end, to receive the files it sends back.
+    ///
+    /// After the ZFIN exchange that ends our half of the session, we send our ZRINIT instead
+    /// of "OO", so this only works with a remote end serving the session (see
+    /// [`Receiver::serve`]).
+    pub fn into_receiver<'a>(mut self) -> Result<Receiver<'a, D>, Error<D::Error>> {
+        let mut errors = 0;
+        loop {
+            self.dev
+                .send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZFIN))?;
+            match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                Ok(frame) if frame.r#type == FrameType::ZFIN => break,
+                Ok(_) | Err(Error::TimedOut) => count_error(&mut errors)?,
+                Err(error) if error.is_corruption() => count_error(&mut errors)?,
+                Err(error) => return Err(error),
+            }
+        }
+        let mut receiver = Receiver::from_device(self.dev);
+        receiver.send_zrinit()?;
+        Ok(receiver)
+    }
+
+    fn set_capabilities(&mut self, zrinit: &FrameHeader) {
+        (self.capabilities, self.buffer_len) = zrinit.zrinit();
+        let mut capabilities = self.capabilities;
+        if !self.dev.high_escapes {
+            capabilities.remove(ReceiverCapabilities::ESCHIGH);
+        }
+        self.dev.escape = self.escape.max(EscapeMode::from_capabilities(capabilities));
+    }
+
+    fn strategy(&self) -> Strategy {
+        let full_duplex = self
+            .capabilities
+            .contains(ReceiverCapabilities::CANFDX | ReceiverCapabilities::CANOVIO);
+        match self.strategy {
+            Some(Strategy::Window(window)) => Strategy::Window(window.max(1)),
+            Some(Strategy::Segmented(segment)) => Strategy::Segmented(segment.max(1)),
+            Some(strategy) => strategy,
+            None if self.buffer_len > 0 => Strategy::Segmented(self.buffer_len.into()),
+            None if full_duplex => Strategy::Streaming,
+            // The receiver can't tell us about errors while receiving, so wait for it after
+            // each subpacket.
+            None => Strategy::Segmented(SUBPACKET_LEN as u32),
+        }
+    }
+
+    /// Encoding of our binary headers, depending on what the receiver supports.
+    fn encoding(&self) -> FrameEncoding {
+        if self.capabilities.contains(ReceiverCapabilities::CANFC32) {
+            FrameEncoding::BIN32
+        } else {
+            FrameEncoding::BIN16
+        }
+    }
+
+    fn send_header(&mut self, r#type: FrameType, count: u32) -> Result<(), Error<D::Error>> {
+        let encoding = self.encoding();
+        self.dev
+            .send_frame(FrameHeader::new(encoding, r#type).set_count(count))
+    }
+
+    fn send_zrqinit(&mut self) -> Result<(), Error<D::Error>> {
+        self.dev
+            .send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZRQINIT))
+    }
+
+    /// Start a session, and wait for the receiver's ZRINIT.
+    pub fn start(&mut self) -> Result<(), Error<D::Error>> {
+        // Start the receiving program, in case the other end is a shell.
+        for byte in b"rz\r" {
+            self.dev.send(*byte)?;
+        }
+
+        let mut errors = 0;
+        self.send_zrqinit()?;
+        loop {
+            let frame = match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                Ok(frame) => frame,
+                Err(error) if error.is_corruption() || matches!(error, Error::TimedOut) => {
+                    count_error(&mut errors)?;
+                    self.send_zrqinit()?;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            match frame.r#type {
+                FrameType::ZRINIT => {
+                    self.set_capabilities(&frame);
+                    if self.escape != EscapeMode::Clean {
+                        self.send_zsinit()?;
+                    }
+                    return Ok(());
+                }
+                // An echo of our own ZRQINIT.
+                FrameType::ZRQINIT => continue,
+                // The receiver checking that we're a real sender.
+                FrameType::ZCHALLENGE => self.dev.send_frame(
+                    FrameHeader::new(FrameEncoding::HEX, FrameType::ZACK).set_count(frame.count()),
+                )?,
+                _ => {
+                    count_error(&mut errors)?;
+                    self.send_zrqinit()?;
+                }
+            }
+        }
+    }
+
+    /// Tell the receiver which bytes to escape in what it sends us.
+    fn send_zsinit(&mut self) -> Result<(), Error<D::Error>> {
+        let mut errors = 0;
+        'send: loop {
+            let encoding = self.encoding();
+            let flags = self.escape.transmitter_flags();
+            self.dev.send_frame(
+                FrameHeader::new(encoding, FrameType::ZSINIT).set_flags(flags.bits().into()),
+            )?;
+            // We don't have an attention string.
+            self.dev
+                .send_data_packet(encoding, PacketType::ZCRCW, &[0])?;
+
+            loop {
+                match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                    Ok(frame) if frame.r#type == FrameType::ZACK => return Ok(()),
+                    // A repeated ZRINIT, our ZSINIT may still be on its way.
+                    Ok(frame) if frame.r#type == FrameType::ZRINIT => continue,
+                    Ok(_) | Err(Error::TimedOut) => (),
+                    Err(error) if error.is_corruption() => (),
+                    Err(error) => return Err(error),
+                }
+                count_error(&mut errors)?;
+                continue 'send;
+            }
+        }
+    }
+
+    /// Offer a file to the receiver, and send its contents unless it is refused.
+    pub fn send_file(
+        &mut self,
+        info: &FileInfo,
+        options: &FileOptions,
+        source: &mut dyn Source,
+    ) -> Result<Transfer, Error<D::Error>> {
+        let mut meta = [0; SUBPACKET_LEN];
+        let meta_len = info.encode(&mut meta).ok_or(Error::OutputFull)?;
+        let mut errors = 0;
+
+        // Only compress if the receiver can decompress.
+        let mut options = *options;
+        let compress = cfg!(feature = "lzw")
+            && options.transport == TransportOption::ZTLZW
+            && self.capabilities.contains(ReceiverCapabilities::CANLZW);
+        if !compress {
+            options.transport = TransportOption(0);
+        }
+
+        let pos = 'offer: loop {
+            let encoding = self.encoding();
+            self.dev.send_frame(
+                FrameHeader::new(encoding, FrameType::ZFILE).set_flags(options.flags()),
+            )?;
+            self.dev
+                .send_data_packet(encoding, PacketType::ZCRCW, &meta[..meta_len])?;
+
+            loop {
+                let frame = match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                    Ok(frame) => frame,
+                    Err(error) if error.is_corruption() || matches!(error, Error::TimedOut) => {
+                        count_error(&mut errors)?;
+                        continue 'offer;
+                    }
+                    Err(error) => return Err(error),
+                };
+                match frame.r#type {
+                    FrameType::ZRPOS => break 'offer frame.count(),
+                    FrameType::ZSKIP => return Ok(Transfer::Skipped),
+                    FrameType::ZCRC => {
+                        let crc = self.file_crc(source, frame.count())?;
+                        self.send_header(FrameType::ZCRC, crc)?;
+                    }
+                    // A repeated ZRINIT, our ZFILE may still be on its way.
+                    FrameType::ZRINIT => continue,
+                    // The receiver got a corrupted ZFILE.
+                    FrameType::ZNAK => {
+                        count_error(&mut errors)?;
+                        continue 'offer;
+                    }
+                    FrameType::ZFERROR | FrameType::ZABORT | FrameType::ZFIN | FrameType::ZCAN => {
+                        return Err(Error::Aborted)
+                    }
+                    _ => return Err(Error::UnexpectedFrame(frame)),
+                }
+            }
+        };
+
+        // Runs of zeros are skipped, unless the receiver converts line ends.
+        let sparse = options.extended.contains(ExtendedOptions::ZXSPARS)
+            && options.conversion != ConversionOption::ZCNL;
+        self.send_data(source, pos, compress, sparse)
+    }
+
+    /// Have the receiver run `command`, returning its exit status.
+    ///
+    /// With `ack_first`, the receiver acknowledges the command before running it, and the
+    /// returned status is always 0.
+    pub fn command(&mut self, command: &str, ack_first: bool) -> Result<u32, Error<D::Error>> {
+        let mut buf = [0; SUBPACKET_LEN];
+        let len = command.len();
+        if len >= buf.len() {
+            return Err(Error::OutputFull);
+        }
+        buf[..len].copy_from_slice(command.as_bytes());
+
+        let mut flags = CommandFlags::empty();
+        flags.set(CommandFlags::ZCACK1, ack_first);
+        // The command number goes in the ZP0-ZP2 bytes, the flags in ZF0 overlap ZP3.
+        self.command = self.command % 0xff_ffff + 1;
+        let count = self.command | u32::from(flags.bits()) << 24;
+        let mut errors = 0;
+
+        // Anything left from earlier, such as a repeated ZCOMPL, isn't the answer to this command.
+        while self.dev.recv(Duration::ZERO).is_ok() {}
+
+        'command: loop {
+            let encoding = self.encoding();
+            self.dev
+                .send_frame(FrameHeader::new(encoding, FrameType::ZCOMMAND).set_count(count))?;
+            // The command is NUL-terminated.
+            self.dev
+                .send_data_packet(encoding, PacketType::ZCRCW, &buf[..len + 1])?;
+
+            // The command is repeated if its ZCOMPL doesn't arrive in time, the receiver only
+            // runs it once and repeats its ZCOMPL.
+            loop {
+                let frame = match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                    Ok(frame) => frame,
+                    Err(error) if error.is_corruption() || matches!(error, Error::TimedOut) => {
+                        count_error(&mut errors)?;
+                        continue 'command;
+                    }
+                    Err(error) => return Err(error),
+                };
+                match frame.r#type {
+                    FrameType::ZCOMPL => return Ok(frame.count()),
+                    FrameType::ZRINIT => continue,
+                    FrameType::ZNAK => {
+                        count_error(&mut errors)?;
+                        continue 'command;
+                    }
+                    FrameType::ZFERROR | FrameType::ZABORT | FrameType::ZFIN | FrameType::ZCAN => {
+                        return Err(Error::Aborted)
+                    }
+                    _ => return Err(Error::UnexpectedFrame(frame)),
+                }
+            }
+        }
+    }
+
+    /// CRC-32 of the first `len` bytes of the file, or of the whole file if `len` is 0.
+    fn file_crc(&mut self, source: &mut dyn Source, len: u32) -> Result<u32, Error<D::Error>> {
+        let mut buf = [0; SUBPACKET_LEN];
+        let mut digest = CRC32.digest();
+        let mut pos = 0;
+        while len == 0 || pos < len {
+            let chunk = match len {
+                0 => buf.len(),
+                _ => buf.len().min((len - pos) as usize),
+            };
+            let read = source
+                .read(pos as u64, &mut buf[..chunk])
+                .map_err(Error::Source)?;
+            digest.update(&buf[..read]);
+            pos += read as u32;
+            if read < chunk {
+                break;
+            }
+        }
+        Ok(digest.finalize())
+    }
+
+    /// Receive the receiver's response to our data.
+    fn receive_response(&mut self, errors: &mut usize) -> Result<FrameHeader, Error<D::Error>> {
+        loop {
+            match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                Ok(frame) => return Ok(frame),
+                Err(error) if error.is_corruption() || matches!(error, Error::TimedOut) => {
+                    count_error(errors)?;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Whether the receiver has interrupted our data stream, usually with an error header.
+    fn interrupted(&mut self, strategy: Strategy) -> Result<bool, Error<D::Error>> {
+        let mut timeout = Duration::ZERO;
+        loop {
+            let byte = match self.dev.recv(timeout) {
+                Ok(byte) => byte,
+                Err(Error::TimedOut) => return Ok(false),
+                Err(error) => return Err(error),
+            };
+            match byte {
+                ZPAD | CAN => {
+                    self.dev.unread(&[byte]);
+                    return Ok(true);
+                }
+                // Wait for XON, or anything else from the receiver.
+                _ if byte & 0x7f == XOFF && self.flow_control => timeout = SENDER_TIMEOUT,
+                _ if byte & 0x7f == XOFF || byte & 0x7f == XON => timeout = Duration::ZERO,
+                _ if strategy == Strategy::ReverseInterrupt => return Ok(true),
+                // Line noise.
+                _ => continue,
+            }
+        }
+    }
+
+    /// Send the file contents from `pos`, until the receiver acknowledges our ZEOF.
+    ///
+    /// With `compress`, the data is sent compressed with ZTLZW. With `sparse`, each run of
+    /// zeros ends the current segment, and the next frame starts after it.
+    fn send_data(
+        &mut self,
+        source: &mut dyn Source,
+        mut pos: u32,
+        compress: bool,
+        sparse: bool,
+    ) -> Result<Transfer, Error<D::Error>> {
+        let mut buf = [0; MAX_SUBPACKET_LEN];
+        #[cfg(feature = "lzw")]
+        let mut encoder = compress.then(Encoder::new);
+        #[cfg(feature = "lzw")]
+        let mut packed = [0; MAX_SUBPACKET_LEN];
+        let mut errors = 0;
+        let mut clean = 0;
+        let mut synced = pos;
+        // Whether the receiver asked for an offset, which is sent even if it's in a run of
+        // zeros, in case the receiver doesn't handle sparse files.
+        let mut resync = true;
+        let strategy = self.strategy();
+        let window = match strategy {
+            Strategy::Window(window) => window,
+            _ => u32::MAX,
+        };
+
+        loop {
+            let encoding = self.encoding();
+            if sparse && !resync {
+                pos = skip_zeros(source, &mut buf[..self.subpacket_len], pos)?;
+            }
+            resync = false;
+            let start = pos;
+            self.send_header(FrameType::ZDATA, pos)?;
+            #[cfg(feature = "lzw")]
+            if let Some(encoder) = &mut encoder {
+                encoder.reset();
+            }
+
+            // Last offset acknowledged by the receiver, and the last one it was asked to.
+            let mut acked = pos;
+            let mut requested = pos;
+
+            // Send the frame, until the end of the file or a response from the receiver.
+            let response = 'frame: loop {
+                // Compressed data can be up to 1.5 times as large.
+                let limit = if compress {
+                    self.subpacket_len * 2 / 3
+                } else {
+                    self.subpacket_len
+                };
+                let want = match strategy {
+                    Strategy::Segmented(segment) => limit.min((segment - (pos - acked)) as usize),
+                    _ => limit,
+                };
+                let len = source
+                    .read(pos as u64, &mut buf[..want])
+                    .map_err(Error::Source)?;
+                let eof = len < want;
+
+                // End the segment before a run of zeros, the next frame skips it.
+                if sparse && !eof && pos > start && buf[..len].iter().all(|byte| *byte == 0) {
+                    self.dev
+                        .send_data_packet(encoding, PacketType::ZCRCW, &[])?;
+                    break Some(self.receive_response(&mut errors)?);
+                }
+
+                let end = pos + len as u32;
+                let packet_type = match strategy {
+                    _ if eof => PacketType::ZCRCE,
+                    Strategy::Window(window) if end - requested >= (window / 4).max(1) => {
+                        PacketType::ZCRCQ
+                    }
+                    Strategy::Segmented(segment) if end - acked >= segment => PacketType::ZCRCW,
+                    _ => PacketType::ZCRCG,
+                };
+                #[cfg(feature = "lzw")]
+                let data = match &mut encoder {
+                    Some(encoder) => {
+                        let len = encoder.encode(&buf[..len], &mut packed);
+                        &packed[..len]
+                    }
+                    None => &buf[..len],
+                };
+                #[cfg(not(feature = "lzw"))]
+                let data = &buf[..len];
+                self.dev.send_data_packet(encoding, packet_type, data)?;
+                pos = end;
+
+                clean += 1;
+                if clean >= self.clean_run {
+                    self.subpacket_len = (self.subpacket_len * 2).min(self.max_subpacket_len);
+                    clean = 0;
+                }
+
+                match packet_type {
+                    PacketType::ZCRCE => break None,
+                    PacketType::ZCRCW => break Some(self.receive_response(&mut errors)?),
+                    PacketType::ZCRCQ => requested = pos,
+                    _ => (),
+                }
+                if let Strategy::Segmented(_) = strategy {
+                    continue;
+                }
+
+                // Wait for acknowledgements while the window is full, and handle any other
+                // response from the receiver.
+                while pos - acked >= window || self.interrupted(strategy)? {
+                    let frame = self.receive_response(&mut errors)?;
+                    if frame.r#type != FrameType::ZACK {
+                        // End the frame, the receiver ignores data until our next header.
+                        self.dev
+                            .send_data_packet(encoding, PacketType::ZCRCE, &[])?;
+                        break 'frame Some(frame);
+                    }
+                    acked = acked.max(frame.count());
+                }
+            };
+
+            let frame = match response {
+                Some(frame) => frame,
+                None => self.send_eof(pos, &mut errors)?,
+            };
+            match frame.r#type {
+                // The receiver is ready for the next segment.
+                FrameType::ZACK => (),
+                FrameType::ZRPOS => {
+                    // Only give up if the receiver keeps failing at the same offset.
+                    if frame.count() > synced {
+                        errors = 0;
+                    }
+                    count_error(&mut errors)?;
+                    synced = frame.count();
+                    pos = frame.count();
+                    resync = true;
+                    self.subpacket_len = (self.subpacket_len / 2).max(MIN_SUBPACKET_LEN);
+                    self.clean_run = (self.clean_run * 2).min(MAX_CLEAN_RUN);
+                    clean = 0;
+                }
+                FrameType::ZRINIT => return Ok(Transfer::Sent),
+                FrameType::ZSKIP => return Ok(Transfer::Skipped),
+                FrameType::ZFERROR | FrameType::ZABORT | FrameType::ZFIN | FrameType::ZCAN => {
+                    return Err(Error::Aborted)
+                }
+                _ => return Err(Error::UnexpectedFrame(frame)),
+            }
+        }
+    }
+
+    /// Send ZEOF at `pos`, and return the receiver's response.
+    fn send_eof(&mut self, pos: u32, errors: &mut usize) -> Result<FrameHeader, Error<D::Error>> {
+        self.send_header(FrameType::ZEOF, pos)?;
+        loop {
+            let frame = match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                Ok(frame) => frame,
+                Err(error) if error.is_corruption() || matches!(error, Error::TimedOut) => {
+                    count_error(errors)?;
+                    self.send_header(FrameType::ZEOF, pos)?;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            match frame.r#type {
+                // Acknowledgement of data we've already sent.
+                FrameType::ZACK => continue,
+                _ => return Ok(frame),
+            }
+        }
+    }
+
+    /// End the session.
+    ///
+    /// Errors while waiting for the receiver's ZFIN are ignored, as all files have been sent.
+    pub fn finish(mut self) -> Result<(), Error<D::Error>> {
+        for _ in 0..MAX_ERRORS {
+            self.dev
+                .send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZFIN))?;
+            match self.dev.receive_frame_header(SENDER_TIMEOUT) {
+                Ok(frame) if frame.r#type == FrameType::ZFIN => {
+                    self.dev.send(b'O')?;
+                    self.dev.send(b'O')?;
+                    return Ok(());
+                }
+                Ok(_) | Err(Error::TimedOut) => (),
+                Err(error) if error.is_corruption() => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Find the end of the run of zeros at `pos`, reading a `buf` at a time.
+fn skip_zeros<D>(source: &mut dyn Source, buf: &mut [u8], mut pos: u32) -> Result<u32, Error<D>> {
+    loop {
+        let len = source.read(pos as u64, buf).map_err(Error::Source)?;
+        if buf[..len].iter().any(|byte| *byte != 0) {
+            return Ok(pos);
+        }
+        pos += len as u32;
+        if len < buf.len() {
+            return Ok(pos);
+        }
+    }
+}
+
+/// Send `data` as a single file named `name`.
+pub fn send<D: SerialDevice>(dev: D, name: &str, mut data: &[u8]) -> Result<(), Error<D::Error>> {
+    let mut info = FileInfo::new(name);
+    info.size = Some(data.len() as u64);
+
+    let mut sender = Sender::new(dev);
+    sender.start()?;
+    sender.send_file(&info, &FileOptions::default(), &mut data)?;
+    sender.finish()
+}
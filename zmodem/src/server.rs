@@ -0,0 +1,27 @@
+use crate::{recv::Receiver, send::Sender, sink::Sink, Error, SerialDevice};
+
+/// How a served session continues.
+pub enum Served<D: SerialDevice> {
+    /// The remote end finished the session.
+    Finished,
+    /// The remote end is ready to receive files, send them and finish the session with the
+    /// sender. [`Sender::start`] must not be called, the session is already started.
+    Send(Sender<D>),
+}
+
+impl<D: SerialDevice> Receiver<'_, D> {
+    /// Serve a session in both directions.
+    ///
+    /// Files are received into `sink` and commands are run, until the remote end sends ZFIN.
+    /// The remote end then either finishes the session, or sends its ZRINIT to receive files
+    /// from us in turn (see [`Sender::into_receiver`]).
+    pub fn serve(mut self, sink: &mut dyn Sink) -> Result<Served<D>, Error<D::Error>> {
+        match self.receive_session(sink, true)? {
+            None => Ok(Served::Finished),
+            Some(zrinit) => Ok(Served::Send(Sender::from_zrinit(
+                self.into_device(),
+                &zrinit,
+            ))),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::proto::FileInfo;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SinkError {
+    /// There is no room left for the data.
+    Full,
+    /// The sink refused to open the file.
+    Refused,
+    /// The underlying storage failed.
+    Io,
+}
+
+/// A file the sink already has under the name of the incoming file.
+#[derive(Clone, Copy, Debug)]
+pub struct ExistingFile {
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OpenMode {
+    /// Create the file, which doesn't exist yet.
+    Create,
+    /// Create the file, replacing the existing one.
+    Replace,
+    /// Keep the existing contents, writes start at the existing end of the file.
+    Append,
+    /// Keep the existing contents, the sender continues from where a previous transfer stopped.
+    Resume,
+}
+
+/// Destination for received files.
+pub trait Sink {
+    /// Look up an existing file with the same name as `info`.
+    fn existing(&mut self, info: &FileInfo) -> Option<ExistingFile>;
+
+    /// CRC-32 of the first `len` bytes of the existing file with the same name as `info`.
+    ///
+    /// Used to skip unchanged files, sinks that can't read back their files return `None`.
+    fn crc32(&mut self, _info: &FileInfo, _len: u64) -> Option<u32> {
+        None
+    }
+
+    /// Number of bytes that can still be received, if known.
+    fn free_space(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// Begin receiving a file.
+    fn open(&mut self, info: &FileInfo, mode: OpenMode) -> Result<(), SinkError>;
+
+    /// Write `data` at `offset` in the open file.
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), SinkError>;
+
+    /// Fill `len` bytes at `offset` in the open file with zeros, skipped by a sparse transfer.
+    ///
+    /// Sinks that support sparse files can seek over them instead of writing the zeros.
+    fn hole(&mut self, offset: u64, len: u64) -> Result<(), SinkError> {
+        let zeros = [0; 512];
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(zeros.len() as u64) as usize;
+            self.write(offset + done, &zeros[..n])?;
+            done += n as u64;
+        }
+        Ok(())
+    }
+
+    /// Finish receiving the open file.
+    fn close(&mut self) -> Result<(), SinkError>;
+}
+
+/// A sink that stores all files back to back in a single buffer.
+pub struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    // Start of the file currently being received.
+    start: usize,
+    len: usize,
+}
+
+impl<'a> SliceSink<'a> {
+    pub fn new(buf: &'a mut [u8]) -> SliceSink<'a> {
+        Self {
+            buf,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Total number of bytes received.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Sink for SliceSink<'_> {
+    fn existing(&mut self, _info: &FileInfo) -> Option<ExistingFile> {
+        None
+    }
+
+    fn free_space(&mut self) -> Option<u64> {
+        Some((self.buf.len() - self.len) as u64)
+    }
+
+    fn open(&mut self, _info: &FileInfo, _mode: OpenMode) -> Result<(), SinkError> {
+        self.start = self.len;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), SinkError> {
+        let offset = usize::try_from(offset).map_err(|_| SinkError::Full)?;
+        let end = self.start + offset + data.len();
+        if end > self.buf.len() {
+            return Err(SinkError::Full);
+        }
+        self.buf[self.start + offset..end].copy_from_slice(data);
+        self.len = self.len.max(end);
+        Ok(())
+    }
+
+    fn hole(&mut self, offset: u64, len: u64) -> Result<(), SinkError> {
+        let offset = usize::try_from(offset).map_err(|_| SinkError::Full)?;
+        let len = usize::try_from(len).map_err(|_| SinkError::Full)?;
+        let end = self.start + offset + len;
+        if end > self.buf.len() {
+            return Err(SinkError::Full);
+        }
+        self.buf[self.start + offset..end].fill(0);
+        self.len = self.len.max(end);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SourceError {
+    /// The file could not be read.
+    Read,
+}
+
+/// Origin of sent files.
+pub trait Source {
+    /// Read file data at `offset` into `buf`, returning the number of bytes read.
+    ///
+    /// Fewer than `buf.len()` bytes are only returned at the end of the file.
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, SourceError>;
+}
+
+impl Source for &[u8] {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, SourceError> {
+        let offset = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(self.len());
+        let len = buf.len().min(self.len() - offset);
+        buf[..len].copy_from_slice(&self[offset..][..len]);
+        Ok(len)
+    }
+}
@@ -0,0 +1,272 @@
+//! A [`SerialDevice`] over a telnet connection, for boards behind network console servers.
+//!
+//! Binary mode is negotiated in both directions, IAC bytes in the data are doubled, and any
+//! other option the server asks for is refused.
+
+use crate::SerialDevice;
+use core::time::Duration;
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Instant,
+    vec::Vec,
+};
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+const BINARY: u8 = 0;
+const SGA: u8 = 3;
+
+const CR: u8 = b'\r';
+const NUL: u8 = 0;
+
+/// How long to wait for the server to answer our binary mode requests.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Output is sent once this much is buffered, or before waiting for input.
+const OUTPUT_LEN: usize = 4096;
+
+/// State of an option on one side of the connection (RFC 1143, without the queue).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Q {
+    No,
+    Yes,
+    /// We asked for it, and are waiting for the answer.
+    WantYes,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Data,
+    /// After a CR, when the server isn't sending binary, which is followed by NUL or LF.
+    Cr,
+    Iac,
+    /// After IAC and a negotiation command.
+    Option(u8),
+    /// Inside a subnegotiation, which we ignore.
+    Sub,
+    SubIac,
+}
+
+pub struct TelnetDevice {
+    stream: TcpStream,
+    input: [u8; 4096],
+    start: usize,
+    end: usize,
+    output: Vec<u8>,
+    state: State,
+    // Options we support, as [BINARY, SGA], enabled by us (local) or the server (remote).
+    local: [Q; 2],
+    remote: [Q; 2],
+}
+
+fn option_index(option: u8) -> Option<usize> {
+    match option {
+        BINARY => Some(0),
+        SGA => Some(1),
+        _ => None,
+    }
+}
+
+impl TelnetDevice {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<TelnetDevice> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    /// Use a connected `stream`, after negotiating binary mode.
+    pub fn new(stream: TcpStream) -> io::Result<TelnetDevice> {
+        stream.set_nodelay(true)?;
+        let mut dev = Self {
+            stream,
+            input: [0; 4096],
+            start: 0,
+            end: 0,
+            output: Vec::with_capacity(OUTPUT_LEN),
+            state: State::Data,
+            local: [Q::WantYes; 2],
+            remote: [Q::WantYes; 2],
+        };
+        for option in [BINARY, SGA] {
+            dev.output
+                .extend_from_slice(&[IAC, WILL, option, IAC, DO, option]);
+        }
+        dev.flush()?;
+        dev.settle()?;
+        Ok(dev)
+    }
+
+    /// Wait until the server has answered our binary mode requests, so no data is sent while
+    /// the two ends could disagree on the mode.
+    fn settle(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + NEGOTIATION_TIMEOUT;
+        while self.local[0] == Q::WantYes || self.remote[0] == Q::WantYes {
+            if self.start == self.end {
+                let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                    break;
+                };
+                if !self.fill(timeout)? {
+                    break;
+                }
+            }
+            // Stop at the first data byte, it's left for `recv`.
+            let byte = self.input[self.start];
+            match (self.state, byte) {
+                (State::Data, IAC) => (),
+                (State::Data, _) | (State::Iac, IAC) => break,
+                _ => (),
+            }
+            self.start += 1;
+            self.receive(byte);
+        }
+        self.flush()
+    }
+
+    /// Whether binary mode is enabled in both directions.
+    pub fn is_binary(&self) -> bool {
+        self.local[0] == Q::Yes && self.remote[0] == Q::Yes
+    }
+
+    /// Send any buffered output.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.output.is_empty() {
+            self.stream.write_all(&self.output)?;
+            self.output.clear();
+        }
+        Ok(())
+    }
+
+    /// Handle an option command from the server.
+    fn negotiate(&mut self, command: u8, option: u8) {
+        let Some(index) = option_index(option) else {
+            // Refuse anything we don't support, there's no need to answer a refusal.
+            match command {
+                WILL => self.output.extend_from_slice(&[IAC, DONT, option]),
+                DO => self.output.extend_from_slice(&[IAC, WONT, option]),
+                _ => (),
+            }
+            return;
+        };
+        let (q, enable, disable) = match command {
+            WILL | WONT => (&mut self.remote[index], DO, DONT),
+            _ => (&mut self.local[index], WILL, WONT),
+        };
+        // Only answer requests that change the state, so negotiation can't loop.
+        let reply = match (command, *q) {
+            (WILL | DO, Q::No) => Some(enable),
+            (WONT | DONT, Q::Yes) => Some(disable),
+            _ => None,
+        };
+        *q = if matches!(command, WILL | DO) {
+            Q::Yes
+        } else {
+            Q::No
+        };
+        if let Some(reply) = reply {
+            self.output.extend_from_slice(&[IAC, reply, option]);
+        }
+    }
+
+    /// Process a byte from the connection, returning it if it is data.
+    fn receive(&mut self, byte: u8) -> Option<u8> {
+        match (self.state, byte) {
+            (State::Data, IAC) => self.state = State::Iac,
+            (State::Data, CR) if self.remote[0] != Q::Yes => {
+                self.state = State::Cr;
+                return Some(CR);
+            }
+            (State::Data, byte) => return Some(byte),
+            (State::Cr, NUL) => self.state = State::Data,
+            (State::Cr, byte) => {
+                self.state = State::Data;
+                return self.receive(byte);
+            }
+            (State::Iac, IAC) => {
+                self.state = State::Data;
+                return Some(IAC);
+            }
+            (State::Iac, WILL | WONT | DO | DONT) => self.state = State::Option(byte),
+            (State::Iac, SB) => self.state = State::Sub,
+            // Other commands carry no data.
+            (State::Iac, _) => self.state = State::Data,
+            (State::Option(command), option) => {
+                self.negotiate(command, option);
+                self.state = State::Data;
+            }
+            (State::Sub, IAC) => self.state = State::SubIac,
+            (State::Sub, _) => (),
+            (State::SubIac, SE) => self.state = State::Data,
+            (State::SubIac, _) => self.state = State::Sub,
+        }
+        None
+    }
+
+    /// Wait for more input, returning `false` on timeout.
+    fn fill(&mut self, timeout: Duration) -> io::Result<bool> {
+        self.stream
+            .set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        match self.stream.read(&mut self.input) {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(len) => {
+                self.start = 0;
+                self.end = len;
+                Ok(true)
+            }
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                Ok(false)
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+impl SerialDevice for TelnetDevice {
+    type Error = io::Error;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        match byte {
+            IAC => self.output.extend_from_slice(&[IAC, IAC]),
+            CR if self.local[0] != Q::Yes => self.output.extend_from_slice(&[CR, NUL]),
+            _ => self.output.push(byte),
+        }
+        if self.output.len() >= OUTPUT_LEN {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        self.flush()?;
+        // Input holding only telnet commands doesn't restart the wait.
+        let deadline = Instant::now() + timeout;
+        loop {
+            while self.start < self.end {
+                let byte = self.input[self.start];
+                self.start += 1;
+                if let Some(byte) = self.receive(byte) {
+                    // Answer any negotiation right away.
+                    self.flush()?;
+                    return Ok(Some(byte));
+                }
+            }
+            self.flush()?;
+            let Some(timeout) = deadline.checked_duration_since(Instant::now()) else {
+                return Ok(None);
+            };
+            if !self.fill(timeout)? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+impl Drop for TelnetDevice {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
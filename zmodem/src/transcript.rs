@@ -0,0 +1,255 @@
+//! Recording sessions to transcripts, and replaying them.
+//!
+//! A transcript is text with one line per run of bytes in the same direction, seen from the
+//! recorded device: the time in seconds since recording started, `tx` for bytes it sent or `rx`
+//! for bytes it received, then the bytes in hex. Reads that timed out are recorded as `timeout`.
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! # rz on ttyUSB0
+//! 0.000000 tx 2a 2a 18 42 30 31 30 30 30 30 30 30 32 33 62 65 35 30
+//! 0.000410 rx 2a 18 43 04 00 00 00 00 dd 51 a2 33
+//! 0.500731 timeout
+//! ```
+
+use crate::SerialDevice;
+use core::{fmt, time::Duration};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    time::Instant,
+    vec::Vec,
+};
+
+/// Most bytes written on one line.
+const LINE_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Sent => "tx",
+            Direction::Received => "rx",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordError<E> {
+    Device(E),
+    /// The transcript couldn't be written.
+    Io(io::Error),
+}
+
+/// A [`SerialDevice`] that records everything going through `dev` to `output`.
+///
+/// The last line is only written when the recorder is dropped.
+pub struct Recorder<D: SerialDevice, W: Write> {
+    dev: D,
+    output: W,
+    start: Instant,
+    // Bytes not written yet, in the same direction, and when the first one went through.
+    line: Vec<u8>,
+    direction: Direction,
+    time: Duration,
+}
+
+impl<D: SerialDevice, W: Write> Recorder<D, W> {
+    pub fn new(dev: D, output: W) -> Recorder<D, W> {
+        Self {
+            dev,
+            output,
+            start: Instant::now(),
+            line: Vec::with_capacity(LINE_LEN),
+            direction: Direction::Sent,
+            time: Duration::ZERO,
+        }
+    }
+
+    /// Write the pending line, if any.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        write!(
+            self.output,
+            "{}.{:06} {}",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.direction.name()
+        )?;
+        for byte in &self.line {
+            write!(self.output, " {byte:02x}")?;
+        }
+        writeln!(self.output)?;
+        self.line.clear();
+        Ok(())
+    }
+
+    fn record(&mut self, direction: Direction, byte: u8) -> io::Result<()> {
+        if direction != self.direction || self.line.len() == LINE_LEN {
+            self.flush()?;
+        }
+        if self.line.is_empty() {
+            self.direction = direction;
+            self.time = self.start.elapsed();
+        }
+        self.line.push(byte);
+        Ok(())
+    }
+
+    fn record_timeout(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let time = self.start.elapsed();
+        writeln!(
+            self.output,
+            "{}.{:06} timeout",
+            time.as_secs(),
+            time.subsec_micros()
+        )
+    }
+}
+
+impl<D: SerialDevice, W: Write> SerialDevice for Recorder<D, W> {
+    type Error = RecordError<D::Error>;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.dev.send(byte).map_err(RecordError::Device)?;
+        self.record(Direction::Sent, byte).map_err(RecordError::Io)
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        let byte = self.dev.recv(timeout).map_err(RecordError::Device)?;
+        match byte {
+            Some(byte) => self.record(Direction::Received, byte),
+            None => self.record_timeout(),
+        }
+        .map_err(RecordError::Io)?;
+        Ok(byte)
+    }
+
+    fn is_cancelled(&mut self) -> bool {
+        self.dev.is_cancelled()
+    }
+}
+
+impl<D: SerialDevice, W: Write> Drop for Recorder<D, W> {
+    fn drop(&mut self) {
+        self.flush().and_then(|_| self.output.flush()).ok();
+    }
+}
+
+/// A line of a transcript that couldn't be parsed, numbered from 1.
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid transcript line {}", self.line)
+    }
+}
+
+/// The end of the transcript was reached.
+#[derive(Debug)]
+pub struct EndOfTranscript;
+
+/// A [`SerialDevice`] that plays back the received side of a transcript.
+///
+/// Received bytes and timeouts are returned in order, regardless of what is sent, so a session
+/// can be replayed deterministically. What is sent is kept, to compare with the recording.
+pub struct Replay {
+    // The recorded reads, `None` for timeouts.
+    received: VecDeque<Option<u8>>,
+    recorded: Vec<u8>,
+    sent: Vec<u8>,
+}
+
+/// A line of a transcript, with the time since recording started.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    Sent(Duration, Vec<u8>),
+    Received(Duration, Vec<u8>),
+    Timeout(Duration),
+}
+
+/// Parse a line of a transcript, which isn't empty or a comment.
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.split_ascii_whitespace();
+    let time = Duration::try_from_secs_f64(fields.next()?.parse().ok()?).ok()?;
+    let direction = fields.next()?;
+    let bytes = fields.map(|byte| u8::from_str_radix(byte, 16).ok());
+    match direction {
+        "tx" => Some(Entry::Sent(time, bytes.collect::<Option<_>>()?)),
+        "rx" => Some(Entry::Received(time, bytes.collect::<Option<_>>()?)),
+        "timeout" if bytes.count() == 0 => Some(Entry::Timeout(time)),
+        _ => None,
+    }
+}
+
+/// Parse a transcript into its lines.
+pub fn parse(transcript: &str) -> Result<Vec<Entry>, ParseError> {
+    let mut entries = Vec::new();
+    for (i, line) in transcript.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        entries.push(parse_line(line).ok_or(ParseError { line: i + 1 })?);
+    }
+    Ok(entries)
+}
+
+impl Replay {
+    pub fn parse(transcript: &str) -> Result<Replay, ParseError> {
+        let mut received = VecDeque::new();
+        let mut recorded = Vec::new();
+        for entry in parse(transcript)? {
+            match entry {
+                Entry::Sent(_, bytes) => recorded.extend(bytes),
+                Entry::Received(_, bytes) => received.extend(bytes.into_iter().map(Some)),
+                Entry::Timeout(_) => received.push_back(None),
+            }
+        }
+        Ok(Self {
+            received,
+            recorded,
+            sent: Vec::new(),
+        })
+    }
+
+    /// Bytes sent during the recording.
+    pub fn recorded(&self) -> &[u8] {
+        &self.recorded
+    }
+
+    /// Bytes sent during the replay.
+    pub fn sent(&self) -> &[u8] {
+        &self.sent
+    }
+
+    /// Whether all recorded reads have been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.received.is_empty()
+    }
+}
+
+/// Borrowed, so the replay can be inspected once the session is over.
+impl SerialDevice for &mut Replay {
+    type Error = EndOfTranscript;
+
+    fn send(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.sent.push(byte);
+        Ok(())
+    }
+
+    fn recv(&mut self, _timeout: Duration) -> Result<Option<u8>, Self::Error> {
+        self.received.pop_front().ok_or(EndOfTranscript)
+    }
+}
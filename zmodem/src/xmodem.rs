@@ -0,0 +1,271 @@
+use crate::{
+    crc16,
+    proto::consts::{ACK, CAN, EOT, NAK, SOH, STX},
+    Device, Error, SerialDevice,
+};
+use core::time::Duration;
+
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const PURGE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the second byte of a CRC may take, when telling it from a checksum.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_ERRORS: usize = 10;
+
+/// Number of 'C' invitations sent before falling back to checksum mode.
+const CRC_ATTEMPTS: usize = 3;
+
+enum Block {
+    Data { number: u8, len: usize },
+    Eot,
+}
+
+pub struct Receiver<D: SerialDevice> {
+    dev: Device<D>,
+    crc: bool,
+    // Whether the first valid block decides between CRCs and checksums, rather than `crc`.
+    detect_check: bool,
+}
+
+impl<D: SerialDevice> Receiver<D> {
+    pub fn new(dev: D) -> Receiver<D> {
+        Self::from_device(Device::new(dev))
+    }
+
+    pub(crate) fn from_device(dev: Device<D>) -> Receiver<D> {
+        Self {
+            dev,
+            crc: true,
+            detect_check: false,
+        }
+    }
+
+    /// Tell whether blocks end with a CRC or a checksum from the first one, for a sender that
+    /// may have answered either invitation.
+    ///
+    /// A checksum is a single byte, after which the sender waits for us, so a block followed by
+    /// a second byte has a CRC.
+    pub(crate) fn set_detect_check(mut self) -> Self {
+        self.detect_check = true;
+        self
+    }
+
+    /// Discard incoming bytes until the line has been quiet for a second.
+    fn purge(&mut self) -> Result<(), Error<D::Error>> {
+        loop {
+            match self.dev.recv(PURGE_TIMEOUT) {
+                Ok(_) => continue,
+                Err(Error::TimedOut) => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Send the cancel sequence.
+    fn cancel(&mut self) -> Result<(), Error<D::Error>> {
+        for _ in 0..8 {
+            self.dev.send(CAN)?;
+        }
+        Ok(())
+    }
+
+    /// Receive a single block into `buf`.
+    ///
+    /// Returns `None` if a corrupted block was received.
+    fn receive_block(
+        &mut self,
+        buf: &mut [u8; 1024],
+        timeout: Duration,
+    ) -> Result<Option<Block>, Error<D::Error>> {
+        let len = loop {
+            match self.dev.recv(timeout)? {
+                SOH => break 128,
+                STX => break 1024,
+                EOT => return Ok(Some(Block::Eot)),
+                CAN => {
+                    if self.dev.recv(timeout)? == CAN {
+                        return Err(Error::Aborted);
+                    }
+                }
+                _ => continue,
+            }
+        };
+
+        let number = self.dev.recv(BLOCK_TIMEOUT)?;
+        let inverse = self.dev.recv(BLOCK_TIMEOUT)?;
+        for byte in &mut buf[..len] {
+            *byte = self.dev.recv(BLOCK_TIMEOUT)?;
+        }
+
+        let first = self.dev.recv(BLOCK_TIMEOUT)?;
+        let second = match (self.detect_check, self.crc) {
+            (true, _) => match self.dev.recv(CHECK_TIMEOUT) {
+                Ok(byte) => Some(byte),
+                Err(Error::TimedOut) => None,
+                Err(error) => return Err(error),
+            },
+            (false, true) => Some(self.dev.recv(BLOCK_TIMEOUT)?),
+            (false, false) => None,
+        };
+        let valid = match second {
+            Some(second) => u16::from_be_bytes([first, second]) == crc16(&buf[..len], None),
+            None => {
+                first
+                    == buf[..len]
+                        .iter()
+                        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            }
+        };
+
+        if number ^ inverse != 0xff || !valid {
+            return Ok(None);
+        }
+        if self.detect_check {
+            self.detect_check = false;
+            self.crc = second.is_some();
+        }
+
+        Ok(Some(Block::Data { number, len }))
+    }
+
+    /// Send `invite` and wait for the first block of a file.
+    ///
+    /// If `invite` is `None`, the sender has already started transmitting.
+    fn receive_first_block(
+        &mut self,
+        buf: &mut [u8; 1024],
+        mut invite: Option<u8>,
+    ) -> Result<Block, Error<D::Error>> {
+        let mut errors = 0;
+        loop {
+            if let Some(byte) = invite {
+                self.dev.send(byte)?;
+            }
+            match self.receive_block(buf, BLOCK_TIMEOUT) {
+                Ok(Some(block)) => return Ok(block),
+                Ok(None) => self.purge()?,
+                Err(Error::TimedOut) => (),
+                Err(error) => return Err(error),
+            }
+            errors += 1;
+            if errors >= MAX_ERRORS {
+                self.cancel()?;
+                return Err(Error::TooManyErrors);
+            }
+            if self.crc && invite.is_some() && errors >= CRC_ATTEMPTS {
+                self.crc = false;
+            }
+            invite = Some(if self.crc { b'C' } else { NAK });
+        }
+    }
+
+    /// Receive files into `output`, returning the total number of bytes received.
+    ///
+    /// Both XMODEM (single file, padded to the block size) and YMODEM batch transfers are
+    /// accepted; the protocol is selected by the number of the first block.
+    pub fn receive(&mut self, output: &mut [u8]) -> Result<usize, Error<D::Error>> {
+        self.receive_files(output, true)
+    }
+
+    pub(crate) fn receive_files(
+        &mut self,
+        output: &mut [u8],
+        invite: bool,
+    ) -> Result<usize, Error<D::Error>> {
+        let mut buf = [0; 1024];
+        let mut output_offset = 0;
+        let mut invite = invite.then_some(b'C');
+
+        loop {
+            let (batch, mut expected, mut remaining) =
+                match self.receive_first_block(&mut buf, invite)? {
+                    Block::Data { number: 0, len } => {
+                        // YMODEM header block: the file name followed by the metadata.
+                        let mut fields = buf[..len].split(|b| *b == 0);
+                        let name = fields.next().unwrap_or_default();
+                        if name.is_empty() {
+                            // An empty header ends the batch.
+                            self.dev.send(ACK)?;
+                            return Ok(output_offset);
+                        }
+                        let size = fields
+                            .next()
+                            .and_then(|meta| meta.split(|b| *b == b' ').next())
+                            .and_then(|size| core::str::from_utf8(size).ok())
+                            .and_then(|size| size.parse::<usize>().ok());
+                        self.dev.send(ACK)?;
+                        self.dev.send(if self.crc { b'C' } else { NAK })?;
+                        (true, 1u8, size)
+                    }
+                    Block::Data { number: 1, len } => {
+                        // XMODEM transfer: the first block already contains data.
+                        if output_offset + len > output.len() {
+                            self.cancel()?;
+                            return Err(Error::OutputFull);
+                        }
+                        output[output_offset..][..len].copy_from_slice(&buf[..len]);
+                        output_offset += len;
+                        self.dev.send(ACK)?;
+                        (false, 2u8, None)
+                    }
+                    Block::Data { .. } => {
+                        self.cancel()?;
+                        return Err(Error::TooManyErrors);
+                    }
+                    Block::Eot => {
+                        self.dev.send(ACK)?;
+                        return Ok(output_offset);
+                    }
+                };
+
+            let mut errors = 0;
+            loop {
+                match self.receive_block(&mut buf, BLOCK_TIMEOUT) {
+                    Ok(Some(Block::Data { number, len })) if number == expected => {
+                        let len = remaining.map_or(len, |remaining| len.min(remaining));
+                        if output_offset + len > output.len() {
+                            self.cancel()?;
+                            return Err(Error::OutputFull);
+                        }
+                        output[output_offset..][..len].copy_from_slice(&buf[..len]);
+                        output_offset += len;
+                        if let Some(remaining) = &mut remaining {
+                            *remaining -= len;
+                        }
+                        expected = expected.wrapping_add(1);
+                        errors = 0;
+                        self.dev.send(ACK)?;
+                    }
+                    // Our ACK was lost, and the sender repeated the previous block.
+                    Ok(Some(Block::Data { number, .. })) if number == expected.wrapping_sub(1) => {
+                        self.dev.send(ACK)?;
+                    }
+                    Ok(Some(Block::Data { .. })) => {
+                        self.cancel()?;
+                        return Err(Error::TooManyErrors);
+                    }
+                    Ok(Some(Block::Eot)) => {
+                        self.dev.send(ACK)?;
+                        break;
+                    }
+                    Ok(None) | Err(Error::TimedOut) => {
+                        errors += 1;
+                        if errors >= MAX_ERRORS {
+                            self.cancel()?;
+                            return Err(Error::TooManyErrors);
+                        }
+                        self.purge()?;
+                        self.dev.send(NAK)?;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+
+            if !batch {
+                return Ok(output_offset);
+            }
+
+            // Ask for the next YMODEM header block.
+            invite = Some(if self.crc { b'C' } else { NAK });
+        }
+    }
+}
@@ -0,0 +1,147 @@
+#![cfg(feature = "std")]
+
+use zmodem::{
+    analyse::{Analyser, Record},
+    codec::{self, CodecError, MAX_HEADER_LEN},
+    proto::{EscapeMode, FrameEncoding, FrameHeader, FrameType, PacketType},
+    transcript::{self, Entry},
+};
+
+fn header(output: &mut Vec<u8>, header: FrameHeader) {
+    let mut buf = [0; MAX_HEADER_LEN];
+    let len = codec::encode_header(&header, EscapeMode::Clean, &mut buf).unwrap();
+    output.extend_from_slice(&buf[..len]);
+}
+
+fn subpacket(output: &mut Vec<u8>, packet_type: PacketType, data: &[u8]) {
+    let encoding = FrameEncoding::BIN32;
+    output.extend(codec::subpacket(
+        encoding,
+        packet_type,
+        data,
+        EscapeMode::Clean,
+    ));
+}
+
+fn analyse(bytes: &[u8]) -> Vec<Record> {
+    let mut analyser = Analyser::new(EscapeMode::Clean);
+    let mut records = analyser.push(bytes);
+    records.extend(analyser.finish());
+    records
+}
+
+#[test]
+fn timeline() {
+    let zdata = FrameHeader::new(FrameEncoding::BIN32, FrameType::ZDATA);
+    let mut bytes = b"rz\r".to_vec();
+    header(
+        &mut bytes,
+        FrameHeader::new(FrameEncoding::HEX, FrameType::ZRQINIT),
+    );
+    let start = bytes.len();
+    header(&mut bytes, zdata.set_count(100));
+    subpacket(&mut bytes, PacketType::ZCRCG, &[1; 10]);
+    let corrupt = bytes.len() + 5;
+    subpacket(&mut bytes, PacketType::ZCRCG, &[2; 20]);
+    bytes[corrupt] ^= 1;
+    // Cut short by the sender starting over.
+    bytes.extend_from_slice(&[3; 7]);
+    let restart = bytes.len();
+    header(&mut bytes, zdata.set_count(110));
+    subpacket(&mut bytes, PacketType::ZCRCW, &[2; 20]);
+    header(
+        &mut bytes,
+        FrameHeader::new(FrameEncoding::HEX, FrameType::ZEOF).set_count(130),
+    );
+    bytes.extend_from_slice(b"OO");
+
+    let records = analyse(&bytes);
+    assert_eq!(records[0], Record::Skipped { offset: 0, len: 3 });
+    assert!(
+        matches!(records[1], Record::Header { offset: 3, header } if header.r#type == FrameType::ZRQINIT)
+    );
+    assert!(
+        matches!(records[2], Record::Header { offset, header } if offset == start && header.count() == 100)
+    );
+    let results: Vec<_> = records
+        .iter()
+        .filter_map(|record| match record {
+            Record::Subpacket {
+                len,
+                position,
+                result,
+                ..
+            } => Some((*len, *position, *result)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        results,
+        [
+            (10, Some(100), Ok(PacketType::ZCRCG)),
+            (20, Some(110), Err(CodecError::InvalidCrc)),
+            (7, Some(130), Err(CodecError::Incomplete)),
+            (20, Some(110), Ok(PacketType::ZCRCW)),
+        ]
+    );
+    assert!(records.contains(&Record::Gap {
+        offset: restart,
+        expected: 130,
+        position: 110,
+    }));
+    // The HEX trailer and the XON after ZCRCW aren't skipped.
+    let last = records.len() - 1;
+    assert!(
+        matches!(records[last - 1], Record::Header { header, .. } if header.r#type == FrameType::ZEOF)
+    );
+    assert_eq!(
+        records[last],
+        Record::Skipped {
+            offset: bytes.len() - 2,
+            len: 2,
+        }
+    );
+}
+
+#[test]
+fn invalid_header() {
+    let mut bytes = Vec::new();
+    header(
+        &mut bytes,
+        FrameHeader::new(FrameEncoding::BIN16, FrameType::ZRPOS),
+    );
+    bytes[5] ^= 1;
+    let records = analyse(&bytes);
+    assert_eq!(
+        records[0],
+        Record::InvalidHeader {
+            offset: 0,
+            error: CodecError::InvalidCrc,
+        }
+    );
+    assert_eq!(
+        analyse(&bytes[..4]),
+        [Record::InvalidHeader {
+            offset: 0,
+            error: CodecError::Incomplete,
+        }]
+    );
+}
+
+#[test]
+fn transcript() {
+    let entries = transcript::parse(include_str!("transcripts/corrupted.txt")).unwrap();
+    let mut analyser = Analyser::new(EscapeMode::Clean);
+    let mut records = Vec::new();
+    for entry in entries {
+        if let Entry::Received(_, bytes) = entry {
+            records.extend(analyser.push(&bytes));
+        }
+    }
+    records.extend(analyser.finish());
+    let lines: Vec<String> = records.iter().map(|record| record.to_string()).collect();
+    assert_eq!(lines[0], "skipped 3 bytes");
+    assert!(lines.contains(&"invalid subpacket after 1024 bytes: InvalidCrc at 0".into()));
+    assert!(lines.contains(&"gap: data resumed at 0, expected 2000".into()));
+    assert_eq!(lines.last().unwrap(), "skipped 2 bytes");
+}
@@ -0,0 +1,133 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{pipe, session, MemorySink, Pipe};
+use core::time::Duration;
+use zmodem::{
+    event::Event,
+    proto::{FrameEncoding, FrameHeader, FrameType, PacketType},
+    recv::Receiver,
+    send::Sender,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Frames sent by hand, through a receiver used only for its framing.
+type Raw = Receiver<'static, Pipe>;
+
+fn send(raw: &mut Raw, r#type: FrameType, count: u32) {
+    let frame = FrameHeader::new(FrameEncoding::BIN32, r#type).set_count(count);
+    raw.send_frame(frame).unwrap();
+}
+
+/// The next header other than ZRINIT.
+fn next(raw: &mut Raw) -> FrameHeader {
+    loop {
+        let frame = raw.receive_frame_header(TIMEOUT).unwrap();
+        if frame.r#type != FrameType::ZRINIT {
+            return frame;
+        }
+    }
+}
+
+fn finish(raw: &mut Raw) {
+    send(raw, FrameType::ZFIN, 0);
+    assert_eq!(next(raw).r#type, FrameType::ZFIN);
+    // Rather than "OO", which the receiver would wait a second more for.
+    raw.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZRQINIT))
+        .ok();
+}
+
+/// Run `script` against a receiver with `sink`, returning the Stderr events it reported.
+fn receive(sink: &mut MemorySink, script: impl FnOnce(&mut Raw) + Send + 'static) -> Vec<Vec<u8>> {
+    let mut events = Vec::new();
+    let mut handler = |event: Event| {
+        if let Event::Stderr(text) = event {
+            events.push(text.to_vec());
+        }
+    };
+    let ((), received) = session(
+        pipe(),
+        |dev| script(&mut Receiver::new(dev)),
+        |dev| {
+            let mut receiver = Receiver::new(dev).set_event_handler(&mut handler);
+            receiver.send_zrinit()?;
+            receiver.receive_files(sink)
+        },
+    );
+    received.unwrap();
+    events
+}
+
+#[test]
+fn challenge() {
+    // The receiver echoes the challenge.
+    receive(&mut MemorySink::default(), |raw| {
+        send(raw, FrameType::ZCHALLENGE, 0x1234_5678);
+        let frame = next(raw);
+        assert_eq!(frame.r#type, FrameType::ZACK);
+        assert_eq!(frame.count(), 0x1234_5678);
+        finish(raw);
+    });
+
+    // So does the sender, before starting.
+    let (started, ()) = session(
+        pipe(),
+        |dev| Sender::new(dev).start(),
+        |dev| {
+            let mut raw = Receiver::new(dev);
+            send(&mut raw, FrameType::ZCHALLENGE, 0x8765_4321);
+            let frame = loop {
+                let frame = raw.receive_frame_header(TIMEOUT).unwrap();
+                if frame.r#type != FrameType::ZRQINIT {
+                    break frame;
+                }
+            };
+            assert_eq!(frame.r#type, FrameType::ZACK);
+            assert_eq!(frame.count(), 0x8765_4321);
+            raw.send_zrinit().unwrap();
+        },
+    );
+    started.unwrap();
+}
+
+#[test]
+fn free_space() {
+    let mut sink = MemorySink::default();
+    sink.free_space = Some(123_456);
+    receive(&mut sink, |raw| {
+        send(raw, FrameType::ZFREECNT, 0);
+        let frame = next(raw);
+        assert_eq!(frame.r#type, FrameType::ZACK);
+        assert_eq!(frame.count(), 123_456);
+        finish(raw);
+    });
+
+    // Unknown free space is reported as unlimited.
+    receive(&mut MemorySink::default(), |raw| {
+        send(raw, FrameType::ZFREECNT, 0);
+        assert_eq!(next(raw).count(), u32::MAX);
+        finish(raw);
+    });
+}
+
+#[test]
+fn stderr() {
+    let events = receive(&mut MemorySink::default(), |raw| {
+        // Only ZCRCQ and ZCRCW subpackets are acknowledged.
+        send(raw, FrameType::ZSTDERR, 0);
+        raw.send_data_packet(FrameEncoding::BIN32, PacketType::ZCRCE, b"no ack\n")
+            .unwrap();
+        for (count, packet_type) in [(1, PacketType::ZCRCQ), (2, PacketType::ZCRCW)] {
+            send(raw, FrameType::ZSTDERR, count);
+            raw.send_data_packet(FrameEncoding::BIN32, packet_type, b"ack\n")
+                .unwrap();
+            let frame = next(raw);
+            assert_eq!(frame.r#type, FrameType::ZACK);
+            assert_eq!(frame.count(), count);
+        }
+        finish(raw);
+    });
+    assert_eq!(events, [&b"no ack\n"[..], b"ack\n", b"ack\n"]);
+}
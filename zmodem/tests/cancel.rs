@@ -0,0 +1,51 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::pipe;
+use core::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+use zmodem::{cancel::Cancellable, Error};
+
+#[test]
+fn cancel_transfer() {
+    let (sender, receiver) = pipe();
+    let data = vec![0x5a; 100_000];
+    let sender = thread::spawn(move || zmodem::send(sender, "data.bin", &data).unwrap_err());
+
+    // Cancel once the transfer is under way.
+    let mut checks = 0;
+    let dev = Cancellable::new(receiver, || {
+        checks += 1;
+        checks > 1000
+    });
+    let mut output = vec![0; 100_000];
+    let error = zmodem::receive(dev, &mut output).unwrap_err();
+    assert!(matches!(error, Error::Cancelled));
+    // The sender sees the abort sequence.
+    assert!(matches!(sender.join().unwrap(), Error::Aborted));
+}
+
+#[test]
+fn cancel_while_waiting() {
+    let (_sender, receiver) = pipe();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        flag.store(true, Ordering::Relaxed);
+    });
+
+    let start = Instant::now();
+    let dev = Cancellable::new(receiver, || cancelled.load(Ordering::Relaxed));
+    let error = zmodem::receive(dev, &mut [0; 16]).unwrap_err();
+    assert!(matches!(error, Error::Cancelled));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
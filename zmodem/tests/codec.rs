@@ -0,0 +1,154 @@
+mod common;
+
+use common::data;
+use zmodem::{
+    codec::{self, CodecError, HeaderDecoder, SubpacketDecoder, Unescaped, MAX_HEADER_LEN},
+    proto::{EscapeMode, FrameEncoding, FrameHeader, FrameType, PacketType},
+};
+
+const MODES: [EscapeMode; 3] = [EscapeMode::Clean, EscapeMode::Control, EscapeMode::EightBit];
+
+#[test]
+fn escape() {
+    for mode in MODES {
+        let data = data(512);
+        let escaped: Vec<u8> = codec::escape(&data, mode).collect();
+        if mode == EscapeMode::EightBit {
+            assert!(escaped.iter().all(|byte| byte & 0x80 == 0));
+        }
+        let unescaped: Vec<u8> = codec::unescape(&escaped, mode)
+            .map(|byte| match byte.unwrap() {
+                Unescaped::Byte(byte) => byte,
+                Unescaped::End(_) => panic!("unexpected end"),
+            })
+            .collect();
+        assert_eq!(unescaped, data);
+    }
+    // An escape cut short.
+    let mut unescape = codec::unescape(&[b'a', 0x18], EscapeMode::Clean);
+    assert_eq!(unescape.next(), Some(Ok(Unescaped::Byte(b'a'))));
+    assert_eq!(unescape.next(), Some(Err(CodecError::Incomplete)));
+    assert_eq!(unescape.next(), None);
+}
+
+#[test]
+fn header() {
+    let encodings = [
+        FrameEncoding::HEX,
+        FrameEncoding::BIN16,
+        FrameEncoding::BIN32,
+    ];
+    for (mode, encoding) in MODES.into_iter().flat_map(|m| encodings.map(|e| (m, e))) {
+        let header = FrameHeader::new(encoding, FrameType::ZDATA).set_count(0x8d11_ff18);
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = codec::encode_header(&header, mode, &mut buf).unwrap();
+
+        // Padding and the HEX trailer are consumed too.
+        let (decoded, used) = codec::decode_header(&buf[..len], mode).unwrap();
+        assert_eq!(used, len);
+        assert_eq!(decoded.encoding, encoding);
+        assert_eq!(decoded.r#type, FrameType::ZDATA);
+        assert_eq!(decoded.count(), 0x8d11_ff18);
+
+        let mut decoder = HeaderDecoder::new(mode);
+        let results: Vec<_> = buf[..len]
+            .iter()
+            .map(|b| decoder.push(*b).unwrap())
+            .collect();
+        assert!(results.iter().flatten().count() == 1);
+
+        // Cut into the header itself, a missing HEX trailer is tolerated.
+        assert_eq!(
+            codec::decode_header(&buf[..len - 4], mode).unwrap_err(),
+            CodecError::Incomplete
+        );
+        assert_eq!(
+            codec::encode_header(&header, mode, &mut buf[..len - 1]).unwrap_err(),
+            CodecError::BufferTooSmall
+        );
+    }
+}
+
+#[test]
+fn corrupted_header() {
+    let header = FrameHeader::new(FrameEncoding::BIN32, FrameType::ZRPOS).set_count(1234);
+    let mut buf = [0; MAX_HEADER_LEN];
+    let len = codec::encode_header(&header, EscapeMode::Clean, &mut buf).unwrap();
+    buf[len - 1] ^= 1;
+    assert_eq!(
+        codec::decode_header(&buf[..len], EscapeMode::Clean).unwrap_err(),
+        CodecError::InvalidCrc
+    );
+}
+
+#[test]
+fn subpacket() {
+    let data = data(512);
+    for mode in MODES {
+        for encoding in [FrameEncoding::BIN16, FrameEncoding::BIN32] {
+            let mut buf = vec![0; codec::max_encoded_subpacket_len(data.len())];
+            let len = codec::encode_subpacket(encoding, PacketType::ZCRCW, &data, mode, &mut buf)
+                .unwrap();
+            let iterated: Vec<u8> =
+                codec::subpacket(encoding, PacketType::ZCRCW, &data, mode).collect();
+            assert_eq!(iterated, buf[..len]);
+
+            let mut output = vec![0; data.len()];
+            let (packet_type, decoded, used) =
+                codec::decode_subpacket(encoding, &buf[..len], mode, &mut output).unwrap();
+            assert_eq!(packet_type, PacketType::ZCRCW);
+            assert_eq!(decoded, data);
+            // The XON after ZCRCW is left for the caller.
+            assert_eq!(used, len - 1);
+
+            let mut decoder = SubpacketDecoder::new(encoding, mode);
+            let mut output = vec![0; data.len()];
+            let end = buf[..len]
+                .iter()
+                .find_map(|byte| decoder.push(*byte, &mut output).unwrap());
+            assert_eq!(end, Some((PacketType::ZCRCW, data.len())));
+
+            assert_eq!(
+                codec::decode_subpacket(encoding, &buf[..len - 2], mode, &mut output).unwrap_err(),
+                CodecError::Incomplete
+            );
+            assert_eq!(
+                codec::decode_subpacket(encoding, &buf[..len], mode, &mut output[..10])
+                    .unwrap_err(),
+                CodecError::BufferTooSmall
+            );
+        }
+    }
+}
+
+#[test]
+fn lrzsz_frames() {
+    // Headers as sent by lrzsz: rz's ZRINIT, and sz's ZFILE with a CRC-32.
+    let zrinit = b"**\x18B0100000023be50\r\x8a\x11";
+    let (header, len) = codec::decode_header(zrinit, EscapeMode::Clean).unwrap();
+    assert_eq!(len, zrinit.len());
+    assert_eq!(header.r#type, FrameType::ZRINIT);
+    assert_eq!(header.data, [0, 0, 0, 0x23]);
+    let zfile = b"*\x18C\x04\x00\x00\x00\x00\xdd\x51\xa2\x33";
+    let (header, _) = codec::decode_header(zfile, EscapeMode::Clean).unwrap();
+    assert_eq!(header.encoding, FrameEncoding::BIN32);
+    assert_eq!(header.r#type, FrameType::ZFILE);
+
+    // We send the same bytes.
+    let mut buf = [0; MAX_HEADER_LEN];
+    let len = codec::encode_header(&header, EscapeMode::Clean, &mut buf).unwrap();
+    assert_eq!(buf[..len], zfile[..]);
+    let zrinit_header = FrameHeader::new(FrameEncoding::HEX, FrameType::ZRINIT).set_flags(0x23);
+    let len = codec::encode_header(&zrinit_header, EscapeMode::Clean, &mut buf).unwrap();
+    assert_eq!(buf[..len], zrinit[..]);
+
+    // Subpackets end with the CRC of the data and the end type.
+    for (encoding, subpacket) in [
+        (FrameEncoding::BIN32, &b"hello\x18h\x54\x30\x86\x95"[..]),
+        (FrameEncoding::BIN16, &b"hello\x18h\x66\x81"[..]),
+    ] {
+        let encoded: Vec<u8> =
+            codec::subpacket(encoding, PacketType::ZCRCE, b"hello", EscapeMode::Clean).collect();
+        assert_eq!(encoded, subpacket);
+    }
+}
@@ -0,0 +1,143 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{pipe, run_receiver, run_sender, send_raw, session, wait_for, MemorySink, Pipe};
+use zmodem::{
+    command::{CommandHandler, COMMAND_NOT_FOUND},
+    proto::{FrameEncoding, FrameHeader, FrameType},
+    recv::Receiver,
+    send::Sender,
+    Error, SerialDevice,
+};
+
+/// Records the commands it runs, failing any but `true`.
+#[derive(Default)]
+struct Shell {
+    commands: Vec<String>,
+}
+
+impl CommandHandler for Shell {
+    fn run(&mut self, command: &str) -> u32 {
+        self.commands.push(command.into());
+        match command {
+            "true" => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// Send `commands`, returning their statuses.
+fn send(dev: Pipe, commands: &[(&'static str, bool)]) -> Result<Vec<u32>, Error<()>> {
+    run_sender(Sender::new(dev), |sender| {
+        commands
+            .iter()
+            .map(|(command, ack_first)| sender.command(command, *ack_first))
+            .collect()
+    })
+}
+
+fn receive(dev: Pipe, shell: Option<&mut Shell>) -> Result<(), Error<()>> {
+    let mut receiver = Receiver::new(dev);
+    if let Some(shell) = shell {
+        receiver = receiver.set_command_handler(shell);
+    }
+    run_receiver(receiver, &mut MemorySink::default())
+}
+
+#[test]
+fn commands() {
+    let mut shell = Shell::default();
+    let commands = [("true", false), ("false", false), ("true", false)];
+    let (statuses, received) = session(
+        pipe(),
+        move |dev| send(dev, &commands),
+        |dev| receive(dev, Some(&mut shell)),
+    );
+    assert_eq!(statuses.unwrap(), [0, 1, 0]);
+    received.unwrap();
+    // The same command sent again is run again.
+    assert_eq!(shell.commands, ["true", "false", "true"]);
+}
+
+#[test]
+fn ack_first() {
+    // The command is acknowledged before it runs, so its status isn't known.
+    let mut shell = Shell::default();
+    let (statuses, received) = session(
+        pipe(),
+        |dev| send(dev, &[("false", true)]),
+        |dev| receive(dev, Some(&mut shell)),
+    );
+    assert_eq!(statuses.unwrap(), [0]);
+    received.unwrap();
+    assert_eq!(shell.commands, ["false"]);
+}
+
+#[test]
+fn no_handler() {
+    let (statuses, received) = session(
+        pipe(),
+        |dev| send(dev, &[("true", false)]),
+        |dev| receive(dev, None),
+    );
+    assert_eq!(statuses.unwrap(), [COMMAND_NOT_FOUND]);
+    received.unwrap();
+}
+
+#[test]
+fn lost_zcompl() {
+    // Corrupt the first ZCOMPL the receiver sends, a hex header starting "**\x18B0f".
+    let (sender, receiver) = pipe();
+    let mut recent = Vec::new();
+    let mut corrupted = false;
+    let receiver = receiver.set_fault(move |_, byte| {
+        recent.push(byte);
+        if !corrupted && recent.ends_with(b"\x18B0f") {
+            corrupted = true;
+            return Some(b'x');
+        }
+        Some(byte)
+    });
+
+    let mut shell = Shell::default();
+    let (statuses, received) = session(
+        (sender, receiver),
+        |dev| send(dev, &[("false", false)]),
+        |dev| receive(dev, Some(&mut shell)),
+    );
+    assert_eq!(statuses.unwrap(), [1]);
+    received.unwrap();
+    // The repeated command isn't run again.
+    assert_eq!(shell.commands, ["false"]);
+}
+
+#[test]
+fn lrzsz_commands() {
+    // lrzsz sends every command with ZCACK1 in ZF0 and doesn't number them, so each is run.
+    let mut shell = Shell::default();
+    let ((), received) = session(
+        pipe(),
+        |mut dev| {
+            wait_for(&mut dev, FrameType::ZRINIT);
+            for command in [&b"true\0"[..], b"false\0"] {
+                let header = FrameHeader::new(FrameEncoding::BIN32, FrameType::ZCOMMAND)
+                    .set_count(0x0100_0000);
+                send_raw(&mut dev, header, Some(command));
+                assert_eq!(wait_for(&mut dev, FrameType::ZCOMPL).count(), 0);
+            }
+            send_raw(
+                &mut dev,
+                FrameHeader::new(FrameEncoding::HEX, FrameType::ZFIN),
+                None,
+            );
+            wait_for(&mut dev, FrameType::ZFIN);
+            for byte in b"OO" {
+                dev.send(*byte).unwrap();
+            }
+        },
+        |dev| receive(dev, Some(&mut shell)),
+    );
+    received.unwrap();
+    assert_eq!(shell.commands, ["true", "false"]);
+}
@@ -0,0 +1,304 @@
+//! Links between the two ends of a session, shared by the tests.
+
+#![allow(dead_code)]
+
+use core::time::Duration;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::mpsc::{self, RecvTimeoutError},
+};
+use zmodem::{
+    codec::{self, HeaderDecoder},
+    crc32,
+    proto::{EscapeMode, FileInfo, FileOptions, FrameHeader, FrameType, PacketType},
+    recv::Receiver,
+    send::{Sender, Transfer},
+    sink::{ExistingFile, OpenMode, Sink, SinkError},
+    Error, SerialDevice,
+};
+
+/// Longest a test waits for a byte, whatever timeout the session asks for.
+const MAX_WAIT: Duration = Duration::from_secs(5);
+
+/// What a link does to the byte sent at each position: the byte it delivers, if any.
+type Fault = Box<dyn FnMut(usize, u8) -> Option<u8> + Send>;
+
+/// One end of an in-memory link.
+pub struct Pipe {
+    tx: mpsc::Sender<u8>,
+    rx: mpsc::Receiver<u8>,
+    sent: usize,
+    fault: Option<Fault>,
+}
+
+impl Pipe {
+    /// Pass what this end sends through `fault`.
+    pub fn set_fault(
+        mut self,
+        fault: impl FnMut(usize, u8) -> Option<u8> + Send + 'static,
+    ) -> Self {
+        self.fault = Some(Box::new(fault));
+        self
+    }
+
+    /// A handle sending bytes straight to the other end, alongside this one.
+    pub fn injector(&self) -> mpsc::Sender<u8> {
+        self.tx.clone()
+    }
+
+    /// Flip the byte this end sends at position `pos`.
+    pub fn set_corrupt(self, pos: usize) -> Self {
+        self.set_fault(move |sent, byte| Some(if sent == pos { !byte } else { byte }))
+    }
+}
+
+impl SerialDevice for Pipe {
+    type Error = ();
+
+    fn send(&mut self, byte: u8) -> Result<(), ()> {
+        let byte = match &mut self.fault {
+            Some(fault) => fault(self.sent, byte),
+            None => Some(byte),
+        };
+        self.sent += 1;
+        // The other end may be gone once the session is over.
+        if let Some(byte) = byte {
+            self.tx.send(byte).ok();
+        }
+        Ok(())
+    }
+
+    fn recv(&mut self, timeout: Duration) -> Result<Option<u8>, ()> {
+        let timeout = timeout.min(MAX_WAIT);
+        match self.rx.recv_timeout(timeout) {
+            Ok(byte) => Ok(Some(byte)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                std::thread::sleep(timeout);
+                Ok(None)
+            }
+        }
+    }
+}
+
+pub fn pipe() -> (Pipe, Pipe) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    let end = |tx, rx| Pipe {
+        tx,
+        rx,
+        sent: 0,
+        fault: None,
+    };
+    (end(a_tx, a_rx), end(b_tx, b_rx))
+}
+
+/// Run `sender` and `receiver` at the two ends of `pipe`, returning their results.
+pub fn session<S: Send + 'static, R>(
+    (a, b): (Pipe, Pipe),
+    sender: impl FnOnce(Pipe) -> S + Send + 'static,
+    receiver: impl FnOnce(Pipe) -> R,
+) -> (S, R) {
+    let sender = std::thread::spawn(move || sender(a));
+    let received = receiver(b);
+    (sender.join().unwrap(), received)
+}
+
+/// `len` bytes to send, with every byte value in each run of 256.
+pub fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+}
+
+/// A file named `name`, holding `data`.
+pub fn info<'a>(name: &'a str, data: &[u8]) -> FileInfo<'a> {
+    let mut info = FileInfo::new(name);
+    info.size = Some(data.len() as u64);
+    info
+}
+
+/// Send `data` as `name`, which the receiver must take.
+pub fn send_file(sender: &mut Sender<Pipe>, name: &str, mut data: &[u8]) -> Result<(), Error<()>> {
+    let transfer = sender.send_file(&info(name, data), &FileOptions::default(), &mut data)?;
+    assert_eq!(transfer, Transfer::Sent);
+    Ok(())
+}
+
+/// Start a session with `sender`, `send` what it has to, and finish it.
+pub fn run_sender<T>(
+    mut sender: Sender<Pipe>,
+    send: impl FnOnce(&mut Sender<Pipe>) -> Result<T, Error<()>>,
+) -> Result<T, Error<()>> {
+    sender.start()?;
+    let sent = send(&mut sender)?;
+    sender.finish()?;
+    Ok(sent)
+}
+
+/// Receive files into `sink` with `receiver` until the sender finishes.
+pub fn run_receiver(
+    mut receiver: Receiver<'_, Pipe>,
+    sink: &mut dyn Sink,
+) -> Result<(), Error<()>> {
+    receiver.send_zrinit()?;
+    receiver.receive_files(sink)
+}
+
+/// Send the encoded `header`, followed by a ZCRCW subpacket of `data` if any.
+pub fn send_raw(dev: &mut Pipe, header: FrameHeader, data: Option<&[u8]>) {
+    let mut buf = [0; 1024];
+    let mut len = codec::encode_header(&header, EscapeMode::Clean, &mut buf).unwrap();
+    if let Some(data) = data {
+        len += codec::encode_subpacket(
+            header.encoding,
+            PacketType::ZCRCW,
+            data,
+            EscapeMode::Clean,
+            &mut buf[len..],
+        )
+        .unwrap();
+    }
+    for byte in &buf[..len] {
+        dev.send(*byte).unwrap();
+    }
+}
+
+/// Wait for a header of type `r#type`, skipping any others.
+pub fn wait_for(dev: &mut Pipe, r#type: FrameType) -> FrameHeader {
+    let mut decoder = HeaderDecoder::new(EscapeMode::Clean);
+    loop {
+        let byte = dev.recv(MAX_WAIT).unwrap().unwrap();
+        match decoder.push(byte) {
+            Ok(Some(header)) if header.r#type == r#type => return header,
+            _ => {}
+        }
+    }
+}
+
+/// A link that returns whatever is sent on it, stripping the 8th bit when `mask` is 0x7f.
+pub struct Loopback {
+    bytes: VecDeque<u8>,
+    mask: u8,
+    sent: usize,
+    // Bytes added by the link before the byte sent at each position, in order.
+    noise: Vec<(usize, u8)>,
+}
+
+impl Loopback {
+    pub fn new(mask: u8, noise: Vec<(usize, u8)>) -> Loopback {
+        Loopback {
+            bytes: VecDeque::new(),
+            mask,
+            sent: 0,
+            noise,
+        }
+    }
+}
+
+impl SerialDevice for Loopback {
+    type Error = ();
+
+    fn send(&mut self, byte: u8) -> Result<(), ()> {
+        for (_, noise) in self.noise.iter().filter(|(pos, _)| *pos == self.sent) {
+            self.bytes.push_back(*noise);
+        }
+        self.sent += 1;
+        self.bytes.push_back(byte & self.mask);
+        Ok(())
+    }
+
+    fn recv(&mut self, _timeout: Duration) -> Result<Option<u8>, ()> {
+        Ok(self.bytes.pop_front())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryFile {
+    pub data: Vec<u8>,
+    pub mtime: Option<u64>,
+}
+
+/// A sink keeping files in memory, by name.
+#[derive(Default)]
+pub struct MemorySink {
+    pub files: BTreeMap<String, MemoryFile>,
+    /// Runs of zeros skipped by sparse transfers, as offset and length.
+    pub holes: Vec<(u64, u64)>,
+    pub free_space: Option<u64>,
+    open: Option<String>,
+}
+
+impl MemorySink {
+    pub fn insert(&mut self, name: &str, data: &[u8], mtime: Option<u64>) {
+        let file = MemoryFile {
+            data: data.to_vec(),
+            mtime,
+        };
+        self.files.insert(name.into(), file);
+    }
+
+    pub fn data(&self, name: &str) -> &[u8] {
+        &self.files[name].data
+    }
+
+    fn file(&mut self) -> &mut MemoryFile {
+        let name = self.open.as_ref().expect("no open file");
+        self.files.get_mut(name).unwrap()
+    }
+}
+
+impl Sink for MemorySink {
+    fn existing(&mut self, info: &FileInfo) -> Option<ExistingFile> {
+        self.files.get(info.name).map(|file| ExistingFile {
+            size: file.data.len() as u64,
+            mtime: file.mtime,
+        })
+    }
+
+    fn crc32(&mut self, info: &FileInfo, len: u64) -> Option<u32> {
+        let data = &self.files.get(info.name)?.data;
+        Some(crc32(data.get(..len as usize)?, None))
+    }
+
+    fn free_space(&mut self) -> Option<u64> {
+        self.free_space
+    }
+
+    fn open(&mut self, info: &FileInfo, mode: OpenMode) -> Result<(), SinkError> {
+        if mode == OpenMode::Create && self.files.contains_key(info.name) {
+            return Err(SinkError::Refused);
+        }
+        let file = self.files.entry(info.name.into()).or_default();
+        if matches!(mode, OpenMode::Create | OpenMode::Replace) {
+            file.data.clear();
+        }
+        file.mtime = info.mtime;
+        self.open = Some(info.name.into());
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<(), SinkError> {
+        let file = self.file();
+        let end = offset as usize + data.len();
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[offset as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn hole(&mut self, offset: u64, len: u64) -> Result<(), SinkError> {
+        self.holes.push((offset, len));
+        let file = self.file();
+        let end = (offset + len) as usize;
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[offset as usize..end].fill(0);
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), SinkError> {
+        self.open = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+#![cfg(all(feature = "embedded-io", feature = "embedded-hal-nb"))]
+
+mod common;
+
+use common::data;
+use core::{convert::Infallible, time::Duration};
+use embedded_hal_nb::{nb, serial};
+use std::{collections::VecDeque, sync::mpsc, thread, time::Instant};
+use zmodem::{
+    embedded::{IoDevice, NbDevice},
+    SerialDevice,
+};
+
+/// A UART connected to another one, polled without blocking.
+struct Uart {
+    tx: mpsc::Sender<u8>,
+    rx: mpsc::Receiver<u8>,
+    // A byte read ahead by `read_ready`.
+    next: Option<u8>,
+}
+
+fn uarts() -> (Uart, Uart) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    let a = Uart {
+        tx: a_tx,
+        rx: a_rx,
+        next: None,
+    };
+    let b = Uart {
+        tx: b_tx,
+        rx: b_rx,
+        next: None,
+    };
+    (a, b)
+}
+
+impl Uart {
+    fn poll(&mut self) -> Option<u8> {
+        if self.next.is_none() {
+            self.next = self.rx.try_recv().ok();
+        }
+        self.next
+    }
+}
+
+impl embedded_io::ErrorType for Uart {
+    type Error = Infallible;
+}
+
+impl embedded_io::ReadReady for Uart {
+    fn read_ready(&mut self) -> Result<bool, Infallible> {
+        Ok(self.poll().is_some())
+    }
+}
+
+impl embedded_io::Read for Uart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+        // Blocking, as the trait requires.
+        let byte = match self.next.take() {
+            Some(byte) => byte,
+            None => self.rx.recv().unwrap(),
+        };
+        buf[0] = byte;
+        Ok(1)
+    }
+}
+
+impl embedded_io::Write for Uart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+        for byte in buf {
+            self.tx.send(*byte).ok();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl serial::ErrorType for Uart {
+    type Error = serial::ErrorKind;
+}
+
+impl serial::Read for Uart {
+    fn read(&mut self) -> nb::Result<u8, serial::ErrorKind> {
+        self.poll();
+        self.next.take().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl serial::Write for Uart {
+    fn write(&mut self, byte: u8) -> nb::Result<(), serial::ErrorKind> {
+        self.tx.send(byte).ok();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), serial::ErrorKind> {
+        Ok(())
+    }
+}
+
+fn clock() -> impl Fn() -> Duration {
+    let start = Instant::now();
+    move || start.elapsed()
+}
+
+fn transfer<S, R>(sender: S, receiver: R)
+where
+    S: SerialDevice + Send + 'static,
+    R: SerialDevice,
+{
+    let data = data(20_000);
+    let sent = data.clone();
+    let sender = thread::spawn(move || zmodem::send(sender, "data.bin", &sent).unwrap());
+    let mut output = vec![0; data.len()];
+    let len = zmodem::receive(receiver, &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(output[..len], data);
+}
+
+#[test]
+fn io_transfer() {
+    let (a, b) = uarts();
+    transfer(IoDevice::new(a, clock()), IoDevice::new(b, clock()));
+}
+
+#[test]
+fn nb_transfer() {
+    let (a, b) = uarts();
+    transfer(NbDevice::new(a, clock()), NbDevice::new(b, clock()));
+}
+
+#[test]
+fn timeout() {
+    let (a, _b) = uarts();
+    let mut dev = IoDevice::new(a, clock());
+    assert_eq!(dev.recv(Duration::from_millis(10)).unwrap(), None);
+    let (a, _b) = uarts();
+    let mut dev = NbDevice::new(a, clock());
+    assert_eq!(dev.recv(Duration::from_millis(10)).unwrap(), None);
+}
+
+/// Replays a list of results from `read`.
+struct Faulty(VecDeque<nb::Result<u8, serial::ErrorKind>>);
+
+impl serial::ErrorType for Faulty {
+    type Error = serial::ErrorKind;
+}
+
+impl serial::Read for Faulty {
+    fn read(&mut self) -> nb::Result<u8, serial::ErrorKind> {
+        self.0.pop_front().unwrap_or(Err(nb::Error::WouldBlock))
+    }
+}
+
+impl serial::Write for Faulty {
+    fn write(&mut self, _byte: u8) -> nb::Result<(), serial::ErrorKind> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), serial::ErrorKind> {
+        Ok(())
+    }
+}
+
+#[test]
+fn line_errors() {
+    let results = [
+        Err(nb::Error::Other(serial::ErrorKind::Parity)),
+        Err(nb::Error::WouldBlock),
+        Ok(b'a'),
+        Err(nb::Error::Other(serial::ErrorKind::Other)),
+    ];
+    let mut dev = NbDevice::new(Faulty(results.into()), clock());
+    let timeout = Duration::from_secs(1);
+    // Corrupted bytes are dropped, other errors are returned.
+    assert_eq!(dev.recv(timeout).unwrap(), Some(b'a'));
+    assert_eq!(dev.recv(timeout).unwrap_err(), serial::ErrorKind::Other);
+}
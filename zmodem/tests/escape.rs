@@ -0,0 +1,171 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{pipe, send_file, send_raw, session, MemorySink, Pipe};
+use std::time::Duration;
+use zmodem::{
+    codec::HeaderDecoder,
+    proto::{
+        consts::{ZDLE, ZHIGH},
+        EscapeMode, FrameEncoding, FrameHeader, FrameType, ReceiverCapabilities, TransmitterFlags,
+    },
+    recv::Receiver,
+    send::Sender,
+    server::Served,
+    Error, SerialDevice,
+};
+
+#[test]
+fn negotiation() {
+    use EscapeMode::*;
+
+    // ESC8 and TESC8 only select the 8th bit escape along with our own flag for it.
+    for (capabilities, mode) in [
+        (ReceiverCapabilities::empty(), Clean),
+        (ReceiverCapabilities::ESCCTL, Control),
+        (ReceiverCapabilities::ESC8, Control),
+        (
+            ReceiverCapabilities::ESCCTL | ReceiverCapabilities::ESC8,
+            Control,
+        ),
+        (
+            ReceiverCapabilities::ESC8 | ReceiverCapabilities::ESCHIGH,
+            EightBit,
+        ),
+    ] {
+        assert_eq!(EscapeMode::from_capabilities(capabilities), mode);
+    }
+    for (flags, mode) in [
+        (TransmitterFlags::empty(), Clean),
+        (TransmitterFlags::TESCCTL, Control),
+        (TransmitterFlags::TESC8, Control),
+        (
+            TransmitterFlags::TESC8 | TransmitterFlags::TESCHIGH,
+            EightBit,
+        ),
+    ] {
+        assert_eq!(EscapeMode::from_transmitter_flags(flags), mode);
+    }
+    for mode in [Clean, Control, EightBit] {
+        assert_eq!(EscapeMode::from_capabilities(mode.capabilities()), mode);
+        assert_eq!(
+            EscapeMode::from_transmitter_flags(mode.transmitter_flags()),
+            mode
+        );
+    }
+}
+
+/// One end of a link which drops control characters, but for ZDLE and the CR LF XON ending
+/// hex headers. It also strips the 8th bit if `mode` is `EightBit`.
+fn link(end: Pipe, mode: EscapeMode) -> Pipe {
+    end.set_fault(move |_, byte| {
+        let byte = match mode {
+            EscapeMode::EightBit => byte & 0x7f,
+            _ => byte,
+        };
+        (byte & 0x60 != 0 || matches!(byte & 0x7f, 0x18 | b'\r' | b'\n' | 0x11)).then_some(byte)
+    })
+}
+
+/// Upload a file with every byte value to a server and download one back, over a link that
+/// needs `mode`, which one end asks for: the sender in its ZSINIT, or the receiver in its
+/// ZRINIT. The other end only follows, with the ZHIGH escape enabled.
+fn transfer(mode: EscapeMode, sender_asks: bool) {
+    let up: Vec<u8> = (0..=255).cycle().take(3000).collect();
+    let down: Vec<u8> = up.iter().rev().copied().collect();
+
+    let (a, b) = pipe();
+    let (a, b) = (link(a, mode), link(b, mode));
+    let sent = up.clone();
+    let client = move |dev| -> Result<MemorySink, Error<()>> {
+        let mut sender = Sender::new(dev);
+        sender = match sender_asks {
+            true => sender.set_escape(mode),
+            false => sender.set_high_escapes(true),
+        };
+        sender.start()?;
+        send_file(&mut sender, "up.bin", &sent)?;
+        let mut sink = MemorySink::default();
+        sender.into_receiver()?.receive_files(&mut sink)?;
+        Ok(sink)
+    };
+
+    let mut uploaded = MemorySink::default();
+    let returned = down.clone();
+    let (client, served) = session((a, b), client, |dev| {
+        let mut receiver = Receiver::new(dev);
+        receiver = match sender_asks {
+            true => receiver.set_high_escapes(true),
+            false => receiver.set_escape(mode),
+        };
+        receiver.send_zrinit()?;
+        match receiver.serve(&mut uploaded)? {
+            Served::Send(mut sender) => {
+                send_file(&mut sender, "down.bin", &returned)?;
+                sender.finish()
+            }
+            Served::Finished => panic!("session not reversed"),
+        }
+    });
+    served.unwrap();
+    assert_eq!(uploaded.data("up.bin"), up, "{mode:?}");
+    assert_eq!(client.unwrap().data("down.bin"), down, "{mode:?}");
+}
+
+#[test]
+fn sender_asks() {
+    transfer(EscapeMode::Control, true);
+    transfer(EscapeMode::EightBit, true);
+}
+
+#[test]
+fn receiver_asks() {
+    transfer(EscapeMode::Control, false);
+    transfer(EscapeMode::EightBit, false);
+}
+
+#[test]
+fn reserved_bits() {
+    // A receiver asking for ESC8 and setting the bit ESCHIGH uses, for its own reasons, only gets
+    // control characters escaped unless the extension is enabled.
+    let data: Vec<u8> = (0..=255).cycle().take(3000).collect();
+    let sender = move |dev| -> Result<(), Error<()>> {
+        let mut sender = Sender::new(dev);
+        sender.start()?;
+        send_file(&mut sender, "a.bin", &data)?;
+        sender.finish()
+    };
+    let (sent, received) = session(pipe(), sender, |mut dev| {
+        let capabilities = ReceiverCapabilities::CANFDX
+            | ReceiverCapabilities::CANOVIO
+            | ReceiverCapabilities::CANFC32
+            | ReceiverCapabilities::ESC8
+            | ReceiverCapabilities::ESCHIGH;
+        let zrinit =
+            FrameHeader::new(FrameEncoding::HEX, FrameType::ZRINIT).set_zrinit(capabilities, 0);
+        send_raw(&mut dev, zrinit, None);
+        let mut received = Vec::new();
+        let mut decoder = HeaderDecoder::new(EscapeMode::Control);
+        loop {
+            let byte = dev.recv(Duration::from_secs(5)).unwrap().unwrap();
+            received.push(byte);
+            let Ok(Some(header)) = decoder.push(byte) else {
+                continue;
+            };
+            let reply = match header.r#type {
+                FrameType::ZFILE => FrameHeader::new(FrameEncoding::HEX, FrameType::ZRPOS),
+                FrameType::ZEOF => zrinit,
+                FrameType::ZFIN => FrameHeader::new(FrameEncoding::HEX, FrameType::ZFIN),
+                _ => continue,
+            };
+            send_raw(&mut dev, reply, None);
+            if header.r#type == FrameType::ZFIN {
+                break received;
+            }
+        }
+    });
+    sent.unwrap();
+    assert!(!received.windows(2).any(|pair| pair == [ZDLE, ZHIGH]));
+    assert!(received.iter().any(|&byte| byte >= 0xa0));
+}
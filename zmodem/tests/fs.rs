@@ -0,0 +1,216 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{pipe, session, Pipe};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::UNIX_EPOCH,
+};
+use zmodem::{
+    fs::FileSink,
+    policy::DefaultPolicy,
+    proto::{FileInfo, FileOptions, ManagementOption},
+    recv::Receiver,
+    send::{Sender, Transfer},
+    sink::{OpenMode, Sink, SinkError},
+    Error,
+};
+
+fn root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("zmodem-fs-{}-{name}", process::id()));
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir_all(&root).unwrap();
+    root
+}
+
+fn receive(sink: &mut FileSink, info: &FileInfo, mode: OpenMode, data: &[u8]) {
+    sink.open(info, mode).unwrap();
+    sink.write(0, data).unwrap();
+    sink.close().unwrap();
+}
+
+#[test]
+fn path() {
+    let root = root("path");
+    let sink = FileSink::new(&root);
+    assert_eq!(sink.path("a.bin"), Some(root.join("a.bin")));
+    assert_eq!(sink.path("./a.bin"), Some(root.join("a.bin")));
+    for name in [
+        "",
+        ".",
+        "../a",
+        "/etc/passwd",
+        "\\a",
+        "C:a",
+        "a\0b",
+        "dir/a",
+        "dir\\a",
+    ] {
+        assert_eq!(sink.path(name), None, "{name:?}");
+    }
+
+    let sink = FileSink::new(&root).set_subdirectories(true);
+    assert_eq!(sink.path("dir/a"), Some(root.join("dir").join("a")));
+    assert_eq!(sink.path("dir\\a"), Some(root.join("dir").join("a")));
+    assert_eq!(sink.path("dir/../../a"), None);
+    fs::remove_dir_all(root).ok();
+}
+
+#[test]
+fn receive_files() {
+    let root = root("receive");
+    let mut sink = FileSink::new(&root).set_subdirectories(true);
+
+    let mut info = FileInfo::new("dir/a.bin");
+    info.mtime = Some(1_000_000_000);
+    info.mode = Some(0o104755);
+    receive(&mut sink, &info, OpenMode::Create, b"hello");
+
+    let path = root.join("dir").join("a.bin");
+    assert_eq!(fs::read(&path).unwrap(), b"hello");
+    let metadata = fs::metadata(&path).unwrap();
+    let mtime = metadata.modified().unwrap().duration_since(UNIX_EPOCH);
+    assert_eq!(mtime.unwrap().as_secs(), 1_000_000_000);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+    }
+
+    // Existing files are only replaced when asked to.
+    assert!(matches!(
+        sink.open(&info, OpenMode::Create),
+        Err(SinkError::Refused)
+    ));
+    receive(&mut sink, &info, OpenMode::Replace, b"bye");
+    assert_eq!(fs::read(&path).unwrap(), b"bye");
+
+    sink.open(&info, OpenMode::Append).unwrap();
+    sink.write(3, b"!").unwrap();
+    sink.close().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"bye!");
+
+    // An unfinished file leaves the destination alone, and no temporary files behind.
+    sink.open(&info, OpenMode::Replace).unwrap();
+    sink.write(0, b"partial").unwrap();
+    drop(sink);
+    assert_eq!(fs::read(&path).unwrap(), b"bye!");
+    assert_eq!(fs::read_dir(root.join("dir")).unwrap().count(), 1);
+    fs::remove_dir_all(root).ok();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let root = root("symlinks");
+    let outside = root.join("outside");
+    let inside = root.join("inside");
+    fs::create_dir_all(&outside).unwrap();
+    fs::create_dir_all(&inside).unwrap();
+    fs::write(outside.join("a"), b"secret").unwrap();
+    symlink(&outside, inside.join("dir")).unwrap();
+    symlink(outside.join("a"), inside.join("a")).unwrap();
+    let mut sink = FileSink::new(&inside).set_subdirectories(true);
+
+    // Files behind a symlink, or through a symlinked directory, are neither read nor written.
+    for name in ["a", "dir/a", "dir/b"] {
+        let info = FileInfo::new(name);
+        assert!(sink.existing(&info).is_none(), "{name}");
+        assert_eq!(sink.crc32(&info, 6), None, "{name}");
+        for mode in [OpenMode::Create, OpenMode::Append, OpenMode::Replace] {
+            assert!(
+                matches!(sink.open(&info, mode), Err(SinkError::Refused)),
+                "{name} {mode:?}"
+            );
+        }
+    }
+    assert_eq!(fs::read(outside.join("a")).unwrap(), b"secret");
+    assert_eq!(fs::read_dir(&outside).unwrap().count(), 1);
+
+    // A file created while one of the same name is received is kept.
+    let info = FileInfo::new("c");
+    sink.open(&info, OpenMode::Create).unwrap();
+    sink.write(0, b"received").unwrap();
+    fs::write(inside.join("c"), b"created").unwrap();
+    assert!(matches!(sink.close(), Err(SinkError::Refused)));
+    assert_eq!(fs::read(inside.join("c")).unwrap(), b"created");
+    assert_eq!(fs::read_dir(&inside).unwrap().count(), 3);
+    fs::remove_dir_all(root).ok();
+}
+
+/// Send `data` as "a.txt" with `management`, to a receiver storing it under `root`.
+fn send_over(
+    root: &Path,
+    management: ManagementOption,
+    mut policy: DefaultPolicy,
+    data: &'static [u8],
+) -> Transfer {
+    let sender = move |dev: Pipe| -> Result<Transfer, Error<()>> {
+        let mut sender = Sender::new(dev);
+        sender.start()?;
+        let mut info = FileInfo::new("a.txt");
+        info.size = Some(data.len() as u64);
+        let options = FileOptions {
+            management,
+            ..FileOptions::default()
+        };
+        let mut source = data;
+        let transfer = sender.send_file(&info, &options, &mut source)?;
+        sender.finish()?;
+        Ok(transfer)
+    };
+    let mut sink = FileSink::new(root);
+    let (sent, received) = session(pipe(), sender, |dev| {
+        let mut receiver = Receiver::new(dev).set_policy(&mut policy);
+        receiver.send_zrinit()?;
+        receiver.receive_files(&mut sink)
+    });
+    received.unwrap();
+    sent.unwrap()
+}
+
+#[test]
+fn receive_over_existing() {
+    let root = root("existing");
+    let path = root.join("a.txt");
+    fs::write(&path, b"original").unwrap();
+    let none = ManagementOption(0);
+    let default = DefaultPolicy::default();
+
+    // An existing file is kept by default.
+    let transfer = send_over(&root, none, default, b"first");
+    assert_eq!(transfer, Transfer::Skipped);
+    assert_eq!(fs::read(&path).unwrap(), b"original");
+
+    // Unless the sender asks to replace it.
+    let transfer = send_over(&root, ManagementOption::ZMCLOB, default, b"second");
+    assert_eq!(transfer, Transfer::Sent);
+    assert_eq!(fs::read(&path).unwrap(), b"second");
+
+    // Or the receiver's policy allows it.
+    let clobber = DefaultPolicy {
+        management: Some(ManagementOption::ZMCLOB),
+        ..DefaultPolicy::default()
+    };
+    let transfer = send_over(&root, none, clobber, b"third");
+    assert_eq!(transfer, Transfer::Sent);
+    assert_eq!(fs::read(&path).unwrap(), b"third");
+
+    // Which can also keep files whatever the sender asks.
+    let protect = DefaultPolicy {
+        management: Some(ManagementOption::ZMPROT),
+        ..DefaultPolicy::default()
+    };
+    let transfer = send_over(&root, ManagementOption::ZMCLOB, protect, b"fourth");
+    assert_eq!(transfer, Transfer::Skipped);
+    assert_eq!(fs::read(&path).unwrap(), b"third");
+
+    // No temporary files are left behind.
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+    fs::remove_dir_all(root).ok();
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{data, pipe, Pipe};
+use core::time::Duration;
+use std::thread;
+use zmodem::{kermit::Receiver, Protocol, SerialDevice};
+
+const SOH: u8 = 0x01;
+const CR: u8 = 0x0d;
+
+fn tochar(byte: u8) -> u8 {
+    byte + 32
+}
+
+fn check1(bytes: &[u8]) -> u8 {
+    let sum = bytes.iter().fold(0u32, |sum, byte| sum + *byte as u32);
+    tochar(((sum + ((sum & 0xc0) >> 6)) & 0x3f) as u8)
+}
+
+fn packet(seq: u8, r#type: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![SOH, tochar(data.len() as u8 + 3), tochar(seq), r#type];
+    packet.extend_from_slice(data);
+    packet.push(check1(&packet[1..]));
+    packet.push(CR);
+    packet
+}
+
+/// Receive a packet up to its end of line, returning its sequence number and type.
+fn recv_packet(dev: &mut Pipe) -> (u8, u8) {
+    let mut packet = Vec::new();
+    loop {
+        match dev.recv(Duration::from_secs(5)).unwrap().unwrap() {
+            SOH => packet.clear(),
+            CR => break,
+            byte => packet.push(byte),
+        }
+    }
+    (packet[1] - 32, packet[2])
+}
+
+/// Send `packet` until the receiver acknowledges it, passing the first attempt through `first`.
+fn send_packet(dev: &mut Pipe, packet: &[u8], first: impl FnOnce(&mut Vec<u8>)) {
+    let mut attempt = packet.to_vec();
+    first(&mut attempt);
+    loop {
+        for byte in &attempt {
+            dev.send(*byte).unwrap();
+        }
+        let (seq, r#type) = recv_packet(dev);
+        if r#type == b'Y' && seq == packet[2] - 32 {
+            return;
+        }
+        assert_eq!(r#type, b'N');
+        attempt = packet.to_vec();
+    }
+}
+
+/// A Kermit sender, with control characters prefixed by '#' and 8-bit bytes by '&'.
+fn kermit_send(mut dev: Pipe, data: &[u8], corrupt: bool) {
+    // MAXL, TIME, NPAD, PADC, EOL, QCTL, QBIN
+    let init = [
+        tochar(94),
+        tochar(10),
+        tochar(0),
+        b'@',
+        tochar(CR),
+        b'#',
+        b'&',
+    ];
+    send_packet(&mut dev, &packet(0, b'S', &init), |_| ());
+    send_packet(&mut dev, &packet(1, b'F', b"DATA.BIN"), |_| ());
+
+    let mut seq = 2;
+    let mut encoded = Vec::new();
+    for (i, byte) in data.iter().enumerate() {
+        if *byte & 0x80 != 0 {
+            encoded.push(b'&');
+        }
+        match *byte & 0x7f {
+            low @ (0..=0x1f | 0x7f) => encoded.extend([b'#', low ^ 64]),
+            low @ (b'#' | b'&') => encoded.extend([b'#', low]),
+            low => encoded.push(low),
+        }
+        if encoded.len() > 80 || i == data.len() - 1 {
+            let corrupt = corrupt && seq == 2;
+            send_packet(&mut dev, &packet(seq, b'D', &encoded), |attempt| {
+                if corrupt {
+                    attempt[5] ^= 1;
+                }
+            });
+            encoded.clear();
+            seq += 1;
+        }
+    }
+    send_packet(&mut dev, &packet(seq, b'Z', &[]), |_| ());
+    send_packet(&mut dev, &packet(seq + 1, b'B', &[]), |_| ());
+}
+
+#[test]
+fn receive() {
+    let (sender, receiver) = pipe();
+    let data = data(300);
+    let sent = data.clone();
+    let sender = thread::spawn(move || kermit_send(sender, &sent, true));
+
+    let mut output = vec![0; 1024];
+    let len = Receiver::new(receiver).receive(&mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(output[..len], data);
+}
+
+#[test]
+fn detect() {
+    let (mut sender, receiver) = pipe();
+    let data = data(300);
+    let sent = data.clone();
+    let sender = thread::spawn(move || {
+        // Wait for the receiver's invitation.
+        while sender.recv(Duration::from_secs(5)).unwrap() != Some(b'C') {}
+        kermit_send(sender, &sent, false)
+    });
+
+    let mut output = vec![0; 1024];
+    let (protocol, len) = zmodem::receive_auto(receiver, &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(protocol, Protocol::Kermit);
+    assert_eq!(output[..len], data);
+}
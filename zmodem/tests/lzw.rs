@@ -0,0 +1,142 @@
+#![cfg(all(feature = "std", feature = "lzw"))]
+
+mod common;
+
+use common::{pipe, session, MemorySink};
+use proptest::prelude::*;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use zmodem::{
+    lzw::{Decoder, Encoder},
+    proto::{FileInfo, FileOptions, TransportOption},
+    recv::Receiver,
+    send::{Sender, Transfer},
+    MAX_SUBPACKET_LEN,
+};
+
+/// Compress `data` in subpackets of the lengths in `cuts`, repeated as needed, and decompress
+/// them again.
+fn round_trip(data: &[u8], cuts: &[usize]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    let mut decoder = Decoder::new();
+    let mut packed = vec![0; MAX_SUBPACKET_LEN];
+    let mut plain = vec![0; MAX_SUBPACKET_LEN];
+    let mut output = Vec::new();
+    let mut rest = data;
+    for cut in cuts.iter().cycle() {
+        if rest.is_empty() {
+            break;
+        }
+        let (input, next) = rest.split_at((*cut).min(rest.len()));
+        let len = encoder.encode(input, &mut packed);
+        assert!(
+            len <= input.len().div_ceil(2) * 3,
+            "{len} from {}",
+            input.len()
+        );
+        let len = decoder.decode(&packed[..len], &mut plain).unwrap();
+        output.extend_from_slice(&plain[..len]);
+        rest = next;
+    }
+    output
+}
+
+/// Bytes that rarely repeat, filling the dictionary quickly.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn code_widths() {
+    // Subpackets of two bytes each add one code, so one ends at every dictionary size: each
+    // change of code width, and the dictionary filling up.
+    let data = noise(20_000);
+    for cuts in [[2], [3], [5], [682]] {
+        assert!(round_trip(&data, &cuts) == data, "{cuts:?}");
+    }
+}
+
+#[test]
+fn full_dictionary() {
+    // Runs of zeros longer than any string in a full dictionary.
+    let mut data = noise(12_000);
+    data.extend(vec![0; 100_000]);
+    data.extend(noise(1_000));
+    assert!(round_trip(&data, &[682]) == data);
+}
+
+fn data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        proptest::collection::vec(any::<u8>(), 0..16_000),
+        // Few symbols, for long strings.
+        proptest::collection::vec(0u8..4, 0..16_000),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn subpackets(
+        data in data(),
+        cuts in proptest::collection::vec(1usize..=682, 1..16),
+    ) {
+        prop_assert!(round_trip(&data, &cuts) == data);
+    }
+}
+
+#[test]
+fn session_compressed() {
+    let data: Vec<u8> = (0..2000)
+        .flat_map(|i| format!("line {} of the file\n", i % 37).into_bytes())
+        .collect();
+    let sent = data.clone();
+    let options = FileOptions {
+        transport: TransportOption::ZTLZW,
+        ..FileOptions::default()
+    };
+
+    // Count what the sender puts on the link.
+    let (a, b) = pipe();
+    let count = Arc::new(AtomicUsize::new(0));
+    let counted = count.clone();
+    let a = a.set_fault(move |_, byte| {
+        counted.fetch_add(1, Ordering::Relaxed);
+        Some(byte)
+    });
+
+    let mut sink = MemorySink::default();
+    let (transfer, received) = session(
+        (a, b),
+        move |dev| {
+            let mut sender = Sender::new(dev);
+            sender.start().unwrap();
+            let mut info = FileInfo::new("data.txt");
+            info.size = Some(sent.len() as u64);
+            let mut source = &sent[..];
+            let transfer = sender.send_file(&info, &options, &mut source).unwrap();
+            sender.finish().unwrap();
+            transfer
+        },
+        |dev| {
+            let mut receiver = Receiver::new(dev);
+            receiver.send_zrinit()?;
+            receiver.receive_files(&mut sink)
+        },
+    );
+    assert_eq!(transfer, Transfer::Sent);
+    received.unwrap();
+    assert!(sink.data("data.txt") == data);
+    let count = count.load(Ordering::Relaxed);
+    assert!(count < data.len() / 4, "{count} bytes for {}", data.len());
+}
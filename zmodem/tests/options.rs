@@ -0,0 +1,125 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{info, pipe, run_receiver, run_sender, session, MemorySink, Pipe};
+use zmodem::{
+    policy::DefaultPolicy,
+    proto::{ConversionOption, ExtendedOptions, FileOptions, ManagementOption},
+    recv::Receiver,
+    send::Sender,
+    Error,
+};
+
+/// Send `data` as `name` with `options`, in subpackets of up to `subpacket_len` bytes.
+fn send(
+    dev: Pipe,
+    name: &'static str,
+    data: Vec<u8>,
+    options: FileOptions,
+    subpacket_len: usize,
+) -> Result<(), Error<()>> {
+    let sender = Sender::new(dev).set_max_subpacket_len(subpacket_len);
+    run_sender(sender, |sender| {
+        let mut source = &data[..];
+        sender.send_file(&info(name, &data), &options, &mut source)
+    })?;
+    Ok(())
+}
+
+fn receive(dev: Pipe, sink: &mut MemorySink, mut policy: DefaultPolicy) -> Result<(), Error<()>> {
+    run_receiver(Receiver::new(dev).set_policy(&mut policy), sink)
+}
+
+fn text() -> FileOptions {
+    FileOptions {
+        conversion: ConversionOption::ZCNL,
+        ..FileOptions::default()
+    }
+}
+
+#[test]
+fn line_ends() {
+    // Subpackets are 32 bytes long, so line ends and lone CRs fall on either side of a
+    // subpacket boundary. Anything after a CP/M end of file is dropped.
+    let mut data = b"first line\r\nsecond line\r\nlast  \r".to_vec();
+    data.extend_from_slice(b"\nlone \r in a line\r\n\r\rat the end\r");
+    data.extend_from_slice(b"x, then more line ends: \r\r\n\r\n\r");
+    data.extend_from_slice(b"\x1a\r\ntrailing garbage");
+    let expected = b"first line\nsecond line\nlast  \nlone \r in a line\n\r\rat the end\r\
+        x, then more line ends: \r\n\n\r";
+
+    let mut sink = MemorySink::default();
+    let (sent, received) = session(
+        pipe(),
+        move |dev| send(dev, "a.txt", data, text(), 32),
+        |dev| receive(dev, &mut sink, DefaultPolicy::default()),
+    );
+    sent.unwrap();
+    received.unwrap();
+    assert_eq!(sink.data("a.txt"), expected);
+
+    // A CR ending the file is kept.
+    let mut sink = MemorySink::default();
+    let (sent, received) = session(
+        pipe(),
+        move |dev| send(dev, "b.txt", b"line\r\nend\r".to_vec(), text(), 32),
+        |dev| receive(dev, &mut sink, DefaultPolicy::default()),
+    );
+    sent.unwrap();
+    received.unwrap();
+    assert_eq!(sink.data("b.txt"), b"line\nend\r");
+}
+
+#[test]
+fn text_not_resumed() {
+    // The existing file is shorter than the part of the file it was received from, so the
+    // whole file is received again.
+    let mut sink = MemorySink::default();
+    sink.insert("a.txt", b"one\ntwo\n", None);
+    let policy = DefaultPolicy {
+        conversion: Some(ConversionOption::ZCRESUM),
+        management: Some(ManagementOption::ZMCLOB),
+    };
+    let (sent, received) = session(
+        pipe(),
+        |dev| {
+            send(
+                dev,
+                "a.txt",
+                b"one\r\ntwo\r\nthree\r\n".to_vec(),
+                text(),
+                1024,
+            )
+        },
+        |dev| receive(dev, &mut sink, policy),
+    );
+    sent.unwrap();
+    received.unwrap();
+    assert_eq!(sink.data("a.txt"), b"one\ntwo\nthree\n");
+}
+
+#[test]
+fn sparse() {
+    let mut data = vec![1; 2048];
+    data.extend(vec![0; 8192]);
+    data.extend(vec![2; 1024]);
+    data.extend(vec![0; 4096]);
+    let sent = data.clone();
+    let options = FileOptions {
+        extended: ExtendedOptions::ZXSPARS,
+        ..FileOptions::default()
+    };
+
+    // The runs of zeros, a subpacket long or more, are skipped and left as holes.
+    let mut sink = MemorySink::default();
+    let (sent, received) = session(
+        pipe(),
+        move |dev| send(dev, "a.bin", sent, options, 1024),
+        |dev| receive(dev, &mut sink, DefaultPolicy::default()),
+    );
+    sent.unwrap();
+    received.unwrap();
+    assert!(sink.data("a.bin") == data);
+    assert_eq!(sink.holes, [(2048, 8192), (11_264, 4096)]);
+}
@@ -0,0 +1,114 @@
+use zmodem::{
+    policy::{Action, DefaultPolicy, Policy},
+    proto::{ConversionOption, FileInfo, FileOptions, ManagementFlags, ManagementOption},
+    sink::ExistingFile,
+};
+
+const EXISTING: ExistingFile = ExistingFile {
+    size: 100,
+    mtime: Some(1_000),
+};
+
+fn info(size: u64, mtime: u64) -> FileInfo<'static> {
+    let mut info = FileInfo::new("a.bin");
+    info.size = Some(size);
+    info.mtime = Some(mtime);
+    info
+}
+
+fn with_management(management: ManagementOption) -> FileOptions {
+    FileOptions {
+        management,
+        ..FileOptions::default()
+    }
+}
+
+fn decide(policy: &mut DefaultPolicy, info: &FileInfo, options: &FileOptions) -> Action {
+    policy.decide(info, options, Some(&EXISTING))
+}
+
+#[test]
+fn new_files() {
+    let mut policy = DefaultPolicy::default();
+    let info = info(100, 1_000);
+    let mut options = FileOptions::default();
+    assert_eq!(policy.decide(&info, &options, None), Action::Accept);
+    options.management_flags = ManagementFlags::ZMSKNOLOC;
+    assert_eq!(policy.decide(&info, &options, None), Action::Skip);
+}
+
+#[test]
+fn management_options() {
+    let mut policy = DefaultPolicy::default();
+    let none = ManagementOption(0);
+    for (management, size, mtime, action) in [
+        // Existing files are kept unless the sender asks otherwise.
+        (none, 100, 1_000, Action::Skip),
+        (none, 100, 2_000, Action::Skip),
+        (none, 50, 1_000, Action::Skip),
+        (ManagementOption::ZMNEWL, 50, 500, Action::Skip),
+        (ManagementOption::ZMNEWL, 200, 500, Action::Accept),
+        (ManagementOption::ZMNEWL, 50, 2_000, Action::Accept),
+        (ManagementOption::ZMNEW, 200, 1_000, Action::Skip),
+        (ManagementOption::ZMNEW, 50, 2_000, Action::Accept),
+        (ManagementOption::ZMDIFF, 100, 1_000, Action::Skip),
+        (ManagementOption::ZMDIFF, 100, 500, Action::Accept),
+        (ManagementOption::ZMPROT, 50, 2_000, Action::Skip),
+        (ManagementOption::ZMAPND, 50, 500, Action::Append),
+        (ManagementOption::ZMCRC, 100, 500, Action::CompareCrc),
+        (ManagementOption::ZMCRC, 50, 500, Action::Accept),
+        (ManagementOption::ZMCLOB, 100, 1_000, Action::Accept),
+    ] {
+        let options = with_management(management);
+        assert_eq!(
+            decide(&mut policy, &info(size, mtime), &options),
+            action,
+            "{management:?} {size} {mtime}"
+        );
+    }
+}
+
+#[test]
+fn receiver_options() {
+    // The receiver's management option takes precedence over the sender's.
+    let mut policy = DefaultPolicy {
+        management: Some(ManagementOption::ZMPROT),
+        ..DefaultPolicy::default()
+    };
+    let options = with_management(ManagementOption::ZMCLOB);
+    assert_eq!(
+        decide(&mut policy, &info(50, 2_000), &options),
+        Action::Skip
+    );
+
+    // Resuming applies to files no shorter than the existing one, even if the sender asks for
+    // binary.
+    let mut policy = DefaultPolicy {
+        conversion: Some(ConversionOption::ZCRESUM),
+        ..DefaultPolicy::default()
+    };
+    let mut options = with_management(ManagementOption::ZMCLOB);
+    options.conversion = ConversionOption::ZCBIN;
+    assert_eq!(
+        decide(&mut policy, &info(200, 500), &options),
+        Action::Resume
+    );
+    assert_eq!(
+        decide(&mut policy, &info(50, 500), &options),
+        Action::Accept
+    );
+    // But not to text files.
+    options.conversion = ConversionOption::ZCNL;
+    assert_eq!(
+        decide(&mut policy, &info(200, 500), &options),
+        Action::Accept
+    );
+
+    // Otherwise the sender's conversion option applies.
+    let mut policy = DefaultPolicy::default();
+    options.conversion = ConversionOption::ZCRESUM;
+    assert_eq!(
+        decide(&mut policy, &info(200, 500), &options),
+        Action::Resume
+    );
+}
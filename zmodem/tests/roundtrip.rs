@@ -0,0 +1,104 @@
+mod common;
+
+use common::Loopback;
+use core::time::Duration;
+use proptest::prelude::*;
+use zmodem::{
+    proto::{EscapeMode, FrameEncoding, FrameHeader, FrameType, PacketType},
+    recv::Receiver,
+};
+
+fn receiver<'a>(escape: EscapeMode, noise: Vec<(usize, u8)>) -> Receiver<'a, Loopback> {
+    // Only links that need the 8-bit escapes can lose the 8th bit.
+    let mask = match escape {
+        EscapeMode::EightBit => 0x7f,
+        _ => 0xff,
+    };
+    Receiver::new(Loopback::new(mask, noise)).set_escape(escape)
+}
+
+fn escape_mode() -> impl Strategy<Value = EscapeMode> {
+    prop_oneof![
+        Just(EscapeMode::Clean),
+        Just(EscapeMode::Control),
+        Just(EscapeMode::EightBit),
+    ]
+}
+
+fn header_encoding() -> impl Strategy<Value = FrameEncoding> {
+    prop_oneof![
+        Just(FrameEncoding::HEX),
+        Just(FrameEncoding::BIN16),
+        Just(FrameEncoding::BIN32),
+    ]
+}
+
+fn data_encoding() -> impl Strategy<Value = FrameEncoding> {
+    prop_oneof![Just(FrameEncoding::BIN16), Just(FrameEncoding::BIN32)]
+}
+
+fn packet_type() -> impl Strategy<Value = PacketType> {
+    prop_oneof![
+        Just(PacketType::ZCRCE),
+        Just(PacketType::ZCRCG),
+        Just(PacketType::ZCRCQ),
+        Just(PacketType::ZCRCW),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn header(
+        escape in escape_mode(),
+        encoding in header_encoding(),
+        r#type in any::<u8>(),
+        data in any::<[u8; 4]>(),
+    ) {
+        let mut receiver = receiver(escape, Vec::new());
+        let mut frame = FrameHeader::new(encoding, FrameType(r#type));
+        frame.data = data;
+        receiver.send_frame(frame).unwrap();
+
+        let received = receiver.receive_frame_header(Duration::ZERO).unwrap();
+        prop_assert_eq!(received.encoding, encoding);
+        prop_assert_eq!(received.r#type, FrameType(r#type));
+        prop_assert_eq!(received.data, data);
+    }
+
+    #[test]
+    fn data_packet(
+        escape in escape_mode(),
+        encoding in data_encoding(),
+        packet_type in packet_type(),
+        data in proptest::collection::vec(any::<u8>(), 0..1024),
+        spare in 0usize..2,
+    ) {
+        let mut receiver = receiver(escape, Vec::new());
+        receiver.send_data_packet(encoding, packet_type, &data).unwrap();
+
+        // Also covers subpackets that exactly fill the buffer.
+        let mut buf = vec![0; data.len() + spare];
+        let (received_type, received) = receiver.receive_data_packet(encoding, &mut buf).unwrap();
+        prop_assert_eq!(received_type, packet_type);
+        prop_assert_eq!(received, &data[..]);
+    }
+
+    #[test]
+    fn data_packet_control_noise(
+        encoding in data_encoding(),
+        data in proptest::collection::vec(any::<u8>(), 0..256),
+        mut noise in proptest::collection::vec((0usize..512, 0u8..0x20), 0..8),
+    ) {
+        // Control characters added by the link are ignored, as they're always escaped. ZDLE
+        // can't be told apart from an escape.
+        noise.retain(|(_, byte)| *byte != 0x18);
+        noise.sort();
+        let mut receiver = receiver(EscapeMode::Control, noise);
+        receiver.send_data_packet(encoding, PacketType::ZCRCE, &data).unwrap();
+
+        let mut buf = vec![0; data.len()];
+        let (packet_type, received) = receiver.receive_data_packet(encoding, &mut buf).unwrap();
+        prop_assert_eq!(packet_type, PacketType::ZCRCE);
+        prop_assert_eq!(received, &data[..]);
+    }
+}
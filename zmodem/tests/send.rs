@@ -0,0 +1,297 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{data, pipe, session, Pipe};
+use core::time::Duration;
+use std::{sync::mpsc, thread, time::Instant};
+use zmodem::{
+    proto::{
+        FileInfo, FileOptions, FrameEncoding, FrameHeader, FrameType, PacketType,
+        ReceiverCapabilities,
+    },
+    recv::Receiver,
+    send::{Sender, Strategy},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const FULL_DUPLEX: ReceiverCapabilities = ReceiverCapabilities::CANFDX
+    .union(ReceiverCapabilities::CANOVIO)
+    .union(ReceiverCapabilities::CANFC32);
+
+/// A data subpacket as received, with the offset of its data in the file.
+#[derive(Debug)]
+struct Subpacket {
+    encoding: FrameEncoding,
+    packet_type: PacketType,
+    pos: u32,
+    len: usize,
+    at: Instant,
+}
+
+/// What a receiver does after each subpacket, given the subpackets so far and a handle for
+/// sending raw bytes to the sender.
+type Hook = Box<dyn FnMut(&mut Receiver<Pipe>, &[Subpacket], &mpsc::Sender<u8>) + Send>;
+
+/// A receiver advertising `capabilities` and `buffer_len`, which acknowledges subpackets as
+/// asked and records them, until the sender finishes. Returns the received file and the
+/// subpackets.
+fn receive(
+    dev: Pipe,
+    capabilities: ReceiverCapabilities,
+    buffer_len: u16,
+    mut hook: Hook,
+) -> (Vec<u8>, Vec<Subpacket>) {
+    let inject = dev.injector();
+    let mut raw = Receiver::new(dev);
+    let zrinit = FrameHeader::new(FrameEncoding::HEX, FrameType::ZRINIT)
+        .set_zrinit(capabilities, buffer_len);
+    raw.send_frame(zrinit).unwrap();
+
+    let mut buf = vec![0; 8192];
+    let mut file = Vec::new();
+    let mut subpackets = Vec::new();
+    loop {
+        let frame = match raw.receive_frame_header(TIMEOUT) {
+            Ok(frame) => frame,
+            // The rest of a frame we asked to be resent.
+            Err(error) if !matches!(error, zmodem::Error::TimedOut) => continue,
+            Err(error) => panic!("{error:?}"),
+        };
+        match frame.r#type {
+            FrameType::ZRQINIT => raw.send_frame(zrinit).unwrap(),
+            FrameType::ZFILE => {
+                raw.receive_data_packet(frame.encoding, &mut buf).unwrap();
+                raw.send_zrpos(0).unwrap();
+            }
+            FrameType::ZDATA => {
+                let mut pos = frame.count();
+                file.truncate(pos as usize);
+                loop {
+                    let Ok((packet_type, data)) = raw.receive_data_packet(frame.encoding, &mut buf)
+                    else {
+                        raw.send_zrpos(file.len() as u32).unwrap();
+                        break;
+                    };
+                    file.extend_from_slice(data);
+                    subpackets.push(Subpacket {
+                        encoding: frame.encoding,
+                        packet_type,
+                        pos,
+                        len: data.len(),
+                        at: Instant::now(),
+                    });
+                    pos += data.len() as u32;
+                    if matches!(packet_type, PacketType::ZCRCQ | PacketType::ZCRCW) {
+                        raw.send_zack(pos).unwrap();
+                    }
+                    hook(&mut raw, &subpackets, &inject);
+                    if matches!(packet_type, PacketType::ZCRCE | PacketType::ZCRCW) {
+                        break;
+                    }
+                }
+            }
+            FrameType::ZEOF if frame.count() as usize == file.len() => {
+                raw.send_frame(zrinit).unwrap()
+            }
+            FrameType::ZEOF => raw.send_zrpos(file.len() as u32).unwrap(),
+            FrameType::ZFIN => {
+                raw.send_zfin().unwrap();
+                return (file, subpackets);
+            }
+            r#type => panic!("unexpected {type:?}"),
+        }
+    }
+}
+
+/// Send `data` with `sender`, returning what the receiver got.
+fn transfer(
+    dev: (Pipe, Pipe),
+    sender: impl FnOnce(Sender<Pipe>) -> Sender<Pipe> + Send + 'static,
+    data: Vec<u8>,
+    capabilities: ReceiverCapabilities,
+    buffer_len: u16,
+    hook: Hook,
+) -> Vec<Subpacket> {
+    let sent = data.clone();
+    let ((), (file, subpackets)) = session(
+        dev,
+        move |dev| {
+            let mut sender = sender(Sender::new(dev));
+            sender.start().unwrap();
+            let mut info = FileInfo::new("data.bin");
+            info.size = Some(sent.len() as u64);
+            let mut source = &sent[..];
+            sender
+                .send_file(&info, &FileOptions::default(), &mut source)
+                .unwrap();
+            sender.finish().unwrap();
+        },
+        |dev| receive(dev, capabilities, buffer_len, hook),
+    );
+    assert!(file == data);
+    subpackets
+}
+
+fn packet_types(subpackets: &[Subpacket]) -> Vec<PacketType> {
+    subpackets.iter().map(|s| s.packet_type).collect()
+}
+
+#[test]
+fn strategies() {
+    const E: PacketType = PacketType::ZCRCE;
+    const G: PacketType = PacketType::ZCRCG;
+    const Q: PacketType = PacketType::ZCRCQ;
+    const W: PacketType = PacketType::ZCRCW;
+
+    let none = |_: &mut Receiver<Pipe>, _: &[Subpacket], _: &mpsc::Sender<u8>| ();
+    for (capabilities, buffer_len, strategy, types) in [
+        // Full duplex receivers get a stream.
+        (FULL_DUPLEX, 0, None, vec![G, G, G, G, E]),
+        // Ones that can't receive while writing get a segment at a time.
+        (ReceiverCapabilities::CANFC32, 0, None, vec![W, W, W, W, E]),
+        (ReceiverCapabilities::CANFDX, 0, None, vec![W, W, W, W, E]),
+        // As do ones with a limited buffer.
+        (FULL_DUPLEX, 2048, None, vec![G, W, G, W, E]),
+        // Unless told otherwise.
+        (
+            FULL_DUPLEX,
+            0,
+            Some(Strategy::Window(2048)),
+            vec![Q, Q, Q, Q, E],
+        ),
+        (
+            FULL_DUPLEX,
+            0,
+            Some(Strategy::Segmented(3072)),
+            vec![G, G, W, G, E],
+        ),
+    ] {
+        let subpackets = transfer(
+            pipe(),
+            move |sender| match strategy {
+                Some(strategy) => sender.set_strategy(strategy),
+                None => sender,
+            },
+            data(4500),
+            capabilities,
+            buffer_len,
+            Box::new(none),
+        );
+        assert_eq!(
+            packet_types(&subpackets),
+            types,
+            "{capabilities:?} {buffer_len}"
+        );
+        // Headers have a CRC-32 only if the receiver can check it.
+        let encoding = match capabilities.contains(ReceiverCapabilities::CANFC32) {
+            true => FrameEncoding::BIN32,
+            false => FrameEncoding::BIN16,
+        };
+        assert!(subpackets.iter().all(|s| s.encoding == encoding));
+    }
+}
+
+/// The longest pause between two subpackets.
+fn longest_pause(subpackets: &[Subpacket]) -> Duration {
+    subpackets
+        .windows(2)
+        .map(|w| w[1].at - w[0].at)
+        .max()
+        .unwrap()
+}
+
+#[test]
+fn flow_control() {
+    // XOFF pauses the sender until XON.
+    let pause = Duration::from_millis(300);
+    let hook =
+        move |_: &mut Receiver<Pipe>, subpackets: &[Subpacket], inject: &mpsc::Sender<u8>| {
+            if subpackets.len() == 2 {
+                inject.send(0x13).unwrap();
+                thread::sleep(pause);
+                inject.send(0x11).unwrap();
+            }
+        };
+    let subpackets = transfer(
+        pipe(),
+        |sender| sender,
+        data(1 << 20),
+        FULL_DUPLEX,
+        0,
+        Box::new(hook),
+    );
+    assert!(longest_pause(&subpackets) >= pause / 2);
+
+    // Unless flow control is disabled, then XOFF is line noise.
+    let hook = |_: &mut Receiver<Pipe>, subpackets: &[Subpacket], inject: &mpsc::Sender<u8>| {
+        if subpackets.len() == 2 {
+            inject.send(0x13).unwrap();
+        }
+    };
+    transfer(
+        pipe(),
+        |sender| sender.set_flow_control(false),
+        data(1 << 16),
+        FULL_DUPLEX,
+        0,
+        Box::new(hook),
+    );
+}
+
+#[test]
+fn reverse_interrupt() {
+    // Anything from the receiver stops the stream, here followed by a ZRPOS.
+    let hook = |raw: &mut Receiver<Pipe>, subpackets: &[Subpacket], inject: &mpsc::Sender<u8>| {
+        if subpackets.len() == 2 {
+            inject.send(b'x').unwrap();
+            raw.send_zrpos(0).unwrap();
+        }
+    };
+    let len = 1 << 20;
+    let subpackets = transfer(
+        pipe(),
+        |sender| sender.set_strategy(Strategy::ReverseInterrupt),
+        data(len),
+        FULL_DUPLEX,
+        0,
+        Box::new(hook),
+    );
+    // The sender went back to the start long before the end of the file.
+    let restart = subpackets.iter().skip(1).position(|s| s.pos == 0).unwrap();
+    assert!(
+        restart < len / 1024 / 2,
+        "restarted after {restart} subpackets"
+    );
+}
+
+#[test]
+fn adaptive_subpackets() {
+    // Bytes are corrupted early in the transfer, more often than the subpackets are long.
+    let (sender, receiver) = pipe();
+    let sender = sender.set_fault(|pos, byte| match pos {
+        10_000..=40_000 if pos % 1000 == 0 => Some(!byte),
+        _ => Some(byte),
+    });
+    let none = |_: &mut Receiver<Pipe>, _: &[Subpacket], _: &mpsc::Sender<u8>| ();
+    let subpackets = transfer(
+        (sender, receiver),
+        |sender| sender.set_max_subpacket_len(2048),
+        data(1 << 20),
+        FULL_DUPLEX,
+        0,
+        Box::new(none),
+    );
+
+    // Subpackets shrink after the errors, and grow back once the link is clean. Ignore the
+    // empty subpackets ending interrupted frames, and the end of the file.
+    let lens: Vec<usize> = subpackets[..subpackets.len() - 1]
+        .iter()
+        .map(|s| s.len)
+        .filter(|len| *len > 0)
+        .collect();
+    let first = lens.iter().take_while(|len| **len == 2048).count();
+    assert!(first > 0, "{lens:?}");
+    assert!(lens[first..].iter().any(|len| *len < 2048), "{lens:?}");
+    assert_eq!(lens.last(), Some(&2048), "{lens:?}");
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{pipe, send_file, session, MemorySink};
+use core::time::Duration;
+use zmodem::{
+    proto::{FrameEncoding, FrameHeader, FrameType, PacketType},
+    recv::Receiver,
+    send::Sender,
+    server::Served,
+    Error,
+};
+
+#[test]
+fn reverse() {
+    let up = vec![0x55; 3000];
+    let down = vec![0xaa; 2000];
+
+    // The client uploads a file, then receives one from the server.
+    let sent = up.clone();
+    let client = move |dev| -> Result<MemorySink, Error<()>> {
+        let mut sender = Sender::new(dev);
+        sender.start()?;
+        send_file(&mut sender, "up.bin", &sent)?;
+        let mut sink = MemorySink::default();
+        sender.into_receiver()?.receive_files(&mut sink)?;
+        Ok(sink)
+    };
+
+    let mut uploaded = MemorySink::default();
+    let (client, served) = session(pipe(), client, |dev| {
+        let mut receiver = Receiver::new(dev);
+        receiver.send_zrinit()?;
+        match receiver.serve(&mut uploaded)? {
+            Served::Send(mut sender) => {
+                send_file(&mut sender, "down.bin", &down)?;
+                sender.finish()
+            }
+            Served::Finished => panic!("session not reversed"),
+        }
+    });
+    served.unwrap();
+    assert_eq!(uploaded.data("up.bin"), up);
+    assert_eq!(client.unwrap().data("down.bin"), down);
+}
+
+#[test]
+fn echoed_zrinit() {
+    // A link echoing the server's ZRINIT during the session doesn't reverse it.
+    let ((), served) = session(
+        pipe(),
+        |dev| {
+            let mut raw = Receiver::new(dev);
+            let timeout = Duration::from_secs(5);
+            let zrinit = raw.receive_frame_header(timeout).unwrap();
+            assert_eq!(zrinit.r#type, FrameType::ZRINIT);
+
+            let zcommand = FrameHeader::new(FrameEncoding::BIN32, FrameType::ZCOMMAND).set_count(1);
+            raw.send_frame(zcommand).unwrap();
+            raw.send_data_packet(FrameEncoding::BIN32, PacketType::ZCRCW, b"true\0")
+                .unwrap();
+            assert_eq!(
+                raw.receive_frame_header(timeout).unwrap().r#type,
+                FrameType::ZCOMPL
+            );
+
+            raw.send_frame(zrinit).unwrap();
+            raw.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZFIN))
+                .unwrap();
+            assert_eq!(
+                raw.receive_frame_header(timeout).unwrap().r#type,
+                FrameType::ZFIN
+            );
+            // Rather than "OO", which the receiver would wait a second more for.
+            raw.send_frame(FrameHeader::new(FrameEncoding::HEX, FrameType::ZRQINIT))
+                .ok();
+        },
+        |dev| {
+            let mut receiver = Receiver::new(dev);
+            receiver.send_zrinit()?;
+            receiver.serve(&mut MemorySink::default())
+        },
+    );
+    assert!(matches!(served, Ok(Served::Finished)));
+}
@@ -0,0 +1,114 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::data;
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+use zmodem::{telnet::TelnetDevice, SerialDevice};
+
+const IAC: u8 = 255;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const SB: u8 = 250;
+const NOP: u8 = 241;
+const SE: u8 = 240;
+const BINARY: u8 = 0;
+const TTYPE: u8 = 24;
+
+#[test]
+fn negotiation() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .write_all(&[
+                IAC, DO, BINARY, IAC, WILL, BINARY, IAC, DO, TTYPE, IAC, SB, TTYPE, 1, IAC, SE,
+                b'a', IAC, IAC, b'b',
+            ])
+            .unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 64];
+        while !received.ends_with(&[b'c', IAC, IAC]) {
+            let len = stream.read(&mut buf).unwrap();
+            assert!(len > 0);
+            received.extend_from_slice(&buf[..len]);
+        }
+        received
+    });
+
+    let mut dev = TelnetDevice::connect(addr).unwrap();
+    let timeout = Duration::from_secs(5);
+    assert_eq!(dev.recv(timeout).unwrap(), Some(b'a'));
+    assert_eq!(dev.recv(timeout).unwrap(), Some(IAC));
+    assert_eq!(dev.recv(timeout).unwrap(), Some(b'b'));
+    assert!(dev.is_binary());
+    dev.send(b'c').unwrap();
+    dev.send(IAC).unwrap();
+    dev.flush().unwrap();
+
+    let received = server.join().unwrap();
+    // Our own requests, the refusal of the terminal type, and no answer to the server agreeing
+    // to binary mode.
+    let requests = [IAC, WILL, BINARY, IAC, DO, BINARY, IAC, WILL, 3, IAC, DO, 3];
+    assert_eq!(received[..requests.len()], requests);
+    assert_eq!(
+        received[requests.len()..],
+        [IAC, WONT, TTYPE, b'c', IAC, IAC]
+    );
+}
+
+#[test]
+fn transfer() {
+    let data = data(200_000);
+    let len = data.len();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let receiver = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let dev = TelnetDevice::new(stream).unwrap();
+        let mut output = vec![0; len];
+        let (_, received) = zmodem::recv::Receiver::new(dev)
+            .set_verify(true)
+            .receive_auto(&mut output)
+            .unwrap();
+        output.truncate(received);
+        output
+    });
+
+    zmodem::send(TelnetDevice::connect(addr).unwrap(), "data.bin", &data).unwrap();
+    assert_eq!(receiver.join().unwrap(), data);
+}
+
+#[test]
+fn commands_only() {
+    // A server sending nothing but commands doesn't keep `recv` waiting past its timeout.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .write_all(&[IAC, WILL, BINARY, IAC, DO, BINARY])
+            .unwrap();
+        for _ in 0..30 {
+            thread::sleep(Duration::from_millis(100));
+            if stream.write_all(&[IAC, NOP]).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut dev = TelnetDevice::connect(addr).unwrap();
+    let start = Instant::now();
+    assert_eq!(dev.recv(Duration::from_millis(500)).unwrap(), None);
+    assert!(start.elapsed() < Duration::from_secs(2));
+    drop(dev);
+    server.join().unwrap();
+}
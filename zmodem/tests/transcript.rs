@@ -0,0 +1,56 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{data, pipe};
+use std::thread;
+use zmodem::transcript::{Recorder, Replay};
+
+/// Replay `transcript` into a receiver, returning what it received and sent.
+fn replay(transcript: &str) -> (Vec<u8>, Replay) {
+    let mut replay = Replay::parse(transcript).unwrap();
+    let mut output = vec![0; 4096];
+    let len = zmodem::receive(&mut replay, &mut output).unwrap();
+    output.truncate(len);
+    assert!(replay.is_finished());
+    (output, replay)
+}
+
+#[test]
+fn record_and_replay() {
+    // The first data subpacket is corrupted, and sent again.
+    let (sender, receiver) = pipe();
+    let sender = sender.set_corrupt(800);
+    let data = data(2000);
+    let sent = data.clone();
+    let sender = thread::spawn(move || zmodem::send(sender, "data.bin", &sent).unwrap());
+
+    let mut transcript = Vec::new();
+    let mut output = vec![0; 4096];
+    let len = zmodem::receive(Recorder::new(receiver, &mut transcript), &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(output[..len], data);
+
+    let transcript = String::from_utf8(transcript).unwrap();
+    assert!(transcript.lines().all(|line| line.len() <= 80));
+    let (output, replay) = replay(&transcript);
+    assert_eq!(output, data);
+    assert_eq!(replay.sent(), replay.recorded());
+}
+
+#[test]
+fn regression() {
+    // What we send depends on the enabled features, so only the received data is compared.
+    let transcript = include_str!("transcripts/corrupted.txt");
+    assert_eq!(replay(transcript).0, data(2000));
+}
+
+#[test]
+fn parse_error() {
+    let error = Replay::parse("# comment\n\n0.1 rx 2a\n0.2 rx 2g\n")
+        .err()
+        .unwrap();
+    assert_eq!(error.line, 4);
+    assert!(Replay::parse("0.1 sent 2a\n").is_err());
+    assert!(Replay::parse("0.1 timeout 2a\n").is_err());
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{data, info, pipe, run_receiver, run_sender, session, MemorySink, Pipe};
+use zmodem::{
+    proto::{FileOptions, ManagementOption},
+    recv::Receiver,
+    send::{Sender, Transfer},
+    source::{Source, SourceError},
+    Error,
+};
+
+/// Send `data` with `options`, returning what happened to it.
+fn send(dev: Pipe, data: &[u8], options: FileOptions) -> Result<Transfer, Error<()>> {
+    run_sender(Sender::new(dev), |sender| {
+        let mut source = data;
+        sender.send_file(&info("data.bin", data), &options, &mut source)
+    })
+}
+
+fn receive(dev: Pipe, sink: &mut MemorySink, verify: bool) -> Result<(), Error<()>> {
+    run_receiver(Receiver::new(dev).set_verify(verify), sink)
+}
+
+#[test]
+fn compare_crc() {
+    let options = FileOptions {
+        management: ManagementOption::ZMCRC,
+        ..FileOptions::default()
+    };
+
+    // A file with the same CRC as the sender's isn't received again.
+    let mut sink = MemorySink::default();
+    sink.insert("data.bin", &data(3000), None);
+    let (sent, received) = session(
+        pipe(),
+        move |dev| send(dev, &data(3000), options),
+        |dev| receive(dev, &mut sink, false),
+    );
+    assert_eq!(sent.unwrap(), Transfer::Skipped);
+    received.unwrap();
+
+    // One of the same size that differs is.
+    let mut sink = MemorySink::default();
+    sink.insert("data.bin", &vec![0; data(3000).len()], None);
+    let (sent, received) = session(
+        pipe(),
+        move |dev| send(dev, &data(3000), options),
+        |dev| receive(dev, &mut sink, false),
+    );
+    assert_eq!(sent.unwrap(), Transfer::Sent);
+    received.unwrap();
+    assert_eq!(sink.data("data.bin"), data(3000));
+}
+
+#[test]
+fn verify() {
+    let mut sink = MemorySink::default();
+    let (sent, received) = session(
+        pipe(),
+        |dev| send(dev, &data(3000), FileOptions::default()),
+        |dev| receive(dev, &mut sink, true),
+    );
+    assert_eq!(sent.unwrap(), Transfer::Sent);
+    received.unwrap();
+    assert_eq!(sink.data("data.bin"), data(3000));
+}
+
+/// A file that changes after its CRC is read.
+struct Changing {
+    data: Vec<u8>,
+    read_crc: bool,
+}
+
+impl Source for Changing {
+    fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize, SourceError> {
+        let len = (&self.data[..]).read(offset, buf)?;
+        if !self.read_crc && offset + len as u64 == self.data.len() as u64 {
+            self.read_crc = true;
+            self.data[0] ^= 1;
+        }
+        Ok(len)
+    }
+}
+
+#[test]
+fn verify_fails() {
+    let mut sink = MemorySink::default();
+    let (sent, received) = session(
+        pipe(),
+        |dev| {
+            let mut sender = Sender::new(dev);
+            sender.start()?;
+            let mut source = Changing {
+                data: data(3000),
+                read_crc: false,
+            };
+            sender.send_file(
+                &info("data.bin", &data(3000)),
+                &FileOptions::default(),
+                &mut source,
+            )
+        },
+        |dev| receive(dev, &mut sink, true),
+    );
+    assert!(matches!(sent, Err(Error::Aborted)));
+    assert!(matches!(received, Err(Error::VerificationFailed)));
+}
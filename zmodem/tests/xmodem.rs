@@ -0,0 +1,235 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::{data, pipe, Pipe};
+use core::time::Duration;
+use std::thread;
+use zmodem::{crc16, xmodem::Receiver, Protocol, SerialDevice};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+
+fn recv(dev: &mut Pipe) -> u8 {
+    dev.recv(Duration::from_secs(5)).unwrap().unwrap()
+}
+
+fn send_all(dev: &mut Pipe, bytes: &[u8]) {
+    for byte in bytes {
+        dev.send(*byte).unwrap();
+    }
+}
+
+/// Send block `number` of `data`, padded to the block size, until it is acknowledged.
+fn send_block(dev: &mut Pipe, number: u8, data: &[u8], crc: bool) {
+    let (start, len) = if data.len() > 128 {
+        (STX, 1024)
+    } else {
+        (SOH, 128)
+    };
+    let mut block = data.to_vec();
+    block.resize(len, 0x1a);
+    loop {
+        send_all(dev, &[start, number, !number]);
+        send_all(dev, &block);
+        if crc {
+            send_all(dev, &crc16(&block, None).to_be_bytes());
+        } else {
+            let sum = block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            send_all(dev, &[sum]);
+        }
+        match recv(dev) {
+            ACK => return,
+            // The receiver invites the first block again after an error.
+            NAK | b'C' => continue,
+            byte => panic!("unexpected response {byte:#x}"),
+        }
+    }
+}
+
+fn send_eot(dev: &mut Pipe) {
+    dev.send(EOT).unwrap();
+    assert_eq!(recv(dev), ACK);
+}
+
+/// Wait for an invitation to send, 'C' for CRCs or NAK for checksums, returning whether it's
+/// for CRCs. A sender without CRCs ignores 'C'.
+fn wait_invite(dev: &mut Pipe, crc: bool) -> bool {
+    loop {
+        match recv(dev) {
+            b'C' if crc => return true,
+            NAK => return false,
+            _ => continue,
+        }
+    }
+}
+
+/// An XMODEM sender, which uses CRCs if `crc` and the receiver asks for them.
+fn xmodem_send(mut dev: Pipe, data: &[u8], crc: bool) {
+    let crc = wait_invite(&mut dev, crc);
+    for (i, block) in data.chunks(128).enumerate() {
+        send_block(&mut dev, i as u8 + 1, block, crc);
+    }
+    send_eot(&mut dev);
+}
+
+fn padded(data: &[u8], block: usize) -> Vec<u8> {
+    let mut padded = data.to_vec();
+    padded.resize(data.len().div_ceil(block) * block, 0x1a);
+    padded
+}
+
+#[test]
+fn xmodem() {
+    let (sender, receiver) = pipe();
+    let data = data(300);
+    let sent = data.clone();
+    let sender = thread::spawn(move || xmodem_send(sender, &sent, true));
+
+    let mut output = vec![0; 1024];
+    let len = Receiver::new(receiver).receive(&mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(output[..len], padded(&data, 128));
+}
+
+#[test]
+fn xmodem_corrupted_block() {
+    // The checksum of the first block is flipped, and the block sent again.
+    let (sender, receiver) = pipe();
+    let sender = sender.set_corrupt(3 + 128 + 1);
+    let data = data(200);
+    let sent = data.clone();
+    let sender = thread::spawn(move || xmodem_send(sender, &sent, true));
+
+    let mut output = vec![0; 1024];
+    let len = Receiver::new(receiver).receive(&mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(output[..len], padded(&data, 128));
+}
+
+#[test]
+fn ymodem_batch() {
+    let (mut sender, receiver) = pipe();
+    let data = data(1500);
+    let sent = data.clone();
+    let sender = thread::spawn(move || {
+        let crc = wait_invite(&mut sender, true);
+        send_block(&mut sender, 0, b"data.bin\x001500 0 0", crc);
+        assert_eq!(recv(&mut sender), b'C');
+        for (i, block) in sent.chunks(1024).enumerate() {
+            send_block(&mut sender, i as u8 + 1, block, crc);
+        }
+        send_eot(&mut sender);
+        // An empty header block ends the batch.
+        assert_eq!(recv(&mut sender), b'C');
+        send_block(&mut sender, 0, &[0], crc);
+    });
+
+    // The size in the header block drops the padding.
+    let mut output = vec![0; 4096];
+    let len = Receiver::new(receiver).receive(&mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(output[..len], data);
+}
+
+#[test]
+fn detect_checksum_sender() {
+    // A sender without CRCs only answers NAK.
+    let (sender, receiver) = pipe();
+    let data = data(256);
+    let sent = data.clone();
+    let sender = thread::spawn(move || xmodem_send(sender, &sent, false));
+
+    let mut output = vec![0; 1024];
+    let (protocol, len) = zmodem::receive_auto(receiver, &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(protocol, Protocol::Xmodem);
+    assert_eq!(output[..len], data);
+}
+
+#[test]
+fn detect_check_from_block() {
+    // The sender answers one invitation, but its first block only arrives after the other.
+    for crc in [true, false] {
+        let (mut sender, receiver) = pipe();
+        let data = data(128);
+        let sent = data.clone();
+        let sender = thread::spawn(move || {
+            let (answered, last) = if crc { (b'C', NAK) } else { (NAK, b'C') };
+            while recv(&mut sender) != answered {}
+            while recv(&mut sender) != last {}
+            for (i, block) in sent.chunks(128).enumerate() {
+                send_block(&mut sender, i as u8 + 1, block, crc);
+            }
+            send_eot(&mut sender);
+        });
+
+        let mut output = vec![0; 1024];
+        let (protocol, len) = zmodem::receive_auto(receiver, &mut output).unwrap();
+        sender.join().unwrap();
+        assert_eq!(protocol, Protocol::Xmodem);
+        assert_eq!(output[..len], data, "crc: {crc}");
+    }
+}
+
+#[test]
+fn detect_after_noise_packets() {
+    // Starts of packets that turn out not to be, each pushed back to be looked at again.
+    let (mut sender, receiver) = pipe();
+    let data = data(128);
+    let sent = data.clone();
+    let sender = thread::spawn(move || {
+        send_all(&mut sender, &[SOH, SOH, SOH, SOH, 5, SOH, 9]);
+        thread::sleep(Duration::from_secs(1));
+        while sender.recv(Duration::from_millis(100)).unwrap().is_some() {}
+        xmodem_send(sender, &sent, true)
+    });
+
+    let mut output = vec![0; 1024];
+    let (protocol, len) = zmodem::receive_auto(receiver, &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(protocol, Protocol::Xmodem);
+    assert_eq!(output[..len], data);
+}
+
+#[test]
+fn detect_after_partial_block() {
+    // A stray SOH and nothing after it isn't taken for a block.
+    let (mut sender, receiver) = pipe();
+    let data = data(128);
+    let sent = data.clone();
+    let sender = thread::spawn(move || {
+        sender.send(SOH).unwrap();
+        thread::sleep(Duration::from_secs(1));
+        // Answer the next invitation, rather than one sent before the SOH was given up on.
+        while sender.recv(Duration::from_millis(100)).unwrap().is_some() {}
+        xmodem_send(sender, &sent, true)
+    });
+
+    let mut output = vec![0; 1024];
+    let (protocol, len) = zmodem::receive_auto(receiver, &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(protocol, Protocol::Xmodem);
+    assert_eq!(output[..len], data);
+}
+
+#[test]
+fn detect_zmodem_after_noise() {
+    // The sender's "rz\r" follows a stray SOH, and is still recognised.
+    let (mut sender, receiver) = pipe();
+    let data = data(1000);
+    let sent = data.clone();
+    let sender = thread::spawn(move || {
+        sender.send(SOH).unwrap();
+        zmodem::send(sender, "data.bin", &sent).unwrap()
+    });
+
+    let mut output = vec![0; 1024];
+    let (protocol, len) = zmodem::receive_auto(receiver, &mut output).unwrap();
+    sender.join().unwrap();
+    assert_eq!(protocol, Protocol::Zmodem);
+    assert_eq!(output[..len], data);
+}